@@ -1,25 +1,110 @@
+pub mod buddy;
 pub mod bump;
+pub mod debug;
+pub mod fixed_size_block;
+pub mod leak;
 pub mod linked_list;
 
 use alloc::alloc::{GlobalAlloc, Layout};
-use core::{arch::x86_64::_MM_HINT_NTA, ptr::null_mut};
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
+use crate::memory;
 use x86_64::{
     structures::paging::{
-        mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
+        mapper::MapToError, FrameAllocator, FrameDeallocator, Mapper, Page, PageSize,
+        PageTableFlags, Size2MiB, Size4KiB,
     },
     VirtAddr,
 };
 
-use self::bump::BumpAllocator;
+// 全局堆分配器通过cargo feature在编译时选择, 默认为alloc-fixed-block
+// 比如: cargo test --no-default-features --features alloc-bump
+#[cfg(not(any(
+    feature = "alloc-bump",
+    feature = "alloc-linked-list",
+    feature = "alloc-fixed-block",
+    feature = "alloc-buddy",
+    feature = "alloc-external"
+)))]
+compile_error!("one of the `alloc-*` features must be enabled to select the global allocator");
 
-//use linked_list_allocator::LockedHeap;
-// 指定堆内存分配器
-//#[global_allocator]
-//static ALLOCATOR: LockedHeap = LockedHeap::empty();
+// 以下feature互斥, 只能启用一个
+#[cfg(any(
+    all(
+        feature = "alloc-bump",
+        any(
+            feature = "alloc-linked-list",
+            feature = "alloc-fixed-block",
+            feature = "alloc-buddy",
+            feature = "alloc-external"
+        )
+    ),
+    all(
+        feature = "alloc-linked-list",
+        any(
+            feature = "alloc-fixed-block",
+            feature = "alloc-buddy",
+            feature = "alloc-external"
+        )
+    ),
+    all(
+        feature = "alloc-fixed-block",
+        any(feature = "alloc-buddy", feature = "alloc-external")
+    ),
+    all(feature = "alloc-buddy", feature = "alloc-external")
+))]
+compile_error!("the `alloc-*` features are mutually exclusive, enable only one of them");
 
+// bump分配器, 只有在所有内存都释放的时候才能复用
+#[cfg(feature = "alloc-bump")]
+type Backend = bump::BumpAllocator;
+#[cfg(feature = "alloc-bump")]
+const fn new_backend() -> Backend {
+    bump::BumpAllocator::new()
+}
+
+// 链表分配器, 每次分配和释放都要遍历链表
+#[cfg(feature = "alloc-linked-list")]
+type Backend = linked_list::LinkedListAllocator;
+#[cfg(feature = "alloc-linked-list")]
+const fn new_backend() -> Backend {
+    linked_list::LinkedListAllocator::new()
+}
+
+// 固定大小块分配器, 分配和释放都是O(1)的, 大块内存由链表分配器处理
+#[cfg(feature = "alloc-fixed-block")]
+type Backend = fixed_size_block::FixedSizeBlockAllocator;
+#[cfg(feature = "alloc-fixed-block")]
+const fn new_backend() -> Backend {
+    fixed_size_block::FixedSizeBlockAllocator::new()
+}
+
+// 伙伴分配器
+#[cfg(feature = "alloc-buddy")]
+type Backend = buddy::BuddyAllocator;
+#[cfg(feature = "alloc-buddy")]
+const fn new_backend() -> Backend {
+    buddy::BuddyAllocator::new()
+}
+
+// linked_list_allocator库提供的分配器
+#[cfg(feature = "alloc-external")]
+type Backend = linked_list_allocator::Heap;
+#[cfg(feature = "alloc-external")]
+const fn new_backend() -> Backend {
+    linked_list_allocator::Heap::empty()
+}
+
+#[cfg(not(feature = "alloc-debug"))]
+#[global_allocator]
+static ALLOCATOR: Locked<Backend> = Locked::new(new_backend());
+
+// 开启alloc-debug后, 在选择的分配器外再包一层DebugAllocator, 用于检查越界写, 重复释放等问题
+#[cfg(feature = "alloc-debug")]
 #[global_allocator]
-static ALLOCATOR: Locked<BumpAllocator> = Locked::new(BumpAllocator::new());
+static ALLOCATOR: Locked<debug::DebugAllocator<Backend>> =
+    Locked::new(debug::DebugAllocator::new(new_backend()));
 
 // 定义对内存的大小及开始位置
 // 该初地址为虚拟内存
@@ -27,6 +112,18 @@ static ALLOCATOR: Locked<BumpAllocator> = Locked::new(BumpAllocator::new());
 pub const HEAP_START: usize = 0x_4444_4444_0000;
 pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
 
+// 堆在HEAP_START之后最多可以扩展到的大小, 可以通过set_heap_limit修改
+pub const HEAP_MAX_SIZE: usize = 16 * 1024 * 1024; // 16 MiB
+
+// 每次扩展堆时至少映射的大小
+const HEAP_GROW_STEP: usize = 64 * 1024;
+
+// 当前已经映射的堆的结束地址
+static HEAP_END: AtomicUsize = AtomicUsize::new(HEAP_START);
+static HEAP_LIMIT: AtomicUsize = AtomicUsize::new(HEAP_MAX_SIZE);
+// 扩展堆时是否使用2MiB的大页
+static HEAP_HUGE_PAGES: AtomicBool = AtomicBool::new(false);
+
 // 需要初始化堆空间，
 // 因为不初始化，堆空间没有在页表中注册，且相应的内存没有被标记为已使用
 // 所以无法使用
@@ -48,8 +145,8 @@ pub fn init_heap(
         let frame = frame_allocator
             .allocate_frame()
             .ok_or(MapToError::FrameAllocationFailed)?;
-        // 将状态置为已分配及可写
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        // 将状态置为已分配及可写, 堆上的数据不应该被执行
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
         // 为page进行内存空间分配及映射, 通过fluash方法，会将对应的映射刷新到tlb中
         unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() }
     }
@@ -58,28 +155,262 @@ pub fn init_heap(
     unsafe {
         ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
     }
+    HEAP_END.store(HEAP_START + HEAP_SIZE, Ordering::SeqCst);
     Ok(())
 }
 
+/// 当前已经映射的堆的大小
+pub fn heap_size() -> usize {
+    HEAP_END.load(Ordering::SeqCst) - HEAP_START
+}
+
+/// 设置堆最多可以扩展到的大小, 已经映射的部分不会被释放
+pub fn set_heap_limit(limit: usize) {
+    HEAP_LIMIT.store(limit, Ordering::SeqCst);
+}
+
+/// 扩展堆时是否使用2MiB的大页, 可以减少tlb的压力
+/// 开启后每次扩展到2MiB的边界, 对齐的部分使用大页映射, 之前的部分仍然使用4KiB的页
+pub fn set_heap_huge_pages(enabled: bool) {
+    HEAP_HUGE_PAGES.store(enabled, Ordering::SeqCst);
+}
+
+// 堆空间不足时, 在当前堆的结尾处映射新的页面, 返回新映射的区域
+// 需要先通过memory::install设置全局的页表及帧分配器, 否则无法扩展
+fn grow_heap(min_size: usize) -> Option<(usize, usize)> {
+    use x86_64::instructions::interrupts;
+
+    let start = HEAP_END.load(Ordering::SeqCst);
+    let huge_pages = HEAP_HUGE_PAGES.load(Ordering::SeqCst);
+    let step = if huge_pages {
+        Size2MiB::SIZE as usize
+    } else {
+        Size4KiB::SIZE as usize
+    };
+    let end_target = align_up(start + min_size.max(HEAP_GROW_STEP), step);
+    let limit = HEAP_START + HEAP_LIMIT.load(Ordering::SeqCst);
+    let size = end_target.min(limit).saturating_sub(start);
+    if size < min_size {
+        return None;
+    }
+
+    let mut end = start;
+    interrupts::without_interrupts(|| {
+        // 锁只在关闭中断时持有, 拿不到说明是持有锁的代码自己在分配内存, 等待只会死锁, 直接让分配失败
+        let (mut mapper, mut frame_allocator) = match (
+            memory::MAPPER.try_lock(),
+            memory::FRAME_ALLOCATOR.try_lock(),
+        ) {
+            (Some(mapper), Some(frame_allocator)) => (mapper, frame_allocator),
+            _ => return,
+        };
+        let (mapper, frame_allocator) = match (mapper.as_mut(), frame_allocator.as_mut()) {
+            (Some(mapper), Some(frame_allocator)) => (mapper, frame_allocator),
+            _ => return,
+        };
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+
+        while end < start + size {
+            // 对齐且剩余空间足够时使用大页, 没有连续的物理内存时退回到4KiB的页
+            let huge_size = Size2MiB::SIZE as usize;
+            if huge_pages && end % huge_size == 0 && end + huge_size <= start + size {
+                let page = Page::<Size2MiB>::containing_address(VirtAddr::new(end as u64));
+                if let Some(frame) = frame_allocator.allocate_huge::<Size2MiB>() {
+                    match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+                        Ok(flush) => flush.flush(),
+                        Err(_) => {
+                            unsafe { frame_allocator.deallocate_huge(frame) };
+                            break;
+                        }
+                    }
+                    end += huge_size;
+                    continue;
+                }
+            }
+
+            let page = Page::<Size4KiB>::containing_address(VirtAddr::new(end as u64));
+            let frame = match frame_allocator.allocate_frame() {
+                Some(frame) => frame,
+                None => break,
+            };
+            match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+                Ok(flush) => flush.flush(),
+                Err(_) => {
+                    unsafe { frame_allocator.deallocate_frame(frame) };
+                    break;
+                }
+            }
+            end += Page::<Size4KiB>::SIZE as usize;
+        }
+    });
+
+    HEAP_END.store(end, Ordering::SeqCst);
+    if end > start {
+        Some((start, end - start))
+    } else {
+        None
+    }
+}
+
 fn align_up(addr: usize, align: usize) -> usize {
     (addr + align - 1) & !(align - 1)
 }
 
 // 因为无法直接为Mutex实现GlobalAlloc,所以自定义一个lock
+// 同时记录通过GlobalAlloc分配出去的内存的使用情况
 pub struct Locked<A> {
     inner: spin::Mutex<A>,
+    allocated: AtomicUsize,
+    allocations: AtomicUsize,
+    peak: AtomicUsize,
 }
 
 impl<A> Locked<A> {
     pub const fn new(inner: A) -> Self {
         Locked {
             inner: spin::Mutex::new(inner),
+            allocated: AtomicUsize::new(0),
+            allocations: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
         }
     }
 
     pub fn lock(&self) -> spin::MutexGuard<A> {
         self.inner.lock()
     }
+
+    // 以下两个方法都在持有inner锁的时候调用, 所以不需要更强的内存顺序
+    fn record_alloc(&self, size: usize) {
+        let allocated = self.allocated.fetch_add(size, Ordering::Relaxed) + size;
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+        self.peak.fetch_max(allocated, Ordering::Relaxed);
+    }
+
+    fn record_dealloc(&self, size: usize) {
+        self.allocated.fetch_sub(size, Ordering::Relaxed);
+        self.allocations.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<A: HeapAllocator> Locked<A> {
+    /// 当前堆的使用情况
+    pub fn stats(&self) -> HeapStats {
+        let allocator = self.lock();
+        let (free, largest_free_block) = allocator.free_space();
+        HeapStats {
+            allocated: self.allocated.load(Ordering::Relaxed),
+            free,
+            allocations: self.allocations.load(Ordering::Relaxed),
+            peak: self.peak.load(Ordering::Relaxed),
+            largest_free_block,
+        }
+    }
+}
+
+/// 堆的使用情况
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    /// 已分配出去的字节数(按layout的大小计算, 不包括对齐及分配器自身的开销)
+    pub allocated: usize,
+    /// 分配器中空闲的字节数
+    pub free: usize,
+    /// 还未释放的分配的个数
+    pub allocations: usize,
+    /// allocated的历史最大值
+    pub peak: usize,
+    /// 最大的空闲块, 大于这个大小的分配需要扩展堆
+    pub largest_free_block: usize,
+}
+
+/// 全局堆分配器的使用情况
+pub fn stats() -> HeapStats {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| ALLOCATOR.stats())
+}
+
+// 各个堆分配器的公共接口, 由Locked<A>统一实现GlobalAlloc
+pub trait HeapAllocator {
+    /// 把[heap_start, heap_start + heap_size)交给分配器管理, 只能调用一次
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize);
+
+    /// 分配内存, 失败时返回空指针
+    fn alloc(&mut self, layout: Layout) -> *mut u8;
+
+    /// 释放内存, ptr必须是由同一个分配器以同样的layout分配的
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout);
+
+    /// 把紧接在当前堆之后新映射的区域交给分配器
+    unsafe fn extend(&mut self, start: usize, size: usize);
+
+    /// 空闲的字节数及最大的空闲块的大小
+    fn free_space(&self) -> (usize, usize);
+}
+
+unsafe impl<A: HeapAllocator> GlobalAlloc for Locked<A> {
+    // 因为内存分配器定义的方法参数是self，而我们需要在内部改变结构体对应的内容，所以需要内存可变性。
+    // 持有锁时关闭中断: 线程可能在分配的途中被时钟中断抢占,
+    // 之后在关闭中断时分配内存的线程会一直等待这个锁
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        use x86_64::instructions::interrupts;
+
+        interrupts::without_interrupts(|| {
+            let mut allocator = self.lock();
+            loop {
+                let ptr = allocator.alloc(layout);
+                if !ptr.is_null() {
+                    self.record_alloc(layout.size());
+                    leak::record(ptr, layout);
+                    return ptr;
+                }
+
+                // 堆空间不足, 扩展堆后重试, 多申请一些空间用于对齐
+                // 扩展到上限后grow_heap返回None, 分配失败
+                let min_size = layout.size().max(layout.align()) * 2;
+                match grow_heap(min_size) {
+                    Some((start, size)) => allocator.extend(start, size),
+                    None => return null_mut(),
+                }
+            }
+        })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        use x86_64::instructions::interrupts;
+
+        interrupts::without_interrupts(|| {
+            let mut allocator = self.lock();
+            allocator.dealloc(ptr, layout);
+            self.record_dealloc(layout.size());
+            leak::forget(ptr);
+        })
+    }
+}
+
+#[cfg(feature = "alloc-external")]
+impl HeapAllocator for linked_list_allocator::Heap {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        linked_list_allocator::Heap::init(self, heap_start, heap_size)
+    }
+
+    fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        self.allocate_first_fit(layout)
+            .map_or(null_mut(), |ptr| ptr.as_ptr())
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        self.deallocate(core::ptr::NonNull::new_unchecked(ptr), layout)
+    }
+
+    unsafe fn extend(&mut self, start: usize, size: usize) {
+        assert_eq!(start, self.top());
+        linked_list_allocator::Heap::extend(self, size)
+    }
+
+    // 外部分配器无法获取最大的空闲块, 只能返回总的空闲大小
+    fn free_space(&self) -> (usize, usize) {
+        (self.free(), self.free())
+    }
 }
 
 pub struct Dummy;
@@ -0,0 +1,197 @@
+// 伙伴分配器(buddy allocator)
+// 所有块的大小都是2的幂, 且按自身大小对齐, 第i个空闲链表保存大小为2^i的块
+// 分配时找到能放下layout的最小的非空链表, 如果块太大, 就一分为二, 一半放回低一级的链表中, 直到大小合适(split)
+// 释放时计算伙伴块的地址(addr ^ size), 如果伙伴也是空闲的, 就合并成更大的块, 一直向上合并(merge)
+// 好处是能分配连续的大块内存且释放后能合并, 缺点是会有内部碎片, 比如分配33字节会占用64字节的块
+
+use super::{align_up, HeapAllocator};
+use alloc::alloc::Layout;
+use core::{cmp, ptr};
+
+// 空闲链表的个数, 最大的块为2^(ORDERS - 1)字节
+const ORDERS: usize = 48;
+
+// 堆上最小的块为16字节, 需要能放下一个FreeBlock
+const HEAP_MIN_ORDER: usize = 4;
+
+struct FreeBlock {
+    next: Option<&'static mut FreeBlock>,
+}
+
+/// 伙伴分配器的统计信息
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BuddyStats {
+    /// 分配器管理的总字节数
+    pub total: usize,
+    /// 已分配出去的字节数(按块大小计算)
+    pub allocated: usize,
+    /// 大块被拆分的次数
+    pub splits: usize,
+    /// 伙伴块被合并的次数
+    pub merges: usize,
+}
+
+pub struct BuddyAllocator {
+    free_lists: [Option<&'static mut FreeBlock>; ORDERS],
+    // 最小块的order, 即最小块为2^min_order字节
+    min_order: usize,
+    // 块地址加上node_offset才是存放FreeBlock的虚拟地址
+    // 管理堆时为0, 管理物理帧时为physical_memory_offset
+    node_offset: usize,
+    stats: BuddyStats,
+}
+
+impl BuddyAllocator {
+    pub const fn new() -> Self {
+        Self::with_params(HEAP_MIN_ORDER, 0)
+    }
+
+    /// 指定最小块的order及空闲块的访问偏移, 用于管理非堆内存(比如物理帧)
+    pub const fn with_params(min_order: usize, node_offset: usize) -> Self {
+        const EMPTY: Option<&'static mut FreeBlock> = None;
+        BuddyAllocator {
+            free_lists: [EMPTY; ORDERS],
+            min_order,
+            node_offset,
+            stats: BuddyStats {
+                total: 0,
+                allocated: 0,
+                splits: 0,
+                merges: 0,
+            },
+        }
+    }
+
+    /// 将[start, end)加入分配器, 区域会被拆成尽可能大的对齐块
+    pub unsafe fn add_region(&mut self, start: usize, end: usize) {
+        let mut start = align_up(start, 1 << self.min_order);
+        let end = end & !((1 << self.min_order) - 1);
+
+        while start < end {
+            let align_order = start.trailing_zeros() as usize;
+            let size_order = (usize::BITS - 1 - (end - start).leading_zeros()) as usize;
+            let order = cmp::min(cmp::min(align_order, size_order), ORDERS - 1);
+            // 扩展堆时新区域可能与之前的空闲块互为伙伴, 所以也尝试合并
+            self.insert(start, order);
+            self.stats.total += 1 << order;
+            start += 1 << order;
+        }
+    }
+
+    pub fn stats(&self) -> BuddyStats {
+        self.stats
+    }
+
+    /// 能放下layout的块的order
+    pub fn order_for(&self, layout: &Layout) -> usize {
+        let size = layout
+            .size()
+            .max(layout.align())
+            .max(1 << self.min_order)
+            .next_power_of_two();
+        size.trailing_zeros() as usize
+    }
+
+    /// 分配一个2^order字节的块, 返回块的起始地址
+    pub fn alloc_order(&mut self, order: usize) -> Option<usize> {
+        let order = cmp::max(order, self.min_order);
+        let mut current = (order..ORDERS).find(|&i| self.free_lists[i].is_some())?;
+        let addr = self.pop(current)?;
+
+        // 块太大, 拆分, 高地址的一半放回低一级的链表中
+        while current > order {
+            current -= 1;
+            unsafe { self.push(current, addr + (1 << current)) };
+            self.stats.splits += 1;
+        }
+
+        self.stats.allocated += 1 << order;
+        Some(addr)
+    }
+
+    /// 释放一个由alloc_order(order)分配的块, 并尽可能与伙伴合并
+    pub unsafe fn dealloc_order(&mut self, addr: usize, order: usize) {
+        let order = cmp::max(order, self.min_order);
+        self.stats.allocated -= 1 << order;
+        self.stats.merges += self.insert(addr, order);
+    }
+
+    // 将空闲块放回链表, 如果伙伴也是空闲的则一直向上合并, 返回合并的次数
+    unsafe fn insert(&mut self, addr: usize, order: usize) -> usize {
+        let mut addr = addr;
+        let mut current = order;
+        while current < ORDERS - 1 {
+            let buddy = addr ^ (1 << current);
+            if !self.remove(current, buddy) {
+                break;
+            }
+            addr = cmp::min(addr, buddy);
+            current += 1;
+        }
+        self.push(current, addr);
+        current - order
+    }
+
+    unsafe fn push(&mut self, order: usize, addr: usize) {
+        let node = FreeBlock {
+            next: self.free_lists[order].take(),
+        };
+        let node_ptr = (addr + self.node_offset) as *mut FreeBlock;
+        node_ptr.write(node);
+        self.free_lists[order] = Some(&mut *node_ptr);
+    }
+
+    fn pop(&mut self, order: usize) -> Option<usize> {
+        let node = self.free_lists[order].take()?;
+        self.free_lists[order] = node.next.take();
+        Some(node as *mut FreeBlock as usize - self.node_offset)
+    }
+
+    // 从链表中删除指定地址的块, 找不到则返回false
+    fn remove(&mut self, order: usize, addr: usize) -> bool {
+        let target = addr + self.node_offset;
+        let mut current = &mut self.free_lists[order];
+        loop {
+            match current {
+                None => return false,
+                Some(node) if *node as *const FreeBlock as usize == target => {
+                    *current = node.next.take();
+                    return true;
+                }
+                Some(node) => current = &mut node.next,
+            }
+        }
+    }
+}
+
+impl HeapAllocator for BuddyAllocator {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.add_region(heap_start, heap_start + heap_size);
+    }
+
+    fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let order = self.order_for(&layout);
+        match self.alloc_order(order) {
+            Some(addr) => addr as *mut u8,
+            None => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let order = self.order_for(&layout);
+        self.dealloc_order(ptr as usize, order)
+    }
+
+    unsafe fn extend(&mut self, start: usize, size: usize) {
+        self.add_region(start, start + size)
+    }
+
+    fn free_space(&self) -> (usize, usize) {
+        let free = self.stats.total - self.stats.allocated;
+        let largest = (0..ORDERS)
+            .rev()
+            .find(|&i| self.free_lists[i].is_some())
+            .map_or(0, |i| 1 << i);
+        (free, largest)
+    }
+}
@@ -3,8 +3,8 @@
 // 同时记录内存分配的次数，当次数为0，则将整块内存回收
 // 缺点明显，就是只有在所有内存都释放的时候才能被复用
 
-use super::{align_up, Locked};
-use alloc::alloc::{GlobalAlloc, Layout};
+use super::{align_up, HeapAllocator};
+use alloc::alloc::Layout;
 use core::ptr;
 
 pub struct BumpAllocator {
@@ -23,41 +23,51 @@ impl BumpAllocator {
             allocations: 0,
         }
     }
+}
 
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+impl HeapAllocator for BumpAllocator {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
         self.heap_start = heap_start;
         self.heap_end = heap_start + heap_size - 1;
         self.next = heap_start;
     }
-}
 
-unsafe impl GlobalAlloc for Locked<BumpAllocator> {
     // 因为内存分配器定义的方法参数是self，而我们需要在内部改变结构体对应的内容，所以需要内存可变性。
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        let mut bump = self.inner.lock();
+    // 可变性由Locked提供
+    fn alloc(&mut self, layout: Layout) -> *mut u8 {
         // 将bump.next以layout.align()的大小方式对齐，其中layout.align返回的是一个size
-        let alloc_start = align_up(bump.next, layout.align());
+        let alloc_start = align_up(self.next, layout.align());
         // checked_add 是越界检查， 如果越界，则返回None
         let alloc_end = match alloc_start.checked_add(layout.size()) {
             Some(end) => end,
             None => return ptr::null_mut(),
         };
-        if alloc_end > bump.heap_end {
+        if alloc_end > self.heap_end {
             ptr::null_mut()
         } else {
-            bump.next = alloc_end;
-            bump.allocations += 1;
+            self.next = alloc_end;
+            self.allocations += 1;
             alloc_start as *mut u8
         }
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        let mut bump = self.inner.lock();
-
-        bump.allocations -= 1;
-        if bump.allocations == 0 {
+    unsafe fn dealloc(&mut self, _ptr: *mut u8, _layout: Layout) {
+        self.allocations -= 1;
+        if self.allocations == 0 {
             // 可以重新开始分配内存
-            bump.next = bump.heap_start;
+            self.next = self.heap_start;
         }
     }
+
+    unsafe fn extend(&mut self, start: usize, size: usize) {
+        // 新区域紧接在堆的末尾, 直接移动堆的结束位置即可
+        assert_eq!(start, self.heap_end + 1);
+        self.heap_end = start + size - 1;
+    }
+
+    // 只有next之后的内存是可以分配的
+    fn free_space(&self) -> (usize, usize) {
+        let free = (self.heap_end + 1).saturating_sub(self.next);
+        (free, free)
+    }
 }
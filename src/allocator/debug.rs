@@ -0,0 +1,199 @@
+// 调试用的堆分配器, 包在任意一个HeapAllocator外面使用, 比如Locked<DebugAllocator<LinkedListAllocator>>
+// 每次分配都会在用户内存的前后加上保护区(redzone), 内存布局如下:
+// |  保护字节  | size(8字节) | magic(8字节) |  用户内存  |  保护字节(REDZONE) |
+// |<--------------- front -------------->|<- size ->|
+// 释放时检查前后的保护字节及magic, 发现越界写, 重复释放或者layout不一致时, 通过串口打印出问题的layout
+// 释放的内存会被填充为POISON_FREED, 并先放到隔离区中, 而不是马上还给内部的分配器
+// 这样在隔离期间再次释放可以通过magic发现, 离开隔离区时如果填充的内容被修改了, 说明有释放后写入(use after free)
+
+use super::HeapAllocator;
+use crate::serial_println;
+use alloc::alloc::Layout;
+use core::ptr;
+
+// 尾部保护区的大小
+const REDZONE: usize = 16;
+// 头部最小的大小, 包括16字节的保护字节, size及magic
+const MIN_FRONT: usize = 32;
+// 保护区中填充的字节
+const GUARD_BYTE: u8 = 0xfd;
+// 新分配的内存填充的字节, 方便发现读取未初始化内存的问题
+const POISON_ALLOCATED: u8 = 0xcd;
+// 释放后的内存填充的字节
+const POISON_FREED: u8 = 0xdd;
+const MAGIC_ALLOCATED: u64 = 0xa110_ca7e_d000_0001;
+const MAGIC_FREED: u64 = 0xf4ee_d000_f4ee_d001;
+// 隔离区的大小
+const QUARANTINE: usize = 32;
+
+/// 检查到的堆错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corruption {
+    /// 释放已经释放过的内存
+    DoubleFree,
+    /// 头部的保护区或magic被修改, 可能是向前越界写, 也可能是释放了不是由分配器分配的指针
+    BufferUnderflow,
+    /// 尾部的保护区被修改
+    BufferOverflow,
+    /// 释放时的layout大小与分配时不一致, 参数为分配时的大小
+    LayoutMismatch(usize),
+    /// 释放后的内存被修改
+    UseAfterFree,
+}
+
+pub struct DebugAllocator<A> {
+    inner: A,
+    // 隔离区, 保存还未还给内部分配器的(用户指针, layout)
+    quarantine: [Option<(usize, Layout)>; QUARANTINE],
+    quarantine_next: usize,
+    errors: usize,
+    last_error: Option<Corruption>,
+    panic_on_error: bool,
+}
+
+impl<A> DebugAllocator<A> {
+    pub const fn new(inner: A) -> Self {
+        DebugAllocator {
+            inner,
+            quarantine: [None; QUARANTINE],
+            quarantine_next: 0,
+            errors: 0,
+            last_error: None,
+            panic_on_error: true,
+        }
+    }
+
+    /// 发现错误时是否panic, 默认为true, 关闭后只打印并记录错误, 供测试使用
+    pub fn set_panic_on_error(&mut self, panic_on_error: bool) {
+        self.panic_on_error = panic_on_error;
+    }
+
+    /// 一共发现的错误个数
+    pub fn errors(&self) -> usize {
+        self.errors
+    }
+
+    /// 最后一次发现的错误
+    pub fn last_error(&self) -> Option<Corruption> {
+        self.last_error
+    }
+}
+
+impl<A: HeapAllocator> DebugAllocator<A> {
+    // 头部的大小, 需要保证用户指针按layout.align()对齐
+    fn front(layout: &Layout) -> usize {
+        layout.align().max(MIN_FRONT)
+    }
+
+    // 向内部分配器申请的layout
+    fn inner_layout(layout: &Layout) -> Layout {
+        let size = Self::front(layout) + layout.size() + REDZONE;
+        Layout::from_size_align(size, layout.align().max(8)).unwrap()
+    }
+
+    fn report(&mut self, error: Corruption, ptr: usize, layout: Layout) {
+        serial_println!(
+            "HEAP CORRUPTION: {:?} at {:#x}, layout {:?}",
+            error,
+            ptr,
+            layout
+        );
+        self.errors += 1;
+        self.last_error = Some(error);
+        if self.panic_on_error {
+            panic!("heap corruption: {:?} {:?}", error, layout);
+        }
+    }
+
+    // 检查一个还在使用中的块, 没有问题返回true
+    unsafe fn check_allocated(&mut self, ptr: usize, layout: Layout) -> bool {
+        let front = Self::front(&layout);
+        let size = ((ptr - 16) as *const u64).read();
+        let magic = ((ptr - 8) as *const u64).read();
+
+        if magic == MAGIC_FREED {
+            self.report(Corruption::DoubleFree, ptr, layout);
+            return false;
+        }
+        if magic != MAGIC_ALLOCATED || !is_filled(ptr - front, front - 16, GUARD_BYTE) {
+            self.report(Corruption::BufferUnderflow, ptr, layout);
+            return false;
+        }
+        if size as usize != layout.size() {
+            self.report(Corruption::LayoutMismatch(size as usize), ptr, layout);
+            return false;
+        }
+        if !is_filled(ptr + layout.size(), REDZONE, GUARD_BYTE) {
+            self.report(Corruption::BufferOverflow, ptr, layout);
+            return false;
+        }
+        true
+    }
+
+    // 把离开隔离区的块还给内部分配器, 归还前检查填充的内容是否被修改
+    unsafe fn release(&mut self, ptr: usize, layout: Layout) {
+        if !is_filled(ptr, layout.size(), POISON_FREED) {
+            self.report(Corruption::UseAfterFree, ptr, layout);
+        }
+        let front = Self::front(&layout);
+        self.inner
+            .dealloc((ptr - front) as *mut u8, Self::inner_layout(&layout));
+    }
+}
+
+// [start, start + len)是否都为byte
+unsafe fn is_filled(start: usize, len: usize, byte: u8) -> bool {
+    (0..len).all(|i| ((start + i) as *const u8).read() == byte)
+}
+
+impl<A: HeapAllocator> HeapAllocator for DebugAllocator<A> {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.inner.init(heap_start, heap_size)
+    }
+
+    fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let inner_ptr = self.inner.alloc(Self::inner_layout(&layout));
+        if inner_ptr.is_null() {
+            return inner_ptr;
+        }
+
+        let front = Self::front(&layout);
+        let ptr = inner_ptr as usize + front;
+        unsafe {
+            ptr::write_bytes(inner_ptr, GUARD_BYTE, front - 16);
+            ((ptr - 16) as *mut u64).write(layout.size() as u64);
+            ((ptr - 8) as *mut u64).write(MAGIC_ALLOCATED);
+            ptr::write_bytes(ptr as *mut u8, POISON_ALLOCATED, layout.size());
+            ptr::write_bytes((ptr + layout.size()) as *mut u8, GUARD_BYTE, REDZONE);
+        }
+        ptr as *mut u8
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let ptr = ptr as usize;
+        // 有问题的块不再还给内部分配器, 避免破坏内部分配器的数据结构
+        if !self.check_allocated(ptr, layout) {
+            return;
+        }
+
+        ((ptr - 8) as *mut u64).write(MAGIC_FREED);
+        ptr::write_bytes(ptr as *mut u8, POISON_FREED, layout.size());
+
+        // 隔离区是一个环形队列, quarantine_next指向最早放入的块
+        let slot = self.quarantine_next;
+        if let Some((old_ptr, old_layout)) = self.quarantine[slot].take() {
+            self.release(old_ptr, old_layout);
+        }
+        self.quarantine[slot] = Some((ptr, layout));
+        self.quarantine_next = (slot + 1) % QUARANTINE;
+    }
+
+    unsafe fn extend(&mut self, start: usize, size: usize) {
+        self.inner.extend(start, size)
+    }
+
+    // 隔离区中的块不算作空闲内存
+    fn free_space(&self) -> (usize, usize) {
+        self.inner.free_space()
+    }
+}
@@ -0,0 +1,107 @@
+// 固定大小块分配器
+// 将内存按照固定的几种大小(8..2048字节)分成块, 每种大小维护一个空闲链表
+// 分配时将layout向上取整到对应的块大小, 直接从链表头取出一块, 释放时再放回链表头, 都是O(1)的
+// 超过最大块大小的分配则交给LinkedListAllocator处理
+// 缺点是会浪费一些内存, 比如分配9个字节会占用16字节的块
+
+use super::linked_list::LinkedListAllocator;
+use super::HeapAllocator;
+use alloc::alloc::Layout;
+use core::mem;
+
+// 块大小, 同时也是块的对齐大小, 所以都必须是2的幂
+// 最小的块不能小于8, 因为每个空闲块都要存放一个ListNode指针
+const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+// 空闲块中存放的链表节点, 与LinkedListAllocator不同, 不需要保存大小, 因为同一个链表中的块大小都一样
+struct ListNode {
+    next: Option<&'static mut ListNode>,
+}
+
+pub struct FixedSizeBlockAllocator {
+    list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
+    fallback_allocator: LinkedListAllocator,
+}
+
+impl FixedSizeBlockAllocator {
+    pub const fn new() -> Self {
+        const EMPTY: Option<&'static mut ListNode> = None;
+        FixedSizeBlockAllocator {
+            list_heads: [EMPTY; BLOCK_SIZES.len()],
+            fallback_allocator: LinkedListAllocator::new(),
+        }
+    }
+
+    fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
+        self.fallback_allocator.alloc(layout)
+    }
+}
+
+// 找到能放下layout的最小的块大小的下标
+fn list_index(layout: &Layout) -> Option<usize> {
+    let required_block_size = layout.size().max(layout.align());
+    BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
+}
+
+impl HeapAllocator for FixedSizeBlockAllocator {
+    // 初始时所有链表都是空的, 内存全部交给后备分配器, 块在第一次分配的时候才从后备分配器中切出来
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.fallback_allocator.init(heap_start, heap_size);
+    }
+
+    fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        match list_index(&layout) {
+            Some(index) => match self.list_heads[index].take() {
+                // 链表中有空闲块, 直接取出
+                Some(node) => {
+                    self.list_heads[index] = node.next.take();
+                    node as *mut ListNode as *mut u8
+                }
+                // 链表为空, 从后备分配器中分配一个新块
+                None => {
+                    let block_size = BLOCK_SIZES[index];
+                    let block_align = block_size;
+                    let layout = Layout::from_size_align(block_size, block_align).unwrap();
+                    self.fallback_alloc(layout)
+                }
+            },
+            None => self.fallback_alloc(layout),
+        }
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        match list_index(&layout) {
+            // 块不会还给后备分配器, 而是放回对应的链表中
+            Some(index) => {
+                let new_node = ListNode {
+                    next: self.list_heads[index].take(),
+                };
+                // 确认块能放下一个ListNode
+                assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
+                assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
+                let new_node_ptr = ptr as *mut ListNode;
+                new_node_ptr.write(new_node);
+                self.list_heads[index] = Some(&mut *new_node_ptr);
+            }
+            None => self.fallback_allocator.dealloc(ptr, layout),
+        }
+    }
+
+    unsafe fn extend(&mut self, start: usize, size: usize) {
+        self.fallback_allocator.extend(start, size)
+    }
+
+    // 空闲链表中的块也算作空闲内存
+    fn free_space(&self) -> (usize, usize) {
+        let (mut free, mut largest) = self.fallback_allocator.free_space();
+        for (index, head) in self.list_heads.iter().enumerate() {
+            let mut current = head;
+            while let Some(ref node) = current {
+                free += BLOCK_SIZES[index];
+                largest = largest.max(BLOCK_SIZES[index]);
+                current = &node.next;
+            }
+        }
+        (free, largest)
+    }
+}
@@ -0,0 +1,193 @@
+// 内存泄漏检查
+// 开启后, 全局堆分配器会把每一个还未释放的分配记录到一个固定大小的表中(分配器里不能再使用堆)
+// 每条记录包含分配的地址, layout, 以及通过rbp链找到的几层调用者的返回地址, 可以用addr2line找到对应的源码位置
+// 因为要沿着rbp链查找调用者, 所以在.cargo/config.toml中开启了force-frame-pointers
+
+use alloc::alloc::Layout;
+use core::arch::asm;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::Mutex;
+
+// 最多同时记录的分配个数, 超过后的分配不再记录
+const MAX_TRACKED: usize = 256;
+// 每条记录保存的调用者的层数
+const CALLER_DEPTH: usize = 4;
+// 报告中最多列出的泄漏个数
+const MAX_REPORTED: usize = 16;
+// 相邻两个栈帧之间的最大距离, 超过则认为rbp链已经不可信
+const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// 一条还未释放的分配记录
+#[derive(Debug, Clone, Copy)]
+pub struct Allocation {
+    pub id: u64,
+    pub ptr: usize,
+    pub layout: Layout,
+    /// 调用者的返回地址, 由内到外, 0表示没有找到
+    pub callers: [usize; CALLER_DEPTH],
+}
+
+struct Table {
+    entries: [Option<Allocation>; MAX_TRACKED],
+    next_id: u64,
+    // 因为表满了而没有记录的分配个数
+    dropped: usize,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+// 表中的记录个数, 为0时释放内存不需要查表
+static TRACKED: AtomicUsize = AtomicUsize::new(0);
+static TABLE: Mutex<Table> = Mutex::new(Table {
+    entries: [None; MAX_TRACKED],
+    next_id: 0,
+    dropped: 0,
+});
+
+/// 开启或关闭分配记录, 返回之前的状态
+pub fn set_enabled(enabled: bool) -> bool {
+    ENABLED.swap(enabled, Ordering::SeqCst)
+}
+
+// 由全局堆分配器在分配成功后调用
+pub(super) fn record(ptr: *mut u8, layout: Layout) {
+    use x86_64::instructions::interrupts;
+
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let callers = callers();
+    interrupts::without_interrupts(|| {
+        let mut table = TABLE.lock();
+        let id = table.next_id;
+        table.next_id += 1;
+        match table.entries.iter_mut().find(|e| e.is_none()) {
+            Some(entry) => {
+                *entry = Some(Allocation {
+                    id,
+                    ptr: ptr as usize,
+                    layout,
+                    callers,
+                });
+                TRACKED.fetch_add(1, Ordering::Relaxed);
+            }
+            None => table.dropped += 1,
+        }
+    });
+}
+
+// 由全局堆分配器在释放后调用, 关闭记录后也要删除之前的记录
+pub(super) fn forget(ptr: *mut u8) {
+    use x86_64::instructions::interrupts;
+
+    if TRACKED.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    interrupts::without_interrupts(|| {
+        let mut table = TABLE.lock();
+        let entry = table
+            .entries
+            .iter_mut()
+            .find(|e| e.map_or(false, |a| a.ptr == ptr as usize));
+        if let Some(entry) = entry {
+            *entry = None;
+            TRACKED.fetch_sub(1, Ordering::Relaxed);
+        }
+    });
+}
+
+// 沿着rbp链找到调用者的返回地址
+// 第0层是GlobalAlloc::alloc的调用者, 一般是alloc::alloc中的函数, 再往外才是真正的调用者
+fn callers() -> [usize; CALLER_DEPTH] {
+    let mut callers = [0; CALLER_DEPTH];
+    let mut rbp: usize;
+    unsafe { asm!("mov {}, rbp", out(reg) rbp) };
+
+    for caller in callers.iter_mut() {
+        if rbp == 0 || rbp % 8 != 0 {
+            break;
+        }
+        let frame = rbp as *const usize;
+        let (next, return_address) = unsafe { (*frame, *frame.add(1)) };
+        *caller = return_address;
+        if next <= rbp || next - rbp > MAX_FRAME_SIZE {
+            break;
+        }
+        rbp = next;
+    }
+    callers
+}
+
+/// 一段代码中未释放的分配
+pub struct LeakReport {
+    /// 泄漏的分配个数
+    pub count: usize,
+    /// 泄漏的字节数
+    pub bytes: usize,
+    /// 因为记录表已满而没有记录的分配个数, 不为0时报告可能不完整
+    pub dropped: usize,
+    leaks: [Option<Allocation>; MAX_REPORTED],
+}
+
+impl LeakReport {
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// 泄漏的分配, 最多列出MAX_REPORTED个
+    pub fn leaks(&self) -> impl Iterator<Item = &Allocation> {
+        self.leaks.iter().flatten()
+    }
+}
+
+impl fmt::Display for LeakReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} allocation(s) leaked, {} bytes",
+            self.count, self.bytes
+        )?;
+        for leak in self.leaks() {
+            write!(f, "  #{} {:#x} {:?} at", leak.id, leak.ptr, leak.layout)?;
+            for caller in leak.callers.iter().filter(|&&c| c != 0) {
+                write!(f, " {:#x}", caller)?;
+            }
+            writeln!(f)?;
+        }
+        if self.dropped > 0 {
+            writeln!(f, "  ({} allocation(s) were not tracked)", self.dropped)?;
+        }
+        Ok(())
+    }
+}
+
+/// 执行f, 返回f中分配了但没有释放的内存
+pub fn check_leaks<F: FnOnce()>(f: F) -> LeakReport {
+    use x86_64::instructions::interrupts;
+
+    let (first_id, dropped) = interrupts::without_interrupts(|| {
+        let table = TABLE.lock();
+        (table.next_id, table.dropped)
+    });
+    let was_enabled = set_enabled(true);
+    f();
+    set_enabled(was_enabled);
+
+    interrupts::without_interrupts(|| {
+        let table = TABLE.lock();
+        let mut report = LeakReport {
+            count: 0,
+            bytes: 0,
+            dropped: table.dropped - dropped,
+            leaks: [None; MAX_REPORTED],
+        };
+        for leak in table.entries.iter().flatten().filter(|a| a.id >= first_id) {
+            if report.count < MAX_REPORTED {
+                report.leaks[report.count] = Some(*leak);
+            }
+            report.count += 1;
+            report.bytes += leak.layout.size();
+        }
+        report
+    })
+}
@@ -1,10 +1,11 @@
 // 链表实现的堆内存分配缺点也比较明显，每次分配和释放都要便利一遍链表
 // 且内存块多的时候， 就导致无法分配更大块的内存
 // 这些缺点都是因为链表这种结构导致的
+// 为了减少碎片, 链表按地址从小到大排列, 释放的时候会与前后相邻的空闲区域合并
 
 use super::align_up;
-use super::Locked;
-use alloc::alloc::{GlobalAlloc, Layout};
+use super::HeapAllocator;
+use alloc::alloc::Layout;
 use core::mem;
 use core::ptr;
 
@@ -38,21 +39,44 @@ impl LinkedListAllocator {
         }
     }
 
-    pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
-        self.add_free_region(heap_start, heap_size);
-    }
-
     // 将空闲空间放到链表中,用于回收资源
+    // 链表按地址排序, 如果与前一个或后一个空闲区域相邻, 则合并为一个区域
     unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
         assert_eq!(align_up(addr, mem::align_of::<ListNode>()), addr);
         assert!(size >= mem::size_of::<ListNode>());
 
+        // 找到最后一个起始地址小于addr的节点, 新区域插在它后面
+        let mut current = &mut self.head;
+        while current
+            .next
+            .as_ref()
+            .map_or(false, |next| next.start_addr() < addr)
+        {
+            current = current.next.as_mut().unwrap();
+        }
+
         let mut node = ListNode::new(size);
-        node.next = self.head.next.take();
+        node.next = current.next.take();
+
+        // 与后一个区域相邻, 把后一个区域并入新区域
+        if let Some(next) = node.next.take() {
+            if addr + size == next.start_addr() {
+                node.size += next.size;
+                node.next = next.next.take();
+            } else {
+                node.next = Some(next);
+            }
+        }
 
-        let node_ptr = addr as *mut ListNode;
-        node_ptr.write(node);
-        self.head.next = Some(&mut *node_ptr);
+        // 与前一个区域相邻, 把新区域并入前一个区域, head的size为0, 不参与合并
+        if current.size > 0 && current.end_addr() == addr {
+            current.size += node.size;
+            current.next = node.next.take();
+        } else {
+            let node_ptr = addr as *mut ListNode;
+            node_ptr.write(node);
+            current.next = Some(&mut *node_ptr);
+        }
     }
 
     // 用于查询可用区域
@@ -99,18 +123,21 @@ impl LinkedListAllocator {
     }
 }
 
-unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+impl HeapAllocator for LinkedListAllocator {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.add_free_region(heap_start, heap_size);
+    }
+
+    fn alloc(&mut self, layout: Layout) -> *mut u8 {
         // perform layout adjustments
         let (size, align) = LinkedListAllocator::size_align(layout);
-        let mut allocator = self.lock();
 
         // 查找可用空间，并进行分配
-        if let Some((region, alloc_start)) = allocator.find_region(size, align) {
+        if let Some((region, alloc_start)) = self.find_region(size, align) {
             let alloc_end = alloc_start.checked_add(size).expect("overflow");
             let excess_size = region.end_addr() - alloc_end;
             if excess_size > 0 {
-                allocator.add_free_region(alloc_end, excess_size);
+                unsafe { self.add_free_region(alloc_end, excess_size) };
             }
             alloc_start as *mut u8
         } else {
@@ -118,11 +145,69 @@ unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
         }
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
         // perform layout adjustments
         let (size, _) = LinkedListAllocator::size_align(layout);
 
         // 将该空间置为free
-        self.lock().add_free_region(ptr as usize, size)
+        self.add_free_region(ptr as usize, size)
     }
+
+    unsafe fn extend(&mut self, start: usize, size: usize) {
+        // 会与之前堆末尾的空闲区域合并
+        self.add_free_region(start, size)
+    }
+
+    fn free_space(&self) -> (usize, usize) {
+        let mut free = 0;
+        let mut largest = 0;
+        let mut current = &self.head;
+        while let Some(ref region) = current.next {
+            free += region.size;
+            largest = largest.max(region.size);
+            current = region;
+        }
+        (free, largest)
+    }
+}
+
+#[cfg(test)]
+const TEST_HEAP_SIZE: usize = super::HEAP_SIZE;
+
+#[cfg(test)]
+#[repr(align(16))]
+struct TestHeap([u8; TEST_HEAP_SIZE]);
+
+#[cfg(test)]
+static mut TEST_HEAP: TestHeap = TestHeap([0; TEST_HEAP_SIZE]);
+
+#[test_case]
+fn test_free_regions_coalesce() {
+    let mut allocator = LinkedListAllocator::new();
+    unsafe {
+        let start = ptr::addr_of_mut!(TEST_HEAP.0) as usize;
+        allocator.init(start, TEST_HEAP_SIZE);
+    }
+
+    // 交替分配不同大小的内存, 再先释放奇数位置的, 后释放偶数位置的, 制造碎片
+    let sizes = [16, 200, 1000, 24, 4000];
+    let mut blocks = [(ptr::null_mut(), Layout::new::<u8>()); 40];
+    for (i, block) in blocks.iter_mut().enumerate() {
+        let layout = Layout::from_size_align(sizes[i % sizes.len()], 8).unwrap();
+        let ptr = allocator.alloc(layout);
+        assert!(!ptr.is_null());
+        *block = (ptr, layout);
+    }
+    for &(ptr, layout) in blocks.iter().skip(1).step_by(2) {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+    for &(ptr, layout) in blocks.iter().step_by(2) {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+
+    // 所有区域都合并后, 可以一次分配整个堆
+    let whole = Layout::from_size_align(TEST_HEAP_SIZE, 8).unwrap();
+    let ptr = allocator.alloc(whole);
+    assert!(!ptr.is_null());
+    unsafe { allocator.dealloc(ptr, whole) };
 }
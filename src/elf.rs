@@ -0,0 +1,161 @@
+// ELF64文件格式的解析
+// 只解析加载程序需要的部分: 文件头及程序头(program header), 节头(section header)在运行时没有用处, 一般也不会被加载
+// 文件头在文件的开头, 其中e_phoff, e_phnum及e_phentsize描述了程序头表的位置
+// 每个PT_LOAD类型的程序头描述一个需要加载的段: 文件中[p_offset, p_offset + p_filesz)的内容加载到虚拟地址p_vaddr
+// 内存中的大小p_memsz可以大于p_filesz, 多出来的部分(比如.bss)需要清零, p_flags描述了段的读/写/执行权限
+
+use core::mem;
+
+/// 可执行文件(非位置无关)
+pub const ET_EXEC: u16 = 2;
+
+/// 需要加载的段
+pub const PT_LOAD: u32 = 1;
+
+/// 段可执行
+pub const PF_X: u32 = 1;
+/// 段可写
+pub const PF_W: u32 = 2;
+/// 段可读
+pub const PF_R: u32 = 4;
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EM_X86_64: u16 = 0x3e;
+
+/// ELF64文件头
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct FileHeader {
+    pub ident: [u8; 16],
+    pub file_type: u16,
+    pub machine: u16,
+    pub version: u32,
+    pub entry: u64,
+    pub phoff: u64,
+    pub shoff: u64,
+    pub flags: u32,
+    pub ehsize: u16,
+    pub phentsize: u16,
+    pub phnum: u16,
+    pub shentsize: u16,
+    pub shnum: u16,
+    pub shstrndx: u16,
+}
+
+/// ELF64程序头
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub p_flags: u32,
+    pub p_offset: u64,
+    pub p_vaddr: u64,
+    pub p_paddr: u64,
+    pub p_filesz: u64,
+    pub p_memsz: u64,
+    pub p_align: u64,
+}
+
+impl ProgramHeader {
+    pub fn is_load(&self) -> bool {
+        self.p_type == PT_LOAD
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.p_flags & PF_W != 0
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.p_flags & PF_X != 0
+    }
+}
+
+/// 解析ELF文件时的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// 数据比文件头或程序头表短
+    Truncated,
+    /// 不是ELF文件
+    BadMagic,
+    /// 不是小端序的64位x86_64文件
+    Unsupported,
+    /// 程序头的大小与ProgramHeader不一致
+    BadProgramHeader,
+}
+
+/// 一个已经通过基本检查的ELF文件
+pub struct Elf<'a> {
+    data: &'a [u8],
+    header: FileHeader,
+}
+
+impl<'a> Elf<'a> {
+    /// 只解析并检查文件头, 用于在读取整个文件之前确定程序头表的位置
+    pub fn parse_header(data: &[u8]) -> Result<FileHeader, ElfError> {
+        if data.len() < mem::size_of::<FileHeader>() {
+            return Err(ElfError::Truncated);
+        }
+        // data不一定按8字节对齐, 所以用read_unaligned
+        let header = unsafe { (data.as_ptr() as *const FileHeader).read_unaligned() };
+        if header.ident[..4] != ELF_MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+        if header.ident[4] != ELFCLASS64
+            || header.ident[5] != ELFDATA2LSB
+            || header.machine != EM_X86_64
+        {
+            return Err(ElfError::Unsupported);
+        }
+        if header.phnum > 0 && header.phentsize as usize != mem::size_of::<ProgramHeader>() {
+            return Err(ElfError::BadProgramHeader);
+        }
+        Ok(header)
+    }
+
+    /// 检查文件头并确认程序头表在data的范围内
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        let header = Self::parse_header(data)?;
+        let table_end = header
+            .phoff
+            .checked_add(header.phnum as u64 * header.phentsize as u64)
+            .ok_or(ElfError::Truncated)?;
+        if table_end > data.len() as u64 {
+            return Err(ElfError::Truncated);
+        }
+        Ok(Elf { data, header })
+    }
+
+    pub fn header(&self) -> &FileHeader {
+        &self.header
+    }
+
+    /// 程序的入口地址
+    pub fn entry(&self) -> u64 {
+        self.header.entry
+    }
+
+    /// 所有的程序头
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        let data = self.data;
+        let phoff = self.header.phoff as usize;
+        let size = mem::size_of::<ProgramHeader>();
+        (0..self.header.phnum as usize).map(move |i| {
+            let ptr = data[phoff + i * size..].as_ptr() as *const ProgramHeader;
+            unsafe { ptr.read_unaligned() }
+        })
+    }
+
+    /// 需要加载的段
+    pub fn load_segments(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        self.program_headers().filter(|ph| ph.is_load())
+    }
+
+    /// 段在文件中的内容, 超出文件范围时返回None
+    pub fn segment_data(&self, ph: &ProgramHeader) -> Option<&'a [u8]> {
+        let start = ph.p_offset as usize;
+        let end = start.checked_add(ph.p_filesz as usize)?;
+        self.data.get(start..end)
+    }
+}
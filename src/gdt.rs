@@ -1,3 +1,6 @@
+use crate::memory::stack;
+use crate::memory::vma::VmError;
+use core::ptr;
 use lazy_static::lazy_static;
 use x86_64::structures::gdt::SegmentSelector;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable};
@@ -9,55 +12,114 @@ pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 // 当内核堆栈溢出导致页错误中断的时候，此时有一个异常指针被推入中断栈中， 导致第二次错误，同样的导致，依然会把相关指针推入栈中，导致第三次错误。
 // 而x86则是通过InterruptStackTable(ist)表来进行中段时的堆栈切换， 这样在发生堆栈溢出的时候， 中断的堆栈信息就可以推入一个实现准备好的堆栈中，就不会再引发二次中断错误了。
 // 而ist就是早期架构中tss中的一部分， 而在32位模式下的tss会保存进程的寄存器信息及硬件的上下文切换， 而在64位模式下， 则会保存特权栈表及ist.
-lazy_static! {
-    static ref TSS: TaskStateSegment = {
-        let mut tss = TaskStateSegment::new();
-        // 上面提到的ist,
-        // 需要告诉cpu，tss在哪里， 即需要加载tss
-        // 但加载tss比较繁琐， 因历史原因, tss用于分段系统中，
-        // 而分段系统则需要创建一个gdt表
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
-
-            let stack_start = VirtAddr::from_ptr(unsafe { &STACK });
-            let stack_end = stack_start + STACK_SIZE;
-            stack_end
-        };
-        tss
-    };
+// tss在启动后还需要修改(把ist换成带保护页的栈), 所以没有放在lazy_static中
+// cpu只在发生中断时读取ist, 修改时不需要重新加载tss
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
+
+// 启动时使用的二重中断栈, 此时还没有初始化分页, 只能使用静态数组, 栈下面没有保护页
+// 分页初始化完成后由install_guarded_stacks换成带保护页的栈
+const BOOT_IST_STACK_SIZE: usize = 4096 * 5;
+static mut BOOT_IST_STACK: [u8; BOOT_IST_STACK_SIZE] = [0; BOOT_IST_STACK_SIZE];
+
+// syscall指令不会切换栈, 入口代码从这里读取内核栈, 与TSS中的RSP0保持一致
+#[no_mangle]
+static mut SYSCALL_KERNEL_STACK: u64 = 0;
+
+// 二重中断栈的页数
+const DOUBLE_FAULT_STACK_PAGES: u64 = 5;
 
+lazy_static! {
     // gdt是一个全局描述符表， 即global descriptor table,
     // 只有在x86中有此表,该表可以存在在任何位置，但需要告诉cpu该表的内存地址
     // 用于存储分段信息，虽然在64位模式不再支持分段， 但该结构仍然存在， 处理内核和用户空间及tss加载
     // 分页已经是操作系统的标准实现， 所以一个操作系统即便没有分段也一定会有分页
+    // 段的顺序是syscall/sysret要求的: 内核代码段之后是内核数据段, 用户数据段之后是用户代码段
     static ref GDT: (GlobalDescriptorTable, Selectors) = {
         let mut gdt = GlobalDescriptorTable::new();
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        let data_selector = gdt.add_entry(Descriptor::kernel_data_segment());
+        let user_data_selector = gdt.add_entry(Descriptor::user_data_segment());
+        let user_code_selector = gdt.add_entry(Descriptor::user_code_segment());
+        let tss_selector = gdt.add_entry(Descriptor::tss_segment(unsafe { &*ptr::addr_of!(TSS) }));
         (
             gdt,
             Selectors {
                 code_selector,
+                data_selector,
+                user_code_selector,
+                user_data_selector,
                 tss_selector,
             },
         )
     };
 }
 
-struct Selectors {
-    code_selector: SegmentSelector,
-    tss_selector: SegmentSelector,
+/// gdt中各个段的选择子, 用户段的选择子的特权级(RPL)为3
+#[derive(Debug, Clone, Copy)]
+pub struct Selectors {
+    pub code_selector: SegmentSelector,
+    pub data_selector: SegmentSelector,
+    pub user_code_selector: SegmentSelector,
+    pub user_data_selector: SegmentSelector,
+    pub tss_selector: SegmentSelector,
+}
+
+/// gdt中各个段的选择子
+pub fn selectors() -> &'static Selectors {
+    &GDT.1
 }
 
 pub fn init() {
-    use x86_64::instructions::segmentation::{Segment, CS};
+    use x86_64::instructions::segmentation::{Segment, CS, DS, ES, SS};
     //use x86_64::instructions::segmentation::set_cs;
     use x86_64::instructions::tables::load_tss;
 
+    // 上面提到的ist,
+    // 需要告诉cpu，tss在哪里， 即需要加载tss
+    // 但加载tss比较繁琐， 因历史原因, tss用于分段系统中，
+    // 而分段系统则需要创建一个gdt表
+    let stack_start = VirtAddr::from_ptr(unsafe { ptr::addr_of!(BOOT_IST_STACK) });
+    set_ist_stack(DOUBLE_FAULT_IST_INDEX, stack_start + BOOT_IST_STACK_SIZE);
+
     GDT.0.load();
     unsafe {
         CS::set_reg(GDT.1.code_selector);
+        SS::set_reg(GDT.1.data_selector);
+        DS::set_reg(GDT.1.data_selector);
+        ES::set_reg(GDT.1.data_selector);
         load_tss(GDT.1.tss_selector);
     }
 }
+
+// 设置ist中第index个栈的栈顶
+fn set_ist_stack(index: u16, top: VirtAddr) {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| unsafe {
+        (*ptr::addr_of_mut!(TSS)).interrupt_stack_table[index as usize] = top;
+    });
+}
+
+/// 设置从用户态进入内核时使用的栈(TSS中的RSP0)
+/// 用户态发生中断或异常时, cpu先切换到这个栈再压入中断栈帧, 每个运行在用户态的任务都需要自己的内核栈
+/// syscall的入口也使用这个栈
+pub fn set_kernel_stack(top: VirtAddr) {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| unsafe {
+        (*ptr::addr_of_mut!(TSS)).privilege_stack_table[0] = top;
+        *ptr::addr_of_mut!(SYSCALL_KERNEL_STACK) = top.as_u64();
+    });
+}
+
+/// 当前TSS中的RSP0
+pub fn kernel_stack() -> VirtAddr {
+    unsafe { (*ptr::addr_of!(TSS)).privilege_stack_table[0] }
+}
+
+/// 把ist中的栈换成从虚拟内存中分配的, 下面带保护页的栈, 需要在memory::install之后调用
+pub fn install_guarded_stacks() -> Result<(), VmError> {
+    let stack = stack::alloc_stack("double fault", DOUBLE_FAULT_STACK_PAGES)?;
+    set_ist_stack(DOUBLE_FAULT_IST_INDEX, stack.top);
+    Ok(())
+}
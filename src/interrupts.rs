@@ -5,8 +5,15 @@ use pic8259::ChainedPics;
 use spin;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
 use x86_64::structures::paging::page;
+use x86_64::PrivilegeLevel;
 
 use crate::gdt;
+use crate::syscall;
+use crate::thread;
+
+pub mod exceptions;
+
+use self::exceptions::FaultReport;
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
@@ -42,15 +49,29 @@ impl InterruptIndex {
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
+        exceptions::install(&mut idt);
+        // 用户态也可以通过int3进入内核, 所以门的特权级为3
+        idt.breakpoint
+            .set_handler_fn(breakpoint_handler)
+            .set_privilege_level(PrivilegeLevel::Ring3);
         unsafe {
             idt.double_fault
                 .set_handler_fn(double_fault_handler)
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX); // 当二层中断发生时，切换到gdt中表示的堆栈中。
         }
-        idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
+        // 时钟中断会切换线程, 入口由thread中的汇编代码保存及恢复寄存器
+        unsafe {
+            idt[InterruptIndex::Timer.as_usize()].set_handler_addr(thread::timer_entry());
+            idt[thread::YIELD_VECTOR].set_handler_addr(thread::yield_entry());
+        }
         idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
         idt.page_fault.set_handler_fn(page_fault_handler);
+        // 调试用的系统调用入口, 用户态可以通过int 0x80进入
+        unsafe {
+            idt[syscall::SYSCALL_VECTOR]
+                .set_handler_addr(syscall::int80_entry())
+                .set_privilege_level(PrivilegeLevel::Ring3);
+        }
         idt
     };
 }
@@ -63,56 +84,51 @@ pub fn init_idt() {
 
 // 处理断电中断
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+    exceptions::trap(FaultReport::new(3, &stack_frame, None));
 }
 
 // 处理二重中断
 extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
-    _error_code: u64,
+    error_code: u64,
 ) -> ! {
-    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+    // 内核栈溢出时, 访问保护页产生的缺页无法在溢出的栈上处理, 最终变为二重中断
+    // 溢出的栈记录在报告中, 与报告一起交给钩子或随panic打印
+    let mut report = FaultReport::new(8, &stack_frame, Some(error_code));
+    report.stack_overflow = crate::memory::stack::overflow_cause();
+    exceptions::fault(report);
+    hlt_loop();
 }
 
-// 处理时钟中断
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+// 处理时钟中断, 由thread::timer_entry保存完被打断的线程的寄存器后调用
+// 参数是保存了寄存器的栈指针, 返回值是要恢复的栈指针, 可能属于另一个线程
+#[no_mangle]
+extern "C" fn timer_interrupt_handler(stack_pointer: u64) -> u64 {
+    crate::time::tick();
+    crate::task::timer::wake_expired();
     print!(".");
 
     unsafe {
         // 告诉中断处理器，已经处理完当前中断，可以准备好接受下一个中断，否则中断处理程序不会继续接受中断
+        // 在切换线程之前发送, 新线程iretq打开中断之后才能收到下一次时钟中断
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
     }
+    thread::schedule(stack_pointer)
 }
 
 // 处理键盘中断
+// 只读出扫描码交给task::keyboard, 解码及打印在异步任务中进行
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
     use x86_64::instructions::port::Port;
 
-    // pc_keyboard是解码键盘的相关值为对应的字符
-    use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
-    use spin::Mutex;
-
-    lazy_static! {
-        static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> = Mutex::new(
-            Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore)
-        );
-    }
-
-    let mut keyboard = KEYBOARD.lock();
     // 0x60数据端口是当前键盘按下的值
     // 如果不取出的话， 再次按键盘，就不会有相关中段产生
     let mut port = Port::new(0x60);
 
     let scancode: u8 = unsafe { port.read() };
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            match key {
-                DecodedKey::Unicode(character) => print!("{}", character),
-                DecodedKey::RawKey(key) => print!("{:?}", key),
-            }
-        }
-    }
+    crate::task::keyboard::add_scancode(scancode);
+
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
@@ -120,16 +136,32 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
 }
 
 // 页错误
+// 先交给memory::fault中注册的区域处理, 处理成功后返回, cpu会重新执行出错的指令
+// 没有区域认领或者处理失败时, 打印诊断信息并停机
 extern "x86-interrupt" fn page_fault_handler(
     stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
+    use crate::memory::fault::{self, FaultError, PageFault};
     use x86_64::registers::control::Cr2;
 
-    println!("EXCEPTION: PAGE FAULT");
     // cr2寄存器保存导致页面错误的虚拟地址(即想要访问的地址)
-    println!("Accessed Address: {:?}", Cr2::read());
+    let page_fault = PageFault {
+        addr: Cr2::read(),
+        error_code,
+    };
+    let error = match fault::resolve(&page_fault) {
+        Ok(()) => return,
+        Err(error) => error,
+    };
+
+    if let FaultError::StackOverflow(stack) = error {
+        println!("stack overflow on stack {}", stack);
+    }
+    println!("Accessed Address: {:?}", page_fault.addr);
     println!("Error Code: {:?}", error_code);
-    println!("{:#?}", stack_frame);
+    println!("Unresolved: {:?}", error);
+    // 交给异常钩子(测试中用来检查缺页), 没有钩子时打印报告并panic
+    exceptions::fault(FaultReport::new(14, &stack_frame, Some(error_code.bits())));
     hlt_loop();
 }
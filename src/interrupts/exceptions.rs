@@ -0,0 +1,232 @@
+// cpu异常的处理
+// 没有注册处理函数的异常会直接变成二重中断(double fault), 真正的原因就丢失了
+// 所以这里为每一个架构定义的异常都注册处理函数, 统一生成FaultReport, 并解码错误码
+// 异常发生后会先调用通过set_fault_hook注册的钩子(测试中用来检查异常号), 钩子没有处理的话:
+// 调试(#DB)及不可屏蔽中断(NMI)打印后继续执行, 其他的异常打印后panic
+// 断点, 二重中断及页错误的处理函数在interrupts中, 其中断点及二重中断也使用这里的报告
+// 对齐检查只在ring 3开启AC时产生, 机器检查由硬件错误产生, 都无法在测试中触发
+
+use crate::{hlt_loop, println};
+use core::fmt;
+use spin::Mutex;
+use x86_64::structures::idt::{
+    InterruptDescriptorTable, InterruptStackFrame, InterruptStackFrameValue,
+};
+
+/// 选择子错误码所指向的描述符表
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorTable {
+    Gdt,
+    Idt,
+    Ldt,
+}
+
+/// 无效TSS, 段不存在, 栈段错误及一般保护错误的错误码, 指向出错的段选择子
+/// |15        3|  2..1  |   0    |
+/// |   index   | table  |external|
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelectorError {
+    /// 异常是否发生在传递外部事件(比如硬件中断)的过程中
+    pub external: bool,
+    pub table: DescriptorTable,
+    /// 选择子在描述符表中的下标
+    pub index: u16,
+}
+
+impl SelectorError {
+    pub fn decode(error_code: u64) -> Self {
+        let table = match (error_code >> 1) & 0b11 {
+            0b00 => DescriptorTable::Gdt,
+            0b10 => DescriptorTable::Ldt,
+            _ => DescriptorTable::Idt,
+        };
+        SelectorError {
+            external: error_code & 1 != 0,
+            table,
+            index: ((error_code >> 3) & 0x1fff) as u16,
+        }
+    }
+}
+
+/// 一次cpu异常的信息
+#[derive(Clone, Copy)]
+pub struct FaultReport {
+    /// 异常号
+    pub vector: u8,
+    pub name: &'static str,
+    pub error_code: Option<u64>,
+    pub stack_frame: InterruptStackFrameValue,
+    /// 二重中断由内核栈溢出引起时, 溢出的栈的名称
+    pub stack_overflow: Option<&'static str>,
+}
+
+impl FaultReport {
+    pub fn new(vector: u8, stack_frame: &InterruptStackFrame, error_code: Option<u64>) -> Self {
+        FaultReport {
+            vector,
+            name: exception_name(vector),
+            error_code,
+            stack_frame: **stack_frame,
+            stack_overflow: None,
+        }
+    }
+
+    /// 异常是否发生在用户态(ring 3)
+    pub fn from_user(&self) -> bool {
+        self.stack_frame.code_segment & 3 == 3
+    }
+
+    /// 错误码是段选择子时, 返回解码后的选择子
+    pub fn selector_error(&self) -> Option<SelectorError> {
+        match (self.vector, self.error_code) {
+            (10..=13, Some(code)) if code != 0 => Some(SelectorError::decode(code)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FaultReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "EXCEPTION: {} (vector {})", self.name, self.vector)?;
+        if let Some(stack) = self.stack_overflow {
+            writeln!(f, "Stack overflow on stack {}", stack)?;
+        }
+        if let Some(selector) = self.selector_error() {
+            writeln!(
+                f,
+                "Selector: index {} in {:?}, external: {}",
+                selector.index, selector.table, selector.external
+            )?;
+        } else if let Some(error_code) = self.error_code {
+            writeln!(f, "Error Code: {:#x}", error_code)?;
+        }
+        write!(f, "{:#?}", self.stack_frame)
+    }
+}
+
+/// 异常号对应的名称
+pub fn exception_name(vector: u8) -> &'static str {
+    match vector {
+        0 => "DIVIDE ERROR",
+        1 => "DEBUG",
+        2 => "NON-MASKABLE INTERRUPT",
+        3 => "BREAKPOINT",
+        4 => "OVERFLOW",
+        5 => "BOUND RANGE EXCEEDED",
+        6 => "INVALID OPCODE",
+        7 => "DEVICE NOT AVAILABLE",
+        8 => "DOUBLE FAULT",
+        10 => "INVALID TSS",
+        11 => "SEGMENT NOT PRESENT",
+        12 => "STACK-SEGMENT FAULT",
+        13 => "GENERAL PROTECTION FAULT",
+        14 => "PAGE FAULT",
+        16 => "X87 FLOATING-POINT EXCEPTION",
+        17 => "ALIGNMENT CHECK",
+        18 => "MACHINE CHECK",
+        19 => "SIMD FLOATING-POINT EXCEPTION",
+        20 => "VIRTUALIZATION EXCEPTION",
+        29 => "VMM COMMUNICATION EXCEPTION",
+        30 => "SECURITY EXCEPTION",
+        _ => "RESERVED",
+    }
+}
+
+// 异常钩子, 返回true表示异常已经处理, 处理函数直接返回
+// 注意对于错误类(fault)的异常, 返回后会重新执行出错的指令
+static FAULT_HOOK: Mutex<Option<fn(&FaultReport) -> bool>> = Mutex::new(None);
+
+/// 注册异常钩子, 所有经过统一报告的异常都会先调用钩子
+pub fn set_fault_hook(hook: Option<fn(&FaultReport) -> bool>) {
+    *FAULT_HOOK.lock() = hook;
+}
+
+// 先交给钩子处理, 钩子没有处理时返回false
+fn run_hook(report: &FaultReport) -> bool {
+    let hook = *FAULT_HOOK.lock();
+    hook.map_or(false, |hook| hook(report))
+}
+
+/// 无法恢复的异常, 钩子没有处理时打印报告并panic
+pub fn fault(report: FaultReport) {
+    if !run_hook(&report) {
+        panic!("{}", report);
+    }
+}
+
+/// 可以继续执行的异常, 钩子没有处理时只打印报告
+pub fn trap(report: FaultReport) {
+    if !run_hook(&report) {
+        println!("{}", report);
+    }
+}
+
+// 生成异常处理函数, 带error_code的版本对应cpu会压入错误码的异常
+macro_rules! exception_handler {
+    ($name:ident, $vector:expr, $action:ident) => {
+        extern "x86-interrupt" fn $name(stack_frame: InterruptStackFrame) {
+            $action(FaultReport::new($vector, &stack_frame, None));
+        }
+    };
+    ($name:ident, $vector:expr, $action:ident, error_code) => {
+        extern "x86-interrupt" fn $name(stack_frame: InterruptStackFrame, error_code: u64) {
+            $action(FaultReport::new($vector, &stack_frame, Some(error_code)));
+        }
+    };
+}
+
+exception_handler!(divide_error_handler, 0, fault);
+exception_handler!(debug_handler, 1, trap);
+exception_handler!(non_maskable_interrupt_handler, 2, trap);
+exception_handler!(overflow_handler, 4, fault);
+exception_handler!(bound_range_exceeded_handler, 5, fault);
+exception_handler!(invalid_opcode_handler, 6, fault);
+exception_handler!(device_not_available_handler, 7, fault);
+exception_handler!(invalid_tss_handler, 10, fault, error_code);
+exception_handler!(segment_not_present_handler, 11, fault, error_code);
+exception_handler!(stack_segment_fault_handler, 12, fault, error_code);
+exception_handler!(general_protection_fault_handler, 13, fault, error_code);
+exception_handler!(x87_floating_point_handler, 16, fault);
+exception_handler!(alignment_check_handler, 17, fault, error_code);
+exception_handler!(simd_floating_point_handler, 19, fault);
+exception_handler!(virtualization_handler, 20, fault);
+exception_handler!(vmm_communication_exception_handler, 29, fault, error_code);
+exception_handler!(security_exception_handler, 30, fault, error_code);
+
+// 机器检查异常无法恢复, 处理函数不能返回, 即使钩子处理了也只能停机
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    fault(FaultReport::new(18, &stack_frame, None));
+    hlt_loop();
+}
+
+/// 在idt中注册以上异常的处理函数
+pub fn install(idt: &mut InterruptDescriptorTable) {
+    idt.divide_error.set_handler_fn(divide_error_handler);
+    idt.debug.set_handler_fn(debug_handler);
+    idt.non_maskable_interrupt
+        .set_handler_fn(non_maskable_interrupt_handler);
+    idt.overflow.set_handler_fn(overflow_handler);
+    idt.bound_range_exceeded
+        .set_handler_fn(bound_range_exceeded_handler);
+    idt.invalid_opcode.set_handler_fn(invalid_opcode_handler);
+    idt.device_not_available
+        .set_handler_fn(device_not_available_handler);
+    idt.invalid_tss.set_handler_fn(invalid_tss_handler);
+    idt.segment_not_present
+        .set_handler_fn(segment_not_present_handler);
+    idt.stack_segment_fault
+        .set_handler_fn(stack_segment_fault_handler);
+    idt.general_protection_fault
+        .set_handler_fn(general_protection_fault_handler);
+    idt.x87_floating_point
+        .set_handler_fn(x87_floating_point_handler);
+    idt.alignment_check.set_handler_fn(alignment_check_handler);
+    idt.machine_check.set_handler_fn(machine_check_handler);
+    idt.simd_floating_point
+        .set_handler_fn(simd_floating_point_handler);
+    idt.virtualization.set_handler_fn(virtualization_handler);
+    idt.vmm_communication_exception
+        .set_handler_fn(vmm_communication_exception_handler);
+    idt.security_exception
+        .set_handler_fn(security_exception_handler);
+}
@@ -9,10 +9,18 @@
 #![feature(const_mut_refs)]
 
 pub mod allocator;
+pub mod elf;
 pub mod gdt;
 pub mod interrupts;
+pub mod loader;
 pub mod memory;
 pub mod serial;
+pub mod sync;
+pub mod syscall;
+pub mod task;
+pub mod thread;
+pub mod time;
+pub mod usermode;
 pub mod vga_buffer; // 中断处理
 
 extern crate alloc;
@@ -25,10 +33,18 @@ use x86_64::structures::idt::InterruptDescriptorTable;
 pub fn init() {
     // 初始化全局描述符，用于处理分段
     gdt::init();
+    // 开启NX位, 之后映射的堆等区域才能设置为不可执行
+    memory::protect::enable_nxe();
+    // 开启cpu支持的SMEP, SMAP及UMIP, 内核之后只能通过copy_from_user/copy_to_user访问用户内存
+    memory::user::enable_protections();
+    // 开启CR0.WP, 内核写只读页时也产生缺页, 写时复制依赖它
+    memory::address_space::enable_write_protect();
     // 初始化中断, lib.rs中的函数可以让其在项目中任何其他位置调用。只需要使用${proj name}::方法就能进行调用
     // 如果 没初始化中断， 即程序找不到中断处理 函数 ， 则会找二重中断，如果二重中断处理函数也没找到， 则找三重， 三重也没找到一般都只能重启。
     // 操作系统一般有二层中段和是层中断， 其实就是嵌套中断， 在中断程序运行的时候，该程序又导致新的中断， 就是嵌套中断。
     interrupts::init_idt();
+    // 开启syscall/sysret指令
+    syscall::init();
 
     // 初始化中段处理器,
     unsafe { interrupts::PICS.lock().initialize() };
@@ -72,6 +88,19 @@ pub fn test_runner(tests: &[&dyn Testable]) {
     exit_qemu(QemuExitCode::Success);
 }
 
+// 测试中等待直到condition成立, 最多等待ms毫秒, 超时返回false
+// 等待时用hlt让出cpu, 需要开启中断
+pub fn wait_until(ms: u64, condition: impl Fn() -> bool) -> bool {
+    let deadline = time::ticks().saturating_add(time::ms_to_ticks(ms));
+    while !condition() {
+        if time::ticks() >= deadline {
+            return false;
+        }
+        x86_64::instructions::hlt();
+    }
+    true
+}
+
 // qemu退出码
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
@@ -0,0 +1,230 @@
+// 用户程序的加载
+// 从内存中的ELF64镜像创建一个新的地址空间: 每个PT_LOAD段映射到p_vaddr, 文件中的内容复制到新分配的帧中,
+// 超出p_filesz的部分(.bss)保持为0, 页的权限来自p_flags, 同时可写可执行的段会被拒绝(W^X)
+// 然后在用户空间的顶部映射用户栈, 按System V ABI在栈上放好参数:
+//
+//   高地址  argv及envp的字符串
+//           对齐填充
+//           auxv (类型, 值)对, 以AT_NULL结束
+//           0, envp[n-1] .. envp[0]
+//           0, argv[argc-1] .. argv[0]
+//   rsp ->  argc                        (16字节对齐)
+//
+// 只支持静态链接的ET_EXEC文件, 段之间不能共享同一页
+
+use crate::elf::{Elf, ElfError, ProgramHeader, ET_EXEC};
+use crate::memory::address_space::{self, AddressSpace, AddressSpaceError, USER_TOP};
+use crate::memory::phys_to_virt;
+use alloc::vec::Vec;
+use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+/// 用户栈的栈顶
+pub const USER_STACK_TOP: u64 = USER_TOP;
+/// 用户栈的页数
+pub const USER_STACK_PAGES: u64 = 16;
+
+/// auxv中的类型
+pub const AT_NULL: u64 = 0;
+pub const AT_PHDR: u64 = 3;
+pub const AT_PHENT: u64 = 4;
+pub const AT_PHNUM: u64 = 5;
+pub const AT_PAGESZ: u64 = 6;
+pub const AT_ENTRY: u64 = 9;
+
+const PAGE_SIZE: u64 = Page::<Size4KiB>::SIZE;
+
+/// 加载程序时的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadError {
+    /// ELF文件无法解析
+    Elf(ElfError),
+    /// 不是静态链接的可执行文件
+    NotExecutable,
+    /// 段超出了文件或用户空间的范围
+    BadSegment,
+    /// 两个段使用了同一页
+    OverlappingSegments,
+    /// 段同时可写可执行
+    WritableAndExecutable,
+    /// 参数及环境变量放不进用户栈
+    ArgumentsTooLong,
+    /// 创建地址空间或映射页失败
+    AddressSpace(AddressSpaceError),
+}
+
+impl From<ElfError> for LoadError {
+    fn from(error: ElfError) -> Self {
+        LoadError::Elf(error)
+    }
+}
+
+impl From<AddressSpaceError> for LoadError {
+    fn from(error: AddressSpaceError) -> Self {
+        LoadError::AddressSpace(error)
+    }
+}
+
+/// 加载完成, 可以运行的程序
+#[derive(Debug)]
+pub struct Program {
+    pub space: AddressSpace,
+    pub entry: VirtAddr,
+    /// 初始的栈指针, 指向argc
+    pub stack_pointer: VirtAddr,
+}
+
+impl Program {
+    /// 切换到程序的地址空间并进入用户态执行, 不会返回
+    ///
+    /// 调用者需要先通过gdt::set_kernel_stack设置内核栈
+    /// 地址空间交给程序, 程序exit时由sys_exit释放(没有设置exit的处理函数时)
+    pub unsafe fn run(self) -> ! {
+        use crate::usermode::enter_user_mode;
+
+        self.space.activate();
+        enter_user_mode(self.entry, self.stack_pointer)
+    }
+}
+
+/// 加载image中的程序, argv及envp放在用户栈上
+pub fn load(image: &[u8], argv: &[&str], envp: &[&str]) -> Result<Program, LoadError> {
+    let elf = Elf::parse(image)?;
+    if elf.header().file_type != ET_EXEC {
+        return Err(LoadError::NotExecutable);
+    }
+    let mut space = AddressSpace::new()?;
+    for ph in elf.load_segments() {
+        load_segment(&mut space, &elf, &ph)?;
+    }
+
+    let entry = VirtAddr::try_new(elf.entry()).map_err(|_| LoadError::BadSegment)?;
+    let auxv = [
+        (AT_PHDR, program_headers_addr(&elf).unwrap_or(0)),
+        (AT_PHENT, u64::from(elf.header().phentsize)),
+        (AT_PHNUM, u64::from(elf.header().phnum)),
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_ENTRY, entry.as_u64()),
+    ];
+    let stack_pointer = setup_stack(&mut space, argv, envp, &auxv)?;
+    Ok(Program {
+        space,
+        entry,
+        stack_pointer,
+    })
+}
+
+/// 把data复制到地址空间space中的addr处, 目标页需要已经映射
+pub fn copy_to_space(space: &AddressSpace, addr: VirtAddr, data: &[u8]) -> Result<(), LoadError> {
+    let mut done = 0;
+    while done < data.len() {
+        let current = addr + done as u64;
+        let (phys, _) = space
+            .translate(current)
+            .ok_or(LoadError::AddressSpace(AddressSpaceError::NotMapped))?;
+        // 每次最多复制到页的末尾
+        let n = ((PAGE_SIZE - u64::from(current.page_offset())) as usize).min(data.len() - done);
+        unsafe {
+            let dst = phys_to_virt(phys).as_mut_ptr::<u8>();
+            dst.copy_from_nonoverlapping(data[done..].as_ptr(), n);
+        }
+        done += n;
+    }
+    Ok(())
+}
+
+fn load_segment(space: &mut AddressSpace, elf: &Elf, ph: &ProgramHeader) -> Result<(), LoadError> {
+    if ph.p_memsz == 0 {
+        return Ok(());
+    }
+    if ph.is_writable() && ph.is_executable() {
+        return Err(LoadError::WritableAndExecutable);
+    }
+    let data = elf.segment_data(ph).ok_or(LoadError::BadSegment)?;
+    let end = ph
+        .p_vaddr
+        .checked_add(ph.p_memsz)
+        .ok_or(LoadError::BadSegment)?;
+    let start = VirtAddr::try_new(ph.p_vaddr).map_err(|_| LoadError::BadSegment)?;
+    if ph.p_filesz > ph.p_memsz || !address_space::is_user_address(start) || end > USER_TOP {
+        return Err(LoadError::BadSegment);
+    }
+
+    let mut flags = PageTableFlags::empty();
+    if ph.is_writable() {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if !ph.is_executable() {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    let first = Page::containing_address(start);
+    let last = Page::containing_address(VirtAddr::new(end - 1));
+    for page in Page::range_inclusive(first, last) {
+        match space.map_user(page, flags) {
+            Ok(_) => {}
+            Err(AddressSpaceError::AlreadyMapped) => return Err(LoadError::OverlappingSegments),
+            Err(error) => return Err(error.into()),
+        }
+    }
+    copy_to_space(space, start, data)
+}
+
+// 程序头表在内存中的地址, 即包含它的PT_LOAD段中对应的位置
+// 只考虑load_segment检查过的段(p_memsz不为0), 其他段的字段可能是任意值, 比较时不能溢出
+fn program_headers_addr(elf: &Elf) -> Option<u64> {
+    let phoff = elf.header().phoff;
+    elf.load_segments()
+        .filter(|ph| ph.p_memsz != 0)
+        .find(|ph| ph.p_offset <= phoff && phoff - ph.p_offset < ph.p_filesz)
+        .map(|ph| ph.p_vaddr + (phoff - ph.p_offset))
+}
+
+// 映射用户栈并放入参数, 返回初始的栈指针
+fn setup_stack(
+    space: &mut AddressSpace,
+    argv: &[&str],
+    envp: &[&str],
+    auxv: &[(u64, u64)],
+) -> Result<VirtAddr, LoadError> {
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    let top = Page::<Size4KiB>::containing_address(VirtAddr::new(USER_STACK_TOP - 1)) + 1;
+    for page in Page::range(top - USER_STACK_PAGES, top) {
+        space.map_user(page, flags)?;
+    }
+
+    // 字符串都以0结尾, 放在栈的最高处
+    let mut strings = Vec::new();
+    let mut offsets = Vec::new();
+    for s in argv.iter().chain(envp.iter()) {
+        offsets.push(strings.len() as u64);
+        strings.extend_from_slice(s.as_bytes());
+        strings.push(0);
+    }
+    let strings_start = (USER_STACK_TOP - strings.len() as u64) & !0xf;
+
+    let mut words = Vec::new();
+    words.push(argv.len() as u64);
+    words.extend(offsets[..argv.len()].iter().map(|o| strings_start + o));
+    words.push(0);
+    words.extend(offsets[argv.len()..].iter().map(|o| strings_start + o));
+    words.push(0);
+    for &(kind, value) in auxv.iter().filter(|(kind, _)| *kind != AT_NULL) {
+        words.push(kind);
+        words.push(value);
+    }
+    words.push(AT_NULL);
+    words.push(0);
+
+    let stack_pointer = (strings_start - words.len() as u64 * 8) & !0xf;
+    // 最多使用栈的一半, 剩下的留给程序
+    if USER_STACK_TOP - stack_pointer > USER_STACK_PAGES * PAGE_SIZE / 2 {
+        return Err(LoadError::ArgumentsTooLong);
+    }
+    let mut bytes = Vec::with_capacity(words.len() * 8);
+    for word in words {
+        bytes.extend_from_slice(&word.to_le_bytes());
+    }
+    copy_to_space(space, VirtAddr::new(stack_pointer), &bytes)?;
+    copy_to_space(space, VirtAddr::new(strings_start), &strings)?;
+    Ok(VirtAddr::new(stack_pointer))
+}
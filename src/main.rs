@@ -5,13 +5,15 @@
 #![test_runner(crate::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
-mod memory;
 mod serial;
 mod vga_buffer;
 extern crate alloc;
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
 use qxg_os::allocator;
+use qxg_os::memory;
+use qxg_os::task::executor::Executor;
+use qxg_os::task::{keyboard, Task};
 use x86_64::VirtAddr;
 
 // 非测试时调用此函数处理panic
@@ -63,11 +65,21 @@ fn kernal_main(boot_info: &'static BootInfo) -> ! {
     // 这是后边内存使用的基础
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
     let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    // 使用位图帧分配器, 释放的帧可以被重新分配
     let mut frame_allocator =
-        unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
+        unsafe { memory::BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
 
     // 初始化堆内存分配器, 要在分页初始化之后,依赖于分页
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap init failed");
+    // 交给全局变量管理, 之后堆空间不足时可以自动扩展
+    memory::install(mapper, frame_allocator);
+    // 登记启动栈的保护页, 并把二重中断栈换成带保护页的栈, 栈溢出时可以报告是哪个栈
+    memory::stack::guard_boot_stack();
+    qxg_os::gdt::install_guarded_stacks().expect("failed to allocate interrupt stacks");
+    // 按照ELF段的权限重新设置内核映像的页: 代码不可写, 数据不可执行
+    memory::protect::protect_kernel().expect("failed to protect kernel sections");
+    // 当前的执行流成为0号线程, 之后时钟中断会在线程之间切换
+    qxg_os::thread::init().expect("failed to start threads");
     // 在初始化完allocator就可以使用Box, Vec, Rc等等相关方法，因为这些都依赖于堆内存分配器
 
     // 不管是执行cargo test还是cargo run,入口函数都是这个
@@ -75,7 +87,10 @@ fn kernal_main(boot_info: &'static BootInfo) -> ! {
     #[cfg(test)]
     test_main();
 
-    qxg_os::hlt_loop();
+    // 键盘输入由异步任务解码及打印, 没有就绪的任务时executor用hlt休眠
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(keyboard::print_keypresses()));
+    executor.run();
 }
 
 /*
@@ -49,6 +49,21 @@
 
 // 代码中需要bootloader来支持页表映射，其中开启了map_physical_memory的feature,对应的是第三种方法。
 
+pub mod address_space;
+pub mod bitmap;
+pub mod buddy;
+pub mod dump;
+pub mod fault;
+pub mod huge;
+pub mod protect;
+pub mod stack;
+pub mod user;
+pub mod vma;
+
+pub use self::bitmap::BitmapFrameAllocator;
+pub use self::buddy::BuddyFrameAllocator;
+
+use core::sync::atomic::{AtomicU64, Ordering};
 use x86_64::structures::paging::OffsetPageTable;
 use x86_64::PhysAddr;
 use x86_64::{structures::paging::PageTable, VirtAddr};
@@ -57,10 +72,19 @@ use x86_64::{structures::paging::PageTable, VirtAddr};
 // 后续可以通过OffsetPageTable的相关方法来计算
 // table会通过physical_memory_offset来计算物理地址
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::SeqCst);
     let level_4_table = active_level_4_table(physical_memory_offset);
     OffsetPageTable::new(level_4_table, physical_memory_offset)
 }
 
+// 物理内存在虚拟地址空间中的偏移, 由init保存
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// 通过物理内存的映射访问物理地址, 需要先调用init
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed) + addr.as_u64())
+}
+
 // 访问cr3指向的四级页表， 该页表信息是x86_64的bootloader程序启动的时候就已经创建好的，因为os本身就要运行在虚拟内存之上，
 // 所以在进行entry_point之前就已经初始化好。
 // 而cr3就是指向的最顶层的页表信息及四级页表。
@@ -79,6 +103,27 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut
     &mut *page_table_ptr // unsafe
 }
 
+use spin::Mutex;
+
+// 内核的页表及帧分配器
+// 初始化完成后通过install放到全局变量中, 这样在缺页处理, 堆扩展等无法传参的地方也能修改页表
+// 加锁的顺序: 堆分配器的锁 -> MAPPER -> FRAME_ALLOCATOR, 同时需要两个锁时总是先MAPPER后FRAME_ALLOCATOR
+// 堆扩展时在持有堆分配器的锁时获取这两个锁, 所以持有它们时不能进行堆分配,
+// 否则堆扩展只能失败(grow_heap用try_lock获取, 不会死锁, 但这次分配会失败)
+// 内核中这两个锁都只在关闭中断时获取, 单核上不会被其他线程持有
+pub static MAPPER: Mutex<Option<OffsetPageTable<'static>>> = Mutex::new(None);
+pub static FRAME_ALLOCATOR: Mutex<Option<BitmapFrameAllocator>> = Mutex::new(None);
+
+/// 将页表及帧分配器交给全局变量管理
+pub fn install(mapper: OffsetPageTable<'static>, frame_allocator: BitmapFrameAllocator) {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        *MAPPER.lock() = Some(mapper);
+        *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+    });
+}
+
 use bootloader::bootinfo::MemoryMap;
 
 // BootInfo Frame内存分配器
@@ -128,6 +173,7 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
 }
 
 // 因为有了OffsetPageTable,已经包含了以下功能，所以不需要了
+// 支持大页的地址翻译见huge::translate
 // // 将虚拟地址转换为物理地址
 // pub unsafe fn translate_addr(addr: VirtAddr, physical_memory_offset: VirtAddr) -> Option<PhysAddr> {
 //     translate_addr_inner(addr, physical_memory_offset)
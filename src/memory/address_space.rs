@@ -0,0 +1,496 @@
+// 地址空间: 每个进程一套四级页表
+// 这个内核不是高半核, bootloader把内核映像, 物理内存的映射及启动栈放在低半部分的若干个四级页表项中,
+// 堆和vma也在低半部分, 所以不能简单地按高半部分/低半部分划分
+// 这里把[USER_START, USER_TOP)作为用户空间, 它对应的四级页表项由每个地址空间自己管理,
+// 其余的四级页表项都是内核的, 新建地址空间时从内核的四级页表复制过来, 共享下面的三级页表
+// 内核之后新增的映射只要落在已经存在的四级页表项中, 所有地址空间都能看到,
+// 所以创建第一个地址空间之前先为堆和vma的窗口分配好四级页表项
+// 用户页使用的帧及页表都属于地址空间, 销毁时全部还给帧分配器
+// clone_cow复制地址空间时不复制帧: 父子双方的可写页都改为只读并打上COW标记, 帧的共享计数加一,
+// 之后任何一方写这个页都会产生缺页, 由resolve_fault复制出私有的帧, 最后一个使用者直接恢复可写
+// 内核通过copy_to_user写COW页时也要产生缺页, 这需要CR0.WP, 由enable_write_protect设置
+
+use super::fault::{self, FaultError, PageFault};
+use super::vma::{VMALLOC_END, VMALLOC_START};
+use super::{phys_to_virt, BitmapFrameAllocator, FRAME_ALLOCATOR, MAPPER};
+use crate::allocator::{HEAP_MAX_SIZE, HEAP_START};
+use core::mem::ManuallyDrop;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr3};
+use x86_64::structures::paging::mapper::{MappedFrame, TranslateResult};
+use x86_64::structures::paging::page_table::PageTableEntry;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags,
+    PhysFrame, Size4KiB, Translate,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// 用户空间的起始地址, 即第32个四级页表项
+pub const USER_START: u64 = 0x0000_1000_0000_0000;
+/// 用户空间的结束地址(不含), 即第128个四级页表项, 在堆之下
+pub const USER_TOP: u64 = 0x0000_4000_0000_0000;
+
+/// 写时复制的页, 使用页表项中留给操作系统的第9位标记
+pub const COW: PageTableFlags = PageTableFlags::BIT_9;
+
+const PAGE_SIZE: u64 = Page::<Size4KiB>::SIZE;
+
+/// 地址空间操作的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressSpaceError {
+    /// 页表或帧分配器还没有初始化
+    Unavailable,
+    /// 没有空闲的物理帧
+    OutOfFrames,
+    /// 地址不在用户空间中
+    NotUserAddress,
+    /// 页已经被映射
+    AlreadyMapped,
+    /// 页没有被映射
+    NotMapped,
+    /// 修改页表失败
+    MapFailed,
+}
+
+/// 一个独立的地址空间
+#[derive(Debug)]
+pub struct AddressSpace {
+    level_4_frame: PhysFrame,
+}
+
+impl AddressSpace {
+    /// 创建一个新的地址空间, 内核部分与当前内核页表共享, 用户空间为空
+    pub fn new() -> Result<Self, AddressSpaceError> {
+        use x86_64::instructions::interrupts;
+
+        interrupts::without_interrupts(|| {
+            let mut mapper = MAPPER.lock();
+            let mut frame_allocator = FRAME_ALLOCATOR.lock();
+            let (mapper, frame_allocator) = match (mapper.as_mut(), frame_allocator.as_mut()) {
+                (Some(mapper), Some(frame_allocator)) => (mapper, frame_allocator),
+                _ => return Err(AddressSpaceError::Unavailable),
+            };
+            reserve_kernel_windows(mapper.level_4_table(), frame_allocator)?;
+
+            let frame = allocate_table(frame_allocator)?;
+            let table = unsafe { table_mut(frame.start_address()) };
+            for (i, entry) in mapper.level_4_table().iter().enumerate() {
+                if !is_user_index(i) {
+                    table[i] = entry.clone();
+                } else {
+                    debug_assert!(entry.is_unused(), "kernel mapping inside user space");
+                }
+            }
+            Ok(AddressSpace {
+                level_4_frame: frame,
+            })
+        })
+    }
+
+    /// 四级页表所在的帧, 即切换到该地址空间时写入cr3的值
+    pub fn level_4_frame(&self) -> PhysFrame {
+        self.level_4_frame
+    }
+
+    /// 是否是当前使用的地址空间
+    pub fn is_active(&self) -> bool {
+        Cr3::read().0 == self.level_4_frame
+    }
+
+    /// 切换到该地址空间
+    ///
+    /// 调用者需要保证该地址空间在切换回去之前不会被销毁
+    pub unsafe fn activate(&self) {
+        let (_, flags) = Cr3::read();
+        Cr3::write(self.level_4_frame, flags);
+    }
+
+    /// 分配一个清零的帧并映射到用户页page, 返回分配的帧
+    pub fn map_user(
+        &mut self,
+        page: Page,
+        flags: PageTableFlags,
+    ) -> Result<PhysFrame, AddressSpaceError> {
+        self.with_mapper(|space, mapper, frame_allocator| {
+            let frame = frame_allocator
+                .allocate_frame()
+                .ok_or(AddressSpaceError::OutOfFrames)?;
+            unsafe {
+                let virt = phys_to_virt(frame.start_address());
+                core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, PAGE_SIZE as usize);
+            }
+            match space.map_in(mapper, frame_allocator, page, frame, flags) {
+                Ok(()) => Ok(frame),
+                Err(error) => {
+                    unsafe { frame_allocator.deallocate_frame(frame) };
+                    Err(error)
+                }
+            }
+        })
+    }
+
+    /// 把用户页page映射到frame, frame从此属于该地址空间, 取消映射或销毁时被释放
+    pub fn map_user_to(
+        &mut self,
+        page: Page,
+        frame: PhysFrame,
+        flags: PageTableFlags,
+    ) -> Result<(), AddressSpaceError> {
+        self.with_mapper(|space, mapper, frame_allocator| {
+            space.map_in(mapper, frame_allocator, page, frame, flags)
+        })
+    }
+
+    /// 以写时复制的方式复制该地址空间, 用户页的帧由双方共享, 直到有一方写入
+    /// 目前只支持4KiB的用户页
+    pub fn clone_cow(&mut self) -> Result<AddressSpace, AddressSpaceError> {
+        use x86_64::instructions::{interrupts, tlb};
+
+        let child = AddressSpace::new()?;
+        interrupts::without_interrupts(|| {
+            let mut frame_allocator = FRAME_ALLOCATOR.lock();
+            let frame_allocator = frame_allocator
+                .as_mut()
+                .ok_or(AddressSpaceError::Unavailable)?;
+            let mut child_mapper = unsafe { child.mapper() };
+            let table = unsafe { table_mut(self.level_4_frame.start_address()) };
+            let mut result = Ok(());
+            for_each_user_page(table, &mut |addr, level, entry| {
+                if result.is_err() {
+                    return;
+                }
+                if level != 1 {
+                    result = Err(AddressSpaceError::MapFailed);
+                    return;
+                }
+                if entry.flags().contains(PageTableFlags::WRITABLE) {
+                    entry.set_flags(entry.flags() - PageTableFlags::WRITABLE | COW);
+                }
+                let flags = entry.flags() - PageTableFlags::ACCESSED - PageTableFlags::DIRTY;
+                let frame = PhysFrame::containing_address(entry.addr());
+                let page = Page::containing_address(addr);
+                result = child.map_in(&mut child_mapper, frame_allocator, page, frame, flags);
+                if result.is_ok() {
+                    frame_allocator.share_frame(frame);
+                }
+            });
+            // 父地址空间的页变成了只读, 旧的可写的翻译可能还在tlb中
+            if self.is_active() {
+                tlb::flush_all();
+            }
+            result
+        })?;
+        Ok(child)
+    }
+
+    /// 取消用户页page的映射并释放它的帧, 页表本身在销毁地址空间时才释放
+    pub fn unmap_user(&mut self, page: Page) -> Result<(), AddressSpaceError> {
+        check_user_page(page)?;
+        self.with_mapper(|space, mapper, frame_allocator| {
+            let (frame, flush) = mapper
+                .unmap(page)
+                .map_err(|_| AddressSpaceError::NotMapped)?;
+            if space.is_active() {
+                flush.flush();
+            } else {
+                flush.ignore();
+            }
+            unsafe { frame_allocator.release_frame(frame) };
+            Ok(())
+        })
+    }
+
+    /// 在该地址空间中翻译addr
+    pub fn translate(&self, addr: VirtAddr) -> Option<(PhysAddr, PageTableFlags)> {
+        let mapper = unsafe { self.mapper() };
+        match mapper.translate(addr) {
+            TranslateResult::Mapped {
+                frame,
+                offset,
+                flags,
+            } => Some((frame.start_address() + offset, flags)),
+            _ => None,
+        }
+    }
+
+    /// 用户空间中已经映射的页数
+    pub fn user_pages(&self) -> u64 {
+        let mut count = 0;
+        let table = unsafe { table_mut(self.level_4_frame.start_address()) };
+        for_each_user_page(table, &mut |_, _, _| count += 1);
+        count
+    }
+
+    // 只在持有帧分配器时访问, 这时其他代码不会同时修改这个地址空间的页表
+    unsafe fn mapper(&self) -> OffsetPageTable<'static> {
+        let table = table_mut(self.level_4_frame.start_address());
+        OffsetPageTable::new(table, phys_to_virt(PhysAddr::new(0)))
+    }
+
+    fn with_mapper<R>(
+        &mut self,
+        f: impl FnOnce(
+            &Self,
+            &mut OffsetPageTable<'static>,
+            &mut BitmapFrameAllocator,
+        ) -> Result<R, AddressSpaceError>,
+    ) -> Result<R, AddressSpaceError> {
+        use x86_64::instructions::interrupts;
+
+        interrupts::without_interrupts(|| {
+            let mut frame_allocator = FRAME_ALLOCATOR.lock();
+            let frame_allocator = frame_allocator
+                .as_mut()
+                .ok_or(AddressSpaceError::Unavailable)?;
+            let mut mapper = unsafe { self.mapper() };
+            f(self, &mut mapper, frame_allocator)
+        })
+    }
+
+    fn map_in(
+        &self,
+        mapper: &mut OffsetPageTable<'static>,
+        frame_allocator: &mut BitmapFrameAllocator,
+        page: Page,
+        frame: PhysFrame,
+        flags: PageTableFlags,
+    ) -> Result<(), AddressSpaceError> {
+        use x86_64::structures::paging::mapper::MapToError;
+
+        check_user_page(page)?;
+        let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+            Ok(flush) if self.is_active() => flush.flush(),
+            Ok(flush) => flush.ignore(),
+            Err(MapToError::PageAlreadyMapped(_)) => return Err(AddressSpaceError::AlreadyMapped),
+            Err(MapToError::FrameAllocationFailed) => return Err(AddressSpaceError::OutOfFrames),
+            Err(_) => return Err(AddressSpaceError::MapFailed),
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AddressSpace {
+    /// 释放用户空间中所有映射的帧(共享的帧只减少计数), 用户空间的页表及四级页表本身, 内核部分的页表是共享的, 不释放
+    fn drop(&mut self) {
+        use x86_64::instructions::interrupts;
+
+        // 不能销毁正在使用的页表
+        if self.is_active() {
+            activate_kernel();
+        }
+        interrupts::without_interrupts(|| {
+            let mut frame_allocator = FRAME_ALLOCATOR.lock();
+            let frame_allocator = match frame_allocator.as_mut() {
+                Some(frame_allocator) => frame_allocator,
+                None => return,
+            };
+            let table = unsafe { table_mut(self.level_4_frame.start_address()) };
+            for (i, entry) in table.iter_mut().enumerate() {
+                if is_user_index(i) && !entry.is_unused() {
+                    unsafe { free_table(entry.addr(), 3, frame_allocator) };
+                    entry.set_unused();
+                }
+            }
+            unsafe { frame_allocator.deallocate_frame(self.level_4_frame) };
+        });
+    }
+}
+
+/// 处理当前地址空间中用户页的缺页, 目前只处理写时复制, 不是写时复制的页返回Unclaimed
+pub fn resolve_fault(fault: &PageFault) -> Result<(), FaultError> {
+    if !fault.is_write() || !fault.is_protection_violation() {
+        return Err(FaultError::Unclaimed);
+    }
+    let page = fault.page();
+    let (level_4_frame, _) = Cr3::read();
+    let mut mapper = unsafe {
+        OffsetPageTable::new(
+            table_mut(level_4_frame.start_address()),
+            phys_to_virt(PhysAddr::new(0)),
+        )
+    };
+    let (old_frame, flags) = match mapper.translate(page.start_address()) {
+        TranslateResult::Mapped {
+            frame: MappedFrame::Size4KiB(frame),
+            flags,
+            ..
+        } if flags.contains(COW) => (frame, flags),
+        _ => return Err(FaultError::Unclaimed),
+    };
+    let flags = flags - COW | PageTableFlags::WRITABLE;
+
+    // 缺页可能发生在持有帧分配器的代码中, 这时只能放弃, 否则会死锁
+    let mut frame_allocator = FRAME_ALLOCATOR.try_lock().ok_or(FaultError::Unavailable)?;
+    let frame_allocator = frame_allocator.as_mut().ok_or(FaultError::Unavailable)?;
+
+    // 其他地址空间都已经复制过或者释放了这个帧, 不需要再复制
+    if frame_allocator.share_count(old_frame) == 0 {
+        let flush =
+            unsafe { mapper.update_flags(page, flags) }.map_err(|_| FaultError::MapFailed)?;
+        flush.flush();
+        return Ok(());
+    }
+
+    let new_frame = frame_allocator
+        .allocate_frame()
+        .ok_or(FaultError::OutOfFrames)?;
+    unsafe {
+        let src = phys_to_virt(old_frame.start_address());
+        let dst = phys_to_virt(new_frame.start_address());
+        core::ptr::copy_nonoverlapping(
+            src.as_ptr::<u8>(),
+            dst.as_mut_ptr::<u8>(),
+            PAGE_SIZE as usize,
+        );
+    }
+    // 原地换掉页表项中的帧, 失败时原来的映射保持不变
+    if let Err(error) = fault::replace_frame(&mut mapper, page, new_frame, flags) {
+        unsafe { frame_allocator.deallocate_frame(new_frame) };
+        return Err(error);
+    }
+    unsafe { frame_allocator.release_frame(old_frame) };
+    Ok(())
+}
+
+/// 设置CR0.WP, 内核写只读的页时也产生缺页, 写时复制对内核的写入同样有效
+/// bootloader通常已经设置了, 这里不依赖它
+pub fn enable_write_protect() {
+    unsafe { Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT)) };
+}
+
+/// addr是否在用户空间中
+pub fn is_user_address(addr: VirtAddr) -> bool {
+    (USER_START..USER_TOP).contains(&addr.as_u64())
+}
+
+/// 切换回内核的页表
+pub fn activate_kernel() {
+    if let Some(frame) = kernel_level_4_frame() {
+        let (_, flags) = Cr3::read();
+        unsafe { Cr3::write(frame, flags) };
+    }
+}
+
+/// 当前使用的地址空间, 使用内核页表时返回None
+/// 返回的地址空间不属于调用者, 不能被销毁, 所以用ManuallyDrop包装
+pub fn current() -> Option<ManuallyDrop<AddressSpace>> {
+    let (frame, _) = Cr3::read();
+    if Some(frame) == kernel_level_4_frame() {
+        return None;
+    }
+    Some(ManuallyDrop::new(AddressSpace {
+        level_4_frame: frame,
+    }))
+}
+
+// 内核页表(bootloader创建的四级页表)所在的帧
+fn kernel_level_4_frame() -> Option<PhysFrame> {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        MAPPER.lock().as_mut().map(|mapper| {
+            let virt = VirtAddr::from_ptr(mapper.level_4_table() as *const PageTable);
+            let phys = virt - phys_to_virt(PhysAddr::new(0));
+            PhysFrame::containing_address(PhysAddr::new(phys))
+        })
+    })
+}
+
+fn is_user_index(index: usize) -> bool {
+    let start = (USER_START >> 39) as usize;
+    let end = (USER_TOP >> 39) as usize;
+    start <= index && index < end
+}
+
+fn check_user_page(page: Page) -> Result<(), AddressSpaceError> {
+    if is_user_address(page.start_address()) {
+        Ok(())
+    } else {
+        Err(AddressSpaceError::NotUserAddress)
+    }
+}
+
+// 通过物理内存的映射访问页表
+unsafe fn table_mut(addr: PhysAddr) -> &'static mut PageTable {
+    &mut *phys_to_virt(addr).as_mut_ptr::<PageTable>()
+}
+
+fn allocate_table(
+    frame_allocator: &mut BitmapFrameAllocator,
+) -> Result<PhysFrame, AddressSpaceError> {
+    let frame = frame_allocator
+        .allocate_frame()
+        .ok_or(AddressSpaceError::OutOfFrames)?;
+    unsafe { table_mut(frame.start_address()).zero() };
+    Ok(frame)
+}
+
+// 为堆和vma的窗口分配四级页表项, 之后在这些窗口中新增的映射对所有地址空间都可见
+fn reserve_kernel_windows(
+    level_4_table: &mut PageTable,
+    frame_allocator: &mut BitmapFrameAllocator,
+) -> Result<(), AddressSpaceError> {
+    let windows = [
+        (HEAP_START as u64, (HEAP_START + HEAP_MAX_SIZE) as u64),
+        (VMALLOC_START, VMALLOC_END),
+    ];
+    for (start, end) in windows {
+        for index in (start >> 39)..=((end - 1) >> 39) {
+            let entry = &mut level_4_table[index as usize];
+            if entry.is_unused() {
+                let frame = allocate_table(frame_allocator)?;
+                entry.set_frame(frame, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+            }
+        }
+    }
+    Ok(())
+}
+
+// 遍历用户空间中所有映射的页, f的参数为页的起始地址, 页表项所在的级数(大页时大于1)及页表项
+fn for_each_user_page(
+    level_4_table: &mut PageTable,
+    f: &mut dyn FnMut(VirtAddr, u8, &mut PageTableEntry),
+) {
+    fn walk(
+        table: &mut PageTable,
+        level: u8,
+        base: u64,
+        f: &mut dyn FnMut(VirtAddr, u8, &mut PageTableEntry),
+    ) {
+        let entry_size = 4096u64 << (9 * (level - 1));
+        for (i, entry) in table.iter_mut().enumerate() {
+            if entry.is_unused() {
+                continue;
+            }
+            let addr = base + i as u64 * entry_size;
+            if level == 1 || entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+                f(VirtAddr::new(addr), level, entry);
+            } else {
+                walk(unsafe { table_mut(entry.addr()) }, level - 1, addr, f);
+            }
+        }
+    }
+    for (i, entry) in level_4_table.iter_mut().enumerate() {
+        if is_user_index(i) && !entry.is_unused() {
+            let base = i as u64 * (PAGE_SIZE << 27);
+            walk(unsafe { table_mut(entry.addr()) }, 3, base, f);
+        }
+    }
+}
+
+// 释放addr处的level级页表, 以及它映射的所有帧和下级页表
+unsafe fn free_table(addr: PhysAddr, level: u8, frame_allocator: &mut BitmapFrameAllocator) {
+    for entry in table_mut(addr).iter().filter(|e| !e.is_unused()) {
+        if level == 1 {
+            frame_allocator.release_frame(PhysFrame::containing_address(entry.addr()));
+        } else if entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            // 二级页表中的大页是2MiB, 三级页表中的是1GiB
+            let frames = 1 << (9 * (level - 1));
+            frame_allocator
+                .deallocate_contiguous(PhysFrame::containing_address(entry.addr()), frames);
+        } else {
+            free_table(entry.addr(), level - 1, frame_allocator);
+        }
+    }
+    frame_allocator.deallocate_frame(PhysFrame::containing_address(addr));
+}
@@ -0,0 +1,236 @@
+// 基于位图的物理帧分配器
+// BootInfoFrameAllocator每次分配都要从头遍历usable_frames()并调用nth, 分配的复杂度是O(n), 而且释放的帧无法被再次使用
+// 位图分配器为每个物理帧保存1位, 1表示空闲, 0表示已使用(或不可用)
+// 分配时按u64为单位查找非0的字, 再通过trailing_zeros找到空闲位, 释放时只需要把对应的位重新置1
+// 写时复制的帧会被多个地址空间共享, 每个帧另外保存一个u16的共享计数, 表示除第一个所有者之外还有几个映射,
+// share_frame增加计数, release_frame减少计数, 计数为0时才真正释放
+
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use core::slice;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+const FRAME_SIZE: u64 = 4096;
+const BITS_PER_WORD: usize = 64;
+
+pub struct BitmapFrameAllocator {
+    // 位图本身存放在一段可用的物理内存中, 通过physical_memory_offset访问
+    bitmap: &'static mut [u64],
+    // 每个帧的额外引用数, 与位图放在同一块区域中
+    shares: &'static mut [u16],
+    total_frames: usize,
+    free_frames: usize,
+    // 下一次开始查找的字的下标, 释放更低地址的帧时会回退
+    next: usize,
+}
+
+impl BitmapFrameAllocator {
+    /// 通过memory_map来创建位图帧分配器
+    /// 位图及共享计数会占用第一块足够大的可用区域的开头部分, 这些帧会被标记为已使用
+    /// 调用者需要保证physical_memory_offset正确, 且memory_map中的Usable区域没有被其他分配器使用过
+    pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Self {
+        let usable_regions = || {
+            memory_map
+                .iter()
+                .filter(|r| r.region_type == MemoryRegionType::Usable)
+        };
+
+        // 位图需要覆盖到最高的可用物理地址
+        let max_addr = usable_regions()
+            .map(|r| r.range.end_addr())
+            .max()
+            .unwrap_or(0);
+        let frame_count = (max_addr / FRAME_SIZE) as usize;
+        let words = (frame_count + BITS_PER_WORD - 1) / BITS_PER_WORD;
+        // 位图之后紧跟着每个帧2字节的共享计数
+        let bitmap_bytes = (words * 8 + words * BITS_PER_WORD * 2) as u64;
+
+        // 找到一块能放下位图的可用区域
+        let bitmap_region = usable_regions()
+            .find(|r| r.range.end_addr() - r.range.start_addr() >= bitmap_bytes)
+            .expect("no usable region large enough for the frame bitmap");
+        let bitmap_start = bitmap_region.range.start_addr();
+        let bitmap_end = bitmap_start + bitmap_bytes;
+
+        let virt = physical_memory_offset + bitmap_start;
+        let bitmap = slice::from_raw_parts_mut(virt.as_mut_ptr::<u64>(), words);
+        for word in bitmap.iter_mut() {
+            *word = 0;
+        }
+        let shares_virt = virt + words as u64 * 8;
+        let shares =
+            slice::from_raw_parts_mut(shares_virt.as_mut_ptr::<u16>(), words * BITS_PER_WORD);
+        for count in shares.iter_mut() {
+            *count = 0;
+        }
+
+        let mut allocator = BitmapFrameAllocator {
+            bitmap,
+            shares,
+            total_frames: 0,
+            free_frames: 0,
+            next: 0,
+        };
+
+        for region in usable_regions() {
+            for addr in
+                (region.range.start_addr()..region.range.end_addr()).step_by(FRAME_SIZE as usize)
+            {
+                // 跳过位图自身占用的帧
+                if addr >= bitmap_start && addr < bitmap_end {
+                    continue;
+                }
+                allocator.set_free(Self::frame_index(addr));
+                allocator.total_frames += 1;
+            }
+        }
+        allocator.free_frames = allocator.total_frames;
+        allocator
+    }
+
+    /// 可被分配的帧的总数(不包括位图自身占用的帧)
+    pub fn total_frames(&self) -> usize {
+        self.total_frames
+    }
+
+    /// 当前空闲的帧数
+    pub fn free_frames(&self) -> usize {
+        self.free_frames
+    }
+
+    /// 当前已被分配的帧数
+    pub fn used_frames(&self) -> usize {
+        self.total_frames - self.free_frames
+    }
+
+    /// 分配count个连续的帧, 起始帧号按align个帧对齐, 用于大页等需要连续物理内存的场景
+    pub fn allocate_contiguous(&mut self, count: usize, align: usize) -> Option<PhysFrame> {
+        if count == 0 || self.free_frames < count {
+            return None;
+        }
+        let align = align.max(1);
+        let frames = self.bitmap.len() * BITS_PER_WORD;
+        let mut start = 0;
+        while start + count <= frames {
+            // 找到第一个被占用的帧, 下一次从它之后的对齐位置开始找
+            match (start..start + count).find(|&i| !self.is_free(i)) {
+                Some(used) => start = (used / align + 1) * align,
+                None => {
+                    for i in start..start + count {
+                        self.set_used(i);
+                    }
+                    self.free_frames -= count;
+                    let addr = PhysAddr::new(start as u64 * FRAME_SIZE);
+                    return Some(PhysFrame::containing_address(addr));
+                }
+            }
+        }
+        None
+    }
+
+    /// 释放allocate_contiguous分配的count个帧
+    pub unsafe fn deallocate_contiguous(&mut self, start: PhysFrame, count: usize) {
+        for i in 0..count as u64 {
+            self.deallocate_frame(start + i);
+        }
+    }
+
+    /// 分配一个S大小的大页帧, 即连续且按S::SIZE对齐的帧
+    /// 没有为大页实现FrameAllocator, 否则allocate_frame的调用处都需要标注帧的大小
+    pub fn allocate_huge<S: PageSize>(&mut self) -> Option<PhysFrame<S>> {
+        let count = (S::SIZE / FRAME_SIZE) as usize;
+        let frame = self.allocate_contiguous(count, count)?;
+        PhysFrame::from_start_address(frame.start_address()).ok()
+    }
+
+    /// 释放allocate_huge分配的大页帧
+    pub unsafe fn deallocate_huge<S: PageSize>(&mut self, frame: PhysFrame<S>) {
+        let start = PhysFrame::containing_address(frame.start_address());
+        self.deallocate_contiguous(start, (S::SIZE / FRAME_SIZE) as usize);
+    }
+
+    /// 增加一个已分配帧的共享计数, 用于写时复制时把同一个帧映射到另一个地址空间
+    pub fn share_frame(&mut self, frame: PhysFrame) {
+        let index = Self::frame_index(frame.start_address().as_u64());
+        assert!(!self.is_free(index), "sharing free frame {:?}", frame);
+        self.shares[index] = self.shares[index]
+            .checked_add(1)
+            .expect("frame share count overflow");
+    }
+
+    /// 帧除了第一个所有者之外被共享的次数
+    pub fn share_count(&self, frame: PhysFrame) -> u16 {
+        self.shares[Self::frame_index(frame.start_address().as_u64())]
+    }
+
+    /// 放弃对帧的一个引用, 没有其他引用时释放该帧, 返回帧是否被释放
+    pub unsafe fn release_frame(&mut self, frame: PhysFrame) -> bool {
+        let index = Self::frame_index(frame.start_address().as_u64());
+        if self.shares[index] > 0 {
+            self.shares[index] -= 1;
+            false
+        } else {
+            self.deallocate_frame(frame);
+            true
+        }
+    }
+
+    fn frame_index(addr: u64) -> usize {
+        (addr / FRAME_SIZE) as usize
+    }
+
+    fn is_free(&self, index: usize) -> bool {
+        self.bitmap[index / BITS_PER_WORD] & (1 << (index % BITS_PER_WORD)) != 0
+    }
+
+    fn set_free(&mut self, index: usize) {
+        self.bitmap[index / BITS_PER_WORD] |= 1 << (index % BITS_PER_WORD);
+    }
+
+    fn set_used(&mut self, index: usize) {
+        self.bitmap[index / BITS_PER_WORD] &= !(1 << (index % BITS_PER_WORD));
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if self.free_frames == 0 {
+            return None;
+        }
+
+        // 从next开始找, 找到末尾后再从头找一遍
+        let words = self.bitmap.len();
+        for i in 0..words {
+            let word_index = (self.next + i) % words;
+            let word = self.bitmap[word_index];
+            if word != 0 {
+                let index = word_index * BITS_PER_WORD + word.trailing_zeros() as usize;
+                self.set_used(index);
+                self.free_frames -= 1;
+                self.next = word_index;
+                let addr = PhysAddr::new(index as u64 * FRAME_SIZE);
+                return Some(PhysFrame::containing_address(addr));
+            }
+        }
+
+        None
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let index = Self::frame_index(frame.start_address().as_u64());
+        assert!(
+            index / BITS_PER_WORD < self.bitmap.len(),
+            "deallocating frame outside of the bitmap: {:?}",
+            frame
+        );
+        assert!(!self.is_free(index), "double free of frame {:?}", frame);
+
+        self.set_free(index);
+        self.free_frames += 1;
+        if index / BITS_PER_WORD < self.next {
+            self.next = index / BITS_PER_WORD;
+        }
+    }
+}
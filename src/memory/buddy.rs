@@ -0,0 +1,78 @@
+// 多阶的物理帧伙伴分配器
+// 与BitmapFrameAllocator每次只能分配一个帧不同, 这里可以分配连续的2^order个帧(比如DMA缓冲区, 大页)
+// 释放时会和相邻的伙伴合并, 底层复用allocator::buddy中的伙伴算法, 空闲链表节点通过physical_memory_offset存放在空闲帧中
+
+use crate::allocator::buddy::{BuddyAllocator, BuddyStats};
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+// 4KiB帧对应的order
+const FRAME_ORDER: usize = 12;
+
+pub struct BuddyFrameAllocator {
+    inner: BuddyAllocator,
+}
+
+impl BuddyFrameAllocator {
+    /// 通过memory_map来创建伙伴帧分配器, 所有Usable区域都会交给分配器管理
+    /// 调用者需要保证physical_memory_offset正确, 且这些区域没有被其他分配器使用过
+    pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Self {
+        let mut inner =
+            BuddyAllocator::with_params(FRAME_ORDER, physical_memory_offset.as_u64() as usize);
+        let usable_regions = memory_map
+            .iter()
+            .filter(|r| r.region_type == MemoryRegionType::Usable);
+        for region in usable_regions {
+            inner.add_region(
+                region.range.start_addr() as usize,
+                region.range.end_addr() as usize,
+            );
+        }
+        BuddyFrameAllocator { inner }
+    }
+
+    /// 分配连续的2^order个帧, 返回第一个帧, 起始地址按2^order个帧对齐
+    pub fn allocate_frames(&mut self, order: usize) -> Option<PhysFrame> {
+        let addr = self.inner.alloc_order(order + FRAME_ORDER)?;
+        Some(PhysFrame::containing_address(PhysAddr::new(addr as u64)))
+    }
+
+    /// 释放由allocate_frames(order)分配的连续帧
+    pub unsafe fn deallocate_frames(&mut self, frame: PhysFrame, order: usize) {
+        let addr = frame.start_address().as_u64() as usize;
+        self.inner.dealloc_order(addr, order + FRAME_ORDER)
+    }
+
+    /// 分配器管理的总帧数
+    pub fn total_frames(&self) -> usize {
+        self.inner.stats().total >> FRAME_ORDER
+    }
+
+    /// 当前空闲的帧数
+    pub fn free_frames(&self) -> usize {
+        self.total_frames() - self.used_frames()
+    }
+
+    /// 当前已被分配的帧数
+    pub fn used_frames(&self) -> usize {
+        self.inner.stats().allocated >> FRAME_ORDER
+    }
+
+    /// 拆分及合并的统计信息, 字节数以字节为单位
+    pub fn stats(&self) -> BuddyStats {
+        self.inner.stats()
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.allocate_frames(0)
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.deallocate_frames(frame, 0)
+    }
+}
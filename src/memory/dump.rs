@@ -0,0 +1,200 @@
+// 页表的遍历及打印
+// walk从四级页表开始递归地遍历所有存在的页表项, 对每一个最终的映射(4KiB页, 或者三级/二级页表中的大页)调用回调函数
+// for_each_mapping在walk的基础上把虚拟地址和物理地址都连续, 标志相同的映射合并成一段
+// dump按照类似/proc/self/maps的格式通过串口打印每一段映射, 调试init_heap等修改页表的代码时使用
+
+use super::phys_to_virt;
+use crate::serial_println;
+use core::fmt;
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{PageTable, PageTableFlags};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// 页的大小
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageSize {
+    Size4KiB,
+    Size2MiB,
+    Size1GiB,
+}
+
+impl PageSize {
+    pub fn bytes(self) -> u64 {
+        match self {
+            PageSize::Size4KiB => 4096,
+            PageSize::Size2MiB => 2 * 1024 * 1024,
+            PageSize::Size1GiB => 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/// 一段虚拟地址到物理地址的映射
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mapping {
+    pub start: VirtAddr,
+    /// 映射的字节数
+    pub size: u64,
+    pub phys: PhysAddr,
+    pub flags: PageTableFlags,
+    pub page_size: PageSize,
+}
+
+impl Mapping {
+    pub fn end(&self) -> VirtAddr {
+        self.start + self.size
+    }
+
+    // next是否紧跟在self后面, 且可以合并成一段
+    fn can_merge(&self, next: &Mapping) -> bool {
+        self.end() == next.start
+            && self.phys + self.size == next.phys
+            && self.page_size == next.page_size
+            && comparable_flags(self.flags) == comparable_flags(next.flags)
+    }
+}
+
+// 比较标志时忽略cpu自动设置的访问位及脏位
+fn comparable_flags(flags: PageTableFlags) -> PageTableFlags {
+    flags - PageTableFlags::ACCESSED - PageTableFlags::DIRTY
+}
+
+impl fmt::Display for Mapping {
+    // 格式: 起始地址-结束地址 权限 物理地址 大小 页大小
+    // 权限依次为: r(存在) w(可写) x(可执行) u/k(用户/内核) g(全局)
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flag = |set: bool, c: char| if set { c } else { '-' };
+        write!(
+            f,
+            "{:016x}-{:016x} {}{}{}{}{} {:012x} {:>8}K {:?}",
+            self.start.as_u64(),
+            self.end().as_u64(),
+            flag(self.flags.contains(PageTableFlags::PRESENT), 'r'),
+            flag(self.flags.contains(PageTableFlags::WRITABLE), 'w'),
+            flag(!self.flags.contains(PageTableFlags::NO_EXECUTE), 'x'),
+            if self.flags.contains(PageTableFlags::USER_ACCESSIBLE) {
+                'u'
+            } else {
+                'k'
+            },
+            flag(self.flags.contains(PageTableFlags::GLOBAL), 'g'),
+            self.phys.as_u64(),
+            self.size / 1024,
+            self.page_size,
+        )
+    }
+}
+
+/// 遍历页表中所有的映射, 按虚拟地址从小到大调用f, 不合并
+///
+/// 上级页表项中的权限会限制下级的映射, 所以传给f的标志中, 可写及用户位只在每一级都设置时才保留, 不可执行位只要有一级设置就保留
+pub fn walk(level_4_table: &PageTable, f: &mut dyn FnMut(&Mapping)) {
+    let parent = PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    walk_table(level_4_table, 4, 0, parent, f);
+}
+
+/// 遍历页表中所有的映射, 连续的映射会被合并
+pub fn for_each_mapping(level_4_table: &PageTable, f: &mut dyn FnMut(&Mapping)) {
+    let mut current: Option<Mapping> = None;
+    walk(level_4_table, &mut |mapping| match current.as_mut() {
+        Some(range) if range.can_merge(mapping) => range.size += mapping.size,
+        _ => {
+            if let Some(range) = current.replace(*mapping) {
+                f(&range);
+            }
+        }
+    });
+    if let Some(range) = current {
+        f(&range);
+    }
+}
+
+/// 当前cr3指向的四级页表, 需要先调用memory::init
+pub fn active_level_4_table() -> &'static PageTable {
+    let (frame, _) = Cr3::read();
+    unsafe { &*phys_to_virt(frame.start_address()).as_ptr::<PageTable>() }
+}
+
+/// 通过串口打印页表中的所有映射
+pub fn dump(level_4_table: &PageTable) {
+    let mut count = 0;
+    let mut total = 0;
+    for_each_mapping(level_4_table, &mut |mapping| {
+        serial_println!("{}", mapping);
+        count += 1;
+        total += mapping.size;
+    });
+    serial_println!("{} ranges, {} KiB mapped", count, total / 1024);
+}
+
+/// 打印当前使用的页表
+pub fn dump_active() {
+    dump(active_level_4_table())
+}
+
+/// addr所在页的实际权限, 即考虑了各级页表项后的标志(与walk的规则相同), 没有映射时返回None
+pub fn effective_flags(level_4_table: &PageTable, addr: VirtAddr) -> Option<PageTableFlags> {
+    let mut table = level_4_table;
+    let mut flags = PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    for level in (1..=4u8).rev() {
+        let index = (addr.as_u64() >> (12 + 9 * (level - 1) as u64)) & 0x1ff;
+        let entry = &table[index as usize];
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+        flags = inherit(flags, entry.flags());
+        if level == 1 || entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return Some(flags);
+        }
+        table = unsafe { &*phys_to_virt(entry.addr()).as_ptr::<PageTable>() };
+    }
+    None
+}
+
+// 权限位在各级页表之间的继承
+fn inherit(parent: PageTableFlags, flags: PageTableFlags) -> PageTableFlags {
+    let restricting = PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    let mut flags = flags - (restricting - (parent & restricting));
+    if parent.contains(PageTableFlags::NO_EXECUTE) {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+    flags
+}
+
+fn walk_table(
+    table: &PageTable,
+    level: u8,
+    base: u64,
+    parent: PageTableFlags,
+    f: &mut dyn FnMut(&Mapping),
+) {
+    // 每一项覆盖的地址范围: 一级页表4KiB, 二级2MiB, 三级1GiB, 四级512GiB
+    let entry_size = 4096u64 << (9 * (level - 1));
+    for (i, entry) in table.iter().enumerate() {
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        // 四级页表中高半部分的地址需要符号扩展成规范地址
+        let start = VirtAddr::new_truncate(base + i as u64 * entry_size);
+        let flags = inherit(parent, entry.flags());
+
+        let page_size = match level {
+            1 => Some(PageSize::Size4KiB),
+            2 if entry.flags().contains(PageTableFlags::HUGE_PAGE) => Some(PageSize::Size2MiB),
+            3 if entry.flags().contains(PageTableFlags::HUGE_PAGE) => Some(PageSize::Size1GiB),
+            _ => None,
+        };
+        match page_size {
+            Some(page_size) => f(&Mapping {
+                start,
+                size: entry_size,
+                phys: entry.addr(),
+                flags,
+                page_size,
+            }),
+            None => {
+                let next = unsafe { &*phys_to_virt(entry.addr()).as_ptr::<PageTable>() };
+                walk_table(next, level - 1, start.as_u64(), flags, f);
+            }
+        }
+    }
+}
@@ -0,0 +1,304 @@
+// 缺页处理
+// 内核可以把一段虚拟地址注册为一个区域(FaultRegion), 并指定发生缺页时的处理函数
+// 缺页中断中先找到包含出错地址的区域, 由区域的处理函数映射缺少的页后直接返回, 出错的指令会被重新执行
+// 没有区域认领的地址, 或处理函数失败时, 才打印诊断信息并停机
+// 内置的处理函数有:
+// zero_fill: 第一次访问时分配一个清零的帧(按需分配)
+// guard: 保护页, 永远不映射, 访问即报错
+// copy_on_write: 写只读页时复制一份可写的帧
+// 用户空间中写时复制的页由address_space::resolve_fault处理
+
+use super::{address_space, phys_to_virt, BitmapFrameAllocator, FRAME_ALLOCATOR, MAPPER};
+use core::fmt;
+use spin::Mutex;
+use x86_64::instructions::tlb;
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::structures::paging::mapper::TranslateResult;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags,
+    PhysFrame, Size4KiB, Translate,
+};
+use x86_64::VirtAddr;
+
+// 最多同时注册的区域个数, 缺页中断中不能使用堆, 所以用固定大小的表
+const MAX_REGIONS: usize = 64;
+
+/// 区域的缺页处理函数, 成功时缺少的页已经被映射
+pub type FaultHandler = fn(
+    fault: &PageFault,
+    region: &FaultRegion,
+    mapper: &mut OffsetPageTable<'static>,
+    frame_allocator: &mut BitmapFrameAllocator,
+) -> Result<(), FaultError>;
+
+/// 一次缺页的信息
+#[derive(Debug, Clone, Copy)]
+pub struct PageFault {
+    /// 出错的地址(Cr2)
+    pub addr: VirtAddr,
+    pub error_code: PageFaultErrorCode,
+}
+
+impl PageFault {
+    pub fn page(&self) -> Page {
+        Page::containing_address(self.addr)
+    }
+
+    pub fn is_write(&self) -> bool {
+        self.error_code
+            .contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+    }
+
+    /// 页已经存在, 缺页是由权限引起的
+    pub fn is_protection_violation(&self) -> bool {
+        self.error_code
+            .contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+    }
+}
+
+/// 一段注册了缺页处理函数的虚拟地址[start, end)
+#[derive(Clone, Copy)]
+pub struct FaultRegion {
+    pub name: &'static str,
+    pub start: VirtAddr,
+    pub end: VirtAddr,
+    /// 处理函数映射页时使用的标志
+    pub flags: PageTableFlags,
+    pub handler: FaultHandler,
+}
+
+impl FaultRegion {
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end
+    }
+}
+
+impl fmt::Debug for FaultRegion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FaultRegion")
+            .field("name", &self.name)
+            .field("start", &self.start)
+            .field("end", &self.end)
+            .field("flags", &self.flags)
+            .finish()
+    }
+}
+
+/// 缺页无法处理的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultError {
+    /// 没有区域包含出错的地址
+    Unclaimed,
+    /// 访问了保护页, 参数为区域的名称
+    GuardPage(&'static str),
+    /// 访问了栈下面的保护页, 参数为栈的名称
+    StackOverflow(&'static str),
+    /// 区域不允许这种访问, 比如写只读区域
+    AccessViolation,
+    /// 没有空闲的物理帧
+    OutOfFrames,
+    /// 修改页表失败
+    MapFailed,
+    /// 页表或帧分配器还没有初始化, 或者缺页时正被占用
+    Unavailable,
+}
+
+/// 注册区域时的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionError {
+    /// 与已注册的区域重叠
+    Overlap,
+    /// 区域表已满
+    TableFull,
+    /// 地址没有按页对齐或区域为空
+    Invalid,
+}
+
+static REGIONS: Mutex<[Option<FaultRegion>; MAX_REGIONS]> = Mutex::new([None; MAX_REGIONS]);
+
+/// 注册一个区域, 区域的起止地址需要按页对齐
+pub fn register_region(region: FaultRegion) -> Result<(), RegionError> {
+    use x86_64::instructions::interrupts;
+
+    if region.start >= region.end
+        || !region.start.is_aligned(Page::<Size4KiB>::SIZE)
+        || !region.end.is_aligned(Page::<Size4KiB>::SIZE)
+    {
+        return Err(RegionError::Invalid);
+    }
+    interrupts::without_interrupts(|| {
+        let mut regions = REGIONS.lock();
+        let overlap = regions
+            .iter()
+            .flatten()
+            .any(|r| r.start < region.end && region.start < r.end);
+        if overlap {
+            return Err(RegionError::Overlap);
+        }
+        let slot = regions
+            .iter_mut()
+            .find(|r| r.is_none())
+            .ok_or(RegionError::TableFull)?;
+        *slot = Some(region);
+        Ok(())
+    })
+}
+
+/// 删除起始地址为start的区域, 返回被删除的区域, 已经映射的页不受影响
+pub fn unregister_region(start: VirtAddr) -> Option<FaultRegion> {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        REGIONS
+            .lock()
+            .iter_mut()
+            .find(|r| r.map_or(false, |r| r.start == start))
+            .and_then(|r| r.take())
+    })
+}
+
+/// 包含addr的区域
+pub fn find_region(addr: VirtAddr) -> Option<FaultRegion> {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        REGIONS
+            .lock()
+            .iter()
+            .flatten()
+            .find(|r| r.contains(addr))
+            .copied()
+    })
+}
+
+/// 尝试处理一次缺页, 由缺页中断调用, 成功时可以直接返回重新执行出错的指令
+pub fn resolve(fault: &PageFault) -> Result<(), FaultError> {
+    // 用户空间的页属于当前的地址空间, 先看是不是写时复制
+    if address_space::is_user_address(fault.addr) {
+        match address_space::resolve_fault(fault) {
+            Err(FaultError::Unclaimed) => {}
+            result => return result,
+        }
+    }
+    let region = find_region(fault.addr).ok_or(FaultError::Unclaimed)?;
+
+    // 缺页可能发生在持有页表锁的代码中, 这时只能放弃, 否则会死锁
+    let mut mapper = MAPPER.try_lock().ok_or(FaultError::Unavailable)?;
+    let mut frame_allocator = FRAME_ALLOCATOR.try_lock().ok_or(FaultError::Unavailable)?;
+    match (mapper.as_mut(), frame_allocator.as_mut()) {
+        (Some(mapper), Some(frame_allocator)) => {
+            (region.handler)(fault, &region, mapper, frame_allocator)
+        }
+        _ => Err(FaultError::Unavailable),
+    }
+}
+
+/// 按需分配: 第一次访问时映射一个清零的帧
+pub fn zero_fill(
+    fault: &PageFault,
+    region: &FaultRegion,
+    mapper: &mut OffsetPageTable<'static>,
+    frame_allocator: &mut BitmapFrameAllocator,
+) -> Result<(), FaultError> {
+    // 页已经存在, 说明是权限问题, 不是缺页
+    if fault.is_protection_violation() {
+        return Err(FaultError::AccessViolation);
+    }
+    if fault.is_write() && !region.flags.contains(PageTableFlags::WRITABLE) {
+        return Err(FaultError::AccessViolation);
+    }
+
+    let frame = frame_allocator
+        .allocate_frame()
+        .ok_or(FaultError::OutOfFrames)?;
+    unsafe {
+        let virt = phys_to_virt(frame.start_address());
+        core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, Page::<Size4KiB>::SIZE as usize);
+    }
+    let flags = region.flags | PageTableFlags::PRESENT;
+    match unsafe { mapper.map_to(fault.page(), frame, flags, frame_allocator) } {
+        Ok(flush) => {
+            flush.flush();
+            Ok(())
+        }
+        Err(_) => {
+            unsafe { frame_allocator.deallocate_frame(frame) };
+            Err(FaultError::MapFailed)
+        }
+    }
+}
+
+/// 保护页: 不映射任何页, 访问即失败
+pub fn guard(
+    _fault: &PageFault,
+    region: &FaultRegion,
+    _mapper: &mut OffsetPageTable<'static>,
+    _frame_allocator: &mut BitmapFrameAllocator,
+) -> Result<(), FaultError> {
+    Err(FaultError::GuardPage(region.name))
+}
+
+/// 写时复制: 区域中的页以只读方式映射, 写入时复制到一个新帧, 再以region.flags(可写)重新映射
+/// 原来的帧可能还被其他映射共享, 所以这里不释放
+pub fn copy_on_write(
+    fault: &PageFault,
+    region: &FaultRegion,
+    mapper: &mut OffsetPageTable<'static>,
+    frame_allocator: &mut BitmapFrameAllocator,
+) -> Result<(), FaultError> {
+    if !fault.is_write() || !fault.is_protection_violation() {
+        return Err(FaultError::AccessViolation);
+    }
+    let page = fault.page();
+    let old_frame = match mapper.translate(page.start_address()) {
+        TranslateResult::Mapped { frame, .. } => frame.start_address(),
+        _ => return Err(FaultError::AccessViolation),
+    };
+
+    let new_frame = frame_allocator
+        .allocate_frame()
+        .ok_or(FaultError::OutOfFrames)?;
+    unsafe {
+        let src = phys_to_virt(old_frame);
+        let dst = phys_to_virt(new_frame.start_address());
+        core::ptr::copy_nonoverlapping(
+            src.as_ptr::<u8>(),
+            dst.as_mut_ptr::<u8>(),
+            Page::<Size4KiB>::SIZE as usize,
+        );
+    }
+
+    let flags = region.flags | PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    if let Err(error) = replace_frame(mapper, page, new_frame, flags) {
+        unsafe { frame_allocator.deallocate_frame(new_frame) };
+        return Err(error);
+    }
+    Ok(())
+}
+
+/// 把已经映射的4KiB页原地换成frame及flags, 并刷新tlb
+/// 只修改最后一级的页表项, 不需要分配页表, 失败时原来的映射保持不变
+pub(crate) fn replace_frame(
+    mapper: &mut OffsetPageTable<'static>,
+    page: Page,
+    frame: PhysFrame,
+    flags: PageTableFlags,
+) -> Result<(), FaultError> {
+    let mut table: &mut PageTable = mapper.level_4_table();
+    for &index in [page.p4_index(), page.p3_index(), page.p2_index()].iter() {
+        let entry = &table[index];
+        if !entry.flags().contains(PageTableFlags::PRESENT)
+            || entry.flags().contains(PageTableFlags::HUGE_PAGE)
+        {
+            return Err(FaultError::MapFailed);
+        }
+        table = unsafe { &mut *phys_to_virt(entry.addr()).as_mut_ptr::<PageTable>() };
+    }
+    let entry = &mut table[page.p1_index()];
+    if !entry.flags().contains(PageTableFlags::PRESENT) {
+        return Err(FaultError::MapFailed);
+    }
+    entry.set_frame(frame, flags);
+    tlb::flush(page.start_address());
+    Ok(())
+}
@@ -0,0 +1,99 @@
+// 大页(2MiB/1GiB)的映射及地址翻译
+// 二级页表项设置HUGE_PAGE时直接映射2MiB, 三级页表项设置时直接映射1GiB, 一个tlb项就能覆盖整个大页
+// 大页需要连续且按大页大小对齐的物理帧, 由BitmapFrameAllocator::allocate_huge提供
+// 1GiB的页需要cpu支持(cpuid 0x80000001的edx第26位), 使用前通过supports_1gib_pages检查
+
+use super::dump::PageSize as MappedSize;
+use super::{FRAME_ALLOCATOR, MAPPER};
+use x86_64::structures::paging::mapper::{MapToError, TranslateResult, UnmapError};
+use x86_64::structures::paging::{
+    Mapper, OffsetPageTable, Page, PageSize, PageTableFlags, Translate,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// cpu是否支持1GiB的页
+pub fn supports_1gib_pages() -> bool {
+    use core::arch::x86_64::__cpuid;
+
+    let max_extended = unsafe { __cpuid(0x8000_0000) }.eax;
+    max_extended >= 0x8000_0001 && unsafe { __cpuid(0x8000_0001) }.edx & (1 << 26) != 0
+}
+
+/// 分配连续的帧并把page映射为一个大页, 需要先调用memory::install
+pub fn map_huge<S: PageSize>(page: Page<S>, flags: PageTableFlags) -> Result<(), MapToError<S>>
+where
+    for<'a> OffsetPageTable<'a>: Mapper<S>,
+{
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut mapper = MAPPER.lock();
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let (mapper, frame_allocator) = match (mapper.as_mut(), frame_allocator.as_mut()) {
+            (Some(mapper), Some(frame_allocator)) => (mapper, frame_allocator),
+            _ => return Err(MapToError::FrameAllocationFailed),
+        };
+        let frame = frame_allocator
+            .allocate_huge::<S>()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        match unsafe {
+            mapper.map_to(
+                page,
+                frame,
+                flags | PageTableFlags::PRESENT,
+                frame_allocator,
+            )
+        } {
+            Ok(flush) => {
+                flush.flush();
+                Ok(())
+            }
+            Err(error) => {
+                unsafe { frame_allocator.deallocate_huge(frame) };
+                Err(error)
+            }
+        }
+    })
+}
+
+/// 取消map_huge建立的映射并释放对应的帧
+pub unsafe fn unmap_huge<S: PageSize>(page: Page<S>) -> Result<(), UnmapError>
+where
+    for<'a> OffsetPageTable<'a>: Mapper<S>,
+{
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut mapper = MAPPER.lock();
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let (mapper, frame_allocator) = match (mapper.as_mut(), frame_allocator.as_mut()) {
+            (Some(mapper), Some(frame_allocator)) => (mapper, frame_allocator),
+            _ => return Err(UnmapError::PageNotMapped),
+        };
+        let (frame, flush) = mapper.unmap(page)?;
+        flush.flush();
+        frame_allocator.deallocate_huge(frame);
+        Ok(())
+    })
+}
+
+/// 把虚拟地址翻译为物理地址, 同时返回所在页的大小, 支持大页
+pub fn translate(addr: VirtAddr) -> Option<(PhysAddr, MappedSize)> {
+    use x86_64::instructions::interrupts;
+    use x86_64::structures::paging::mapper::MappedFrame;
+
+    interrupts::without_interrupts(|| {
+        let mapper = MAPPER.lock();
+        match mapper.as_ref()?.translate(addr) {
+            TranslateResult::Mapped { frame, offset, .. } => {
+                let size = match frame {
+                    MappedFrame::Size4KiB(_) => MappedSize::Size4KiB,
+                    MappedFrame::Size2MiB(_) => MappedSize::Size2MiB,
+                    MappedFrame::Size1GiB(_) => MappedSize::Size1GiB,
+                };
+                Some((frame.start_address() + offset, size))
+            }
+            _ => None,
+        }
+    })
+}
@@ -0,0 +1,157 @@
+// W^X(写与执行互斥)及NX位
+// 开启EFER.NXE后, 页表项的第63位(NO_EXECUTE)才有效, 否则该位是保留位, 设置后会产生缺页
+// bootloader按段映射内核, 但没有严格区分权限, protect_kernel根据内核的ELF程序头重新设置每一页的权限:
+// 代码段只读可执行, 只读数据段只读不可执行, 数据段及bss可写不可执行
+// 可写的段与可执行的段共享同一页时无法同时满足两者, protect_kernel拒绝这样的映像, 不会创建可写又可执行的页
+// 堆, vma及内核栈在映射时就设置了NO_EXECUTE
+// 内核的ELF文件头通过链接器提供的__ehdr_start访问, 它位于第一个加载段的开头, 所以已经被映射
+
+use super::dump;
+use super::MAPPER;
+use crate::elf::{Elf, ElfError, FileHeader, ProgramHeader};
+use core::{mem, ptr, slice};
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::paging::mapper::TranslateResult;
+use x86_64::structures::paging::{Mapper, Page, PageTableFlags, Size4KiB, Translate};
+use x86_64::VirtAddr;
+
+extern "C" {
+    // 由链接器(lld)定义, 指向内存中的ELF文件头
+    static __ehdr_start: u8;
+}
+
+/// 修改内核页权限时的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtectError {
+    /// 内核的ELF文件头无法解析
+    Elf(ElfError),
+    /// 页表还没有初始化
+    Unavailable,
+    /// 修改某一页的标志失败, 比如该页使用了大页映射
+    UpdateFailed(VirtAddr),
+    /// 可写的段与可执行的段共享这一页
+    WritableAndExecutable(VirtAddr),
+}
+
+/// 开启EFER.NXE, 之后页表中的NO_EXECUTE位才会生效
+pub fn enable_nxe() {
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE)) };
+}
+
+/// 当前运行的内核的ELF文件(只包含文件头及程序头表)
+pub fn kernel_elf() -> Result<Elf<'static>, ProtectError> {
+    let start = unsafe { ptr::addr_of!(__ehdr_start) };
+    let header = unsafe { slice::from_raw_parts(start, mem::size_of::<FileHeader>()) };
+    let header = Elf::parse_header(header).map_err(ProtectError::Elf)?;
+    let len = header.phoff as usize + header.phnum as usize * mem::size_of::<ProgramHeader>();
+    let image = unsafe { slice::from_raw_parts(start, len) };
+    Elf::parse(image).map_err(ProtectError::Elf)
+}
+
+// 段覆盖的页
+fn segment_pages(ph: &ProgramHeader) -> impl Iterator<Item = Page> {
+    let start = Page::containing_address(VirtAddr::new(ph.p_vaddr));
+    let end = Page::containing_address(VirtAddr::new(ph.p_vaddr + ph.p_memsz.max(1) - 1));
+    Page::range_inclusive(start, end)
+}
+
+/// 内核映像占用的虚拟地址范围[start, end)
+pub fn kernel_range() -> Result<(VirtAddr, VirtAddr), ProtectError> {
+    let elf = kernel_elf()?;
+    let start = elf.load_segments().map(|ph| ph.p_vaddr).min().unwrap_or(0);
+    let end = elf
+        .load_segments()
+        .map(|ph| ph.p_vaddr + ph.p_memsz)
+        .max()
+        .unwrap_or(0);
+    Ok((VirtAddr::new(start), VirtAddr::new(end)))
+}
+
+// 覆盖page的所有段的权限的并集: (可写, 可执行)
+fn page_permissions(elf: &Elf, page: Page) -> (bool, bool) {
+    elf.load_segments()
+        .filter(|ph| segment_pages(ph).any(|p| p == page))
+        .fold((false, false), |(w, x), ph| {
+            (w || ph.is_writable(), x || ph.is_executable())
+        })
+}
+
+/// 检查elf中没有同时需要可写及可执行的页, 即可写的段与可执行的段不共享页
+pub fn check_wx(elf: &Elf) -> Result<(), ProtectError> {
+    for ph in elf.load_segments() {
+        for page in segment_pages(&ph) {
+            if page_permissions(elf, page) == (true, true) {
+                return Err(ProtectError::WritableAndExecutable(page.start_address()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 根据内核的程序头重新设置内核映像中每一页的权限, 返回修改的页数
+/// 有页需要同时可写可执行时(见check_wx)不修改任何页, 返回WritableAndExecutable
+pub fn protect_kernel() -> Result<usize, ProtectError> {
+    use x86_64::instructions::interrupts;
+
+    let elf = kernel_elf()?;
+    check_wx(&elf)?;
+    interrupts::without_interrupts(|| {
+        let mut mapper = MAPPER.lock();
+        let mapper = mapper.as_mut().ok_or(ProtectError::Unavailable)?;
+        let mut updated = 0;
+        for ph in elf.load_segments() {
+            for page in segment_pages(&ph) {
+                let (writable, executable) = page_permissions(&elf, page);
+
+                let current = match mapper.translate(page.start_address()) {
+                    TranslateResult::Mapped { flags, .. } => flags,
+                    _ => continue,
+                };
+                let mut flags = current - PageTableFlags::WRITABLE - PageTableFlags::NO_EXECUTE;
+                if writable {
+                    flags |= PageTableFlags::WRITABLE;
+                }
+                if !executable {
+                    flags |= PageTableFlags::NO_EXECUTE;
+                }
+                if flags == current {
+                    continue;
+                }
+                let flush = unsafe { mapper.update_flags(page, flags) }
+                    .map_err(|_| ProtectError::UpdateFailed(page.start_address()))?;
+                flush.flush();
+                updated += 1;
+            }
+        }
+        Ok(updated)
+    })
+}
+
+/// addr所在页的标志
+pub fn page_flags(addr: VirtAddr) -> Option<PageTableFlags> {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mapper = MAPPER.lock();
+        match mapper.as_ref()?.translate(addr) {
+            TranslateResult::Mapped { flags, .. } => Some(flags),
+            _ => None,
+        }
+    })
+}
+
+/// [start, end)中同时可写又可执行的4KiB页的个数
+pub fn wx_violations(start: VirtAddr, end: VirtAddr) -> u64 {
+    let mut count = 0;
+    dump::walk(dump::active_level_4_table(), &mut |mapping| {
+        let writable = mapping.flags.contains(PageTableFlags::WRITABLE);
+        let executable = !mapping.flags.contains(PageTableFlags::NO_EXECUTE);
+        if !writable || !executable || mapping.end() <= start || end <= mapping.start {
+            return;
+        }
+        let overlap_start = mapping.start.max(start);
+        let overlap_end = mapping.end().min(end);
+        count += (overlap_end - overlap_start) / Page::<Size4KiB>::SIZE;
+    });
+    count
+}
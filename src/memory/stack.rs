@@ -0,0 +1,182 @@
+// 带保护页的内核栈
+// 栈从vma中分配, 最低的一页不映射, 作为保护页, 并在fault中注册为栈保护区域
+// 栈溢出时访问保护页产生缺页, 但cpu无法在已经溢出的栈上压入缺页的中断栈帧, 所以实际产生的是二重中断
+// 二重中断在ist中的栈上执行, 通过overflowed_stack检查Cr2是否落在某个栈的保护页中
+// 启动栈由bootloader分配, 下面已经有一个不映射的页, guard_boot_stack只是把它登记下来
+
+use super::fault::{self, FaultError, FaultRegion, PageFault};
+use super::vma::{self, Backing, VmError};
+use super::{BitmapFrameAllocator, FRAME_ALLOCATOR, MAPPER};
+use spin::Mutex;
+use x86_64::registers::control::Cr2;
+use x86_64::structures::paging::{
+    FrameDeallocator, Mapper, OffsetPageTable, Page, PageTableFlags, Size4KiB, Translate,
+};
+use x86_64::VirtAddr;
+
+const PAGE_SIZE: u64 = Page::<Size4KiB>::SIZE;
+// 最多登记的栈个数
+const MAX_STACKS: usize = 32;
+// 查找启动栈的保护页时最多向下检查的页数
+const MAX_BOOT_STACK_PAGES: u64 = 1024;
+
+/// 一个内核栈, 栈从top向下增长到bottom, guard为bottom下面的保护页
+#[derive(Debug, Clone, Copy)]
+pub struct KernelStack {
+    pub name: &'static str,
+    pub guard: VirtAddr,
+    pub bottom: VirtAddr,
+    pub top: VirtAddr,
+}
+
+// 已登记的栈, 二重中断时用来查找溢出的栈
+static STACKS: Mutex<[Option<KernelStack>; MAX_STACKS]> = Mutex::new([None; MAX_STACKS]);
+
+/// 分配一个pages页的内核栈, 栈下面有一个保护页
+pub fn alloc_stack(name: &'static str, pages: u64) -> Result<KernelStack, VmError> {
+    let flags = PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    // 栈必须提前映射, 否则栈上的缺页也需要在这个栈上处理
+    let area = vma::vmap(name, (pages + 1) * PAGE_SIZE, flags, Backing::Eager)?;
+    let stack = KernelStack {
+        name,
+        guard: area.start,
+        bottom: area.start + PAGE_SIZE,
+        top: area.end(),
+    };
+
+    // 取消最低一页的映射, 作为保护页
+    let unmapped = unmap_guard(stack.guard);
+    let registered = unmapped.and_then(|_| register(stack));
+    if let Err(error) = registered {
+        let _ = vma::vfree(area.start);
+        return Err(error);
+    }
+    Ok(stack)
+}
+
+/// 释放alloc_stack分配的栈
+///
+/// 调用者需要保证没有代码还在使用这个栈
+pub unsafe fn free_stack(stack: KernelStack) -> Result<(), VmError> {
+    unregister(stack.guard);
+    // 保护页的区域与vma的起始地址相同, 会在vfree中一起删除
+    vma::vfree(stack.guard)
+}
+
+/// 登记当前正在使用的启动栈的保护页, 返回找到的栈
+pub fn guard_boot_stack() -> Option<KernelStack> {
+    use core::arch::asm;
+    use x86_64::instructions::interrupts;
+
+    let rsp: u64;
+    unsafe { asm!("mov {}, rsp", out(reg) rsp) };
+    let top_page = Page::<Size4KiB>::containing_address(VirtAddr::new(rsp));
+
+    // 从当前的栈向下找到第一个没有映射的页, 即bootloader留下的保护页
+    let guard = interrupts::without_interrupts(|| {
+        let mapper = MAPPER.lock();
+        let mapper = mapper.as_ref()?;
+        (1..MAX_BOOT_STACK_PAGES)
+            .map(|i| top_page - i)
+            .find(|page| mapper.translate_addr(page.start_address()).is_none())
+    })?;
+
+    let stack = KernelStack {
+        name: "boot",
+        guard: guard.start_address(),
+        bottom: guard.start_address() + PAGE_SIZE,
+        top: (top_page + 1).start_address(),
+    };
+    register(stack).ok()?;
+    Some(stack)
+}
+
+/// addr落在哪个栈的保护页中, 返回栈的名称
+pub fn overflowed_stack(addr: VirtAddr) -> Option<&'static str> {
+    use x86_64::instructions::interrupts;
+
+    // 二重中断可能发生在持有锁的时候, 所以不能等待
+    interrupts::without_interrupts(|| {
+        STACKS
+            .try_lock()?
+            .iter()
+            .flatten()
+            .find(|s| s.guard <= addr && addr < s.bottom)
+            .map(|s| s.name)
+    })
+}
+
+/// 根据Cr2检查二重中断是否由栈溢出引起
+pub fn overflow_cause() -> Option<&'static str> {
+    overflowed_stack(Cr2::read())
+}
+
+/// 栈保护页的缺页处理函数
+pub fn stack_guard(
+    _fault: &PageFault,
+    region: &FaultRegion,
+    _mapper: &mut OffsetPageTable<'static>,
+    _frame_allocator: &mut BitmapFrameAllocator,
+) -> Result<(), FaultError> {
+    Err(FaultError::StackOverflow(region.name))
+}
+
+fn unmap_guard(guard: VirtAddr) -> Result<(), VmError> {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut mapper = MAPPER.lock();
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let (mapper, frame_allocator) = match (mapper.as_mut(), frame_allocator.as_mut()) {
+            (Some(mapper), Some(frame_allocator)) => (mapper, frame_allocator),
+            _ => return Err(VmError::Unavailable),
+        };
+        let (frame, flush) = mapper
+            .unmap(Page::<Size4KiB>::containing_address(guard))
+            .map_err(|_| VmError::MapFailed)?;
+        flush.flush();
+        unsafe { frame_allocator.deallocate_frame(frame) };
+        Ok(())
+    })
+}
+
+fn register(stack: KernelStack) -> Result<(), VmError> {
+    use x86_64::instructions::interrupts;
+
+    fault::register_region(FaultRegion {
+        name: stack.name,
+        start: stack.guard,
+        end: stack.bottom,
+        flags: PageTableFlags::empty(),
+        handler: stack_guard,
+    })
+    .map_err(|_| VmError::TableFull)?;
+
+    interrupts::without_interrupts(|| {
+        let mut stacks = STACKS.lock();
+        match stacks.iter_mut().find(|s| s.is_none()) {
+            Some(slot) => {
+                *slot = Some(stack);
+                Ok(())
+            }
+            None => {
+                fault::unregister_region(stack.guard);
+                Err(VmError::TableFull)
+            }
+        }
+    })
+}
+
+fn unregister(guard: VirtAddr) {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut stacks = STACKS.lock();
+        if let Some(slot) = stacks
+            .iter_mut()
+            .find(|s| s.map_or(false, |s| s.guard == guard))
+        {
+            *slot = None;
+        }
+    });
+}
@@ -0,0 +1,151 @@
+// 用户内存的保护及访问
+// SMEP: 内核态不能执行用户页中的代码, 防止内核被诱导跳转到用户准备好的代码
+// SMAP: 内核态不能读写用户页, 只有在EFLAGS.AC置位时才允许, stac/clac指令用来设置/清除AC
+// UMIP: 用户态不能执行sgdt, sidt, sldt, smsw及str, 避免泄露内核数据结构的地址
+// 这几个功能不是所有cpu都支持, init时通过cpuid检测, 只开启支持的部分
+// 内核只能通过copy_from_user/copy_to_user访问用户内存, 它们先检查用户地址的每一页都允许这种访问,
+// 再在stac/clac之间复制, 错误的指针返回UserCopyError而不会产生缺页
+// 还没有映射的页只要属于允许用户访问的缺页区域(fault::FaultRegion)也可以访问, 复制时由缺页处理映射
+
+use super::address_space::COW;
+use super::{dump, fault};
+use core::arch::asm;
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::registers::control::{Cr4, Cr4Flags};
+use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+// 用户地址空间的上限(不含), 即低半部分的规范地址
+pub const USER_END: u64 = 0x0000_8000_0000_0000;
+
+// 开启了SMAP时才能使用stac/clac, 否则是无效指令
+static SMAP_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// cpu支持的用户内存保护功能
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Protections {
+    pub smep: bool,
+    pub smap: bool,
+    pub umip: bool,
+}
+
+/// 复制用户内存时的错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserCopyError {
+    /// 范围超出了用户地址空间
+    OutOfRange,
+    /// 该地址没有映射, 或者不允许用户以这种方式访问
+    BadAddress(VirtAddr),
+}
+
+/// 通过cpuid检测支持的功能
+pub fn detect() -> Protections {
+    let max_leaf = unsafe { __cpuid(0) }.eax;
+    if max_leaf < 7 {
+        return Protections::default();
+    }
+    // 结构化扩展功能: ebx第7位SMEP, 第20位SMAP, ecx第2位UMIP
+    let features = unsafe { __cpuid_count(7, 0) };
+    Protections {
+        smep: features.ebx & (1 << 7) != 0,
+        smap: features.ebx & (1 << 20) != 0,
+        umip: features.ecx & (1 << 2) != 0,
+    }
+}
+
+/// 开启cpu支持的SMEP, SMAP及UMIP, 返回开启的功能
+pub fn enable_protections() -> Protections {
+    let supported = detect();
+    let mut flags = Cr4Flags::empty();
+    if supported.smep {
+        flags |= Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION;
+    }
+    if supported.smap {
+        flags |= Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION;
+    }
+    if supported.umip {
+        flags |= Cr4Flags::USER_MODE_INSTRUCTION_PREVENTION;
+    }
+    unsafe { Cr4::update(|cr4| cr4.insert(flags)) };
+    SMAP_ENABLED.store(supported.smap, Ordering::SeqCst);
+    supported
+}
+
+/// 当前开启的功能
+pub fn enabled_protections() -> Protections {
+    let cr4 = Cr4::read();
+    Protections {
+        smep: cr4.contains(Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION),
+        smap: cr4.contains(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION),
+        umip: cr4.contains(Cr4Flags::USER_MODE_INSTRUCTION_PREVENTION),
+    }
+}
+
+/// 从用户地址src复制dst.len()字节到dst
+pub fn copy_from_user(dst: &mut [u8], src: VirtAddr) -> Result<(), UserCopyError> {
+    check_user_range(src, dst.len() as u64, false)?;
+    with_user_access(|| unsafe {
+        ptr::copy_nonoverlapping(src.as_ptr::<u8>(), dst.as_mut_ptr(), dst.len())
+    });
+    Ok(())
+}
+
+/// 把src复制到用户地址dst
+pub fn copy_to_user(dst: VirtAddr, src: &[u8]) -> Result<(), UserCopyError> {
+    check_user_range(dst, src.len() as u64, true)?;
+    with_user_access(|| unsafe {
+        ptr::copy_nonoverlapping(src.as_ptr(), dst.as_mut_ptr::<u8>(), src.len())
+    });
+    Ok(())
+}
+
+/// 检查[start, start + len)是否都是用户可以访问的内存, write表示是否需要可写
+pub fn check_user_range(start: VirtAddr, len: u64, write: bool) -> Result<(), UserCopyError> {
+    if len == 0 {
+        return Ok(());
+    }
+    let end = start
+        .as_u64()
+        .checked_add(len)
+        .ok_or(UserCopyError::OutOfRange)?;
+    if end > USER_END {
+        return Err(UserCopyError::OutOfRange);
+    }
+
+    let mut required = PageTableFlags::USER_ACCESSIBLE;
+    if write {
+        required |= PageTableFlags::WRITABLE;
+    }
+    let table = dump::active_level_4_table();
+    let first = Page::<Size4KiB>::containing_address(start);
+    let last = Page::<Size4KiB>::containing_address(VirtAddr::new(end - 1));
+    for page in Page::range_inclusive(first, last) {
+        let addr = page.start_address();
+        let flags = dump::effective_flags(table, addr)
+            .or_else(|| fault::find_region(addr).map(|region| region.flags));
+        // 出错时报告范围内的第一个地址, 而不是页的起始地址
+        let bad = UserCopyError::BadAddress(addr.max(start));
+        match flags {
+            Some(flags) if flags.contains(required) => {}
+            // 写时复制的页, 写入时由缺页处理复制一份私有的帧
+            Some(flags) if write && flags.contains(PageTableFlags::USER_ACCESSIBLE | COW) => {}
+            _ => return Err(bad),
+        }
+    }
+    Ok(())
+}
+
+// 在stac/clac之间执行f, 这期间内核可以访问用户页
+fn with_user_access<R>(f: impl FnOnce() -> R) -> R {
+    let smap = SMAP_ENABLED.load(Ordering::SeqCst);
+    if smap {
+        unsafe { asm!("stac", options(nostack)) };
+    }
+    let result = f();
+    if smap {
+        unsafe { asm!("clac", options(nostack)) };
+    }
+    result
+}
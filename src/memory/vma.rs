@@ -0,0 +1,316 @@
+// 内核虚拟内存区域(vma), 类似linux的vmalloc
+// 从[VMALLOC_START, VMALLOC_END)中分配一段连续的虚拟地址, 记录它的页表标志及后备策略(Backing)
+// Backing::Demand只保留地址, 在fault中注册zero_fill区域, 第一次访问某一页时才分配帧
+// Backing::Eager在分配时就映射所有页
+// Backing::Huge在分配时就用2MiB的大页映射, 区域的起始地址及大小都按2MiB对齐, 适合大块的长期使用的内存
+// 相邻的区域之间至少隔一个不映射的页, 越界访问会产生缺页而不是写到别的区域中
+// 释放时取消所有已映射的页并归还对应的帧
+
+use super::fault::{self, FaultRegion};
+use super::{phys_to_virt, FRAME_ALLOCATOR, MAPPER};
+use spin::Mutex;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, Size2MiB, Size4KiB,
+};
+use x86_64::VirtAddr;
+
+pub const VMALLOC_START: u64 = 0x6000_0000_0000;
+pub const VMALLOC_END: u64 = VMALLOC_START + 0x100_0000_0000; // 1TiB
+
+// 最多同时存在的区域个数
+const MAX_AREAS: usize = 64;
+const PAGE_SIZE: u64 = Page::<Size4KiB>::SIZE;
+const HUGE_PAGE_SIZE: u64 = Page::<Size2MiB>::SIZE;
+
+/// 区域的后备策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backing {
+    /// 第一次访问时才分配清零的帧
+    Demand,
+    /// 分配区域时就映射所有的页
+    Eager,
+    /// 分配区域时就用2MiB的大页映射所有的页
+    Huge,
+}
+
+/// 一段已分配的虚拟地址
+#[derive(Debug, Clone, Copy)]
+pub struct VmArea {
+    pub name: &'static str,
+    pub start: VirtAddr,
+    /// 页数
+    pub pages: u64,
+    pub flags: PageTableFlags,
+    pub backing: Backing,
+}
+
+impl VmArea {
+    pub fn end(&self) -> VirtAddr {
+        self.start + self.pages * PAGE_SIZE
+    }
+
+    pub fn size(&self) -> u64 {
+        self.pages * PAGE_SIZE
+    }
+
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end()
+    }
+
+    fn page_range(&self) -> impl Iterator<Item = Page> {
+        let start = Page::containing_address(self.start);
+        Page::range(start, start + self.pages)
+    }
+
+    fn huge_page_range(&self) -> impl Iterator<Item = Page<Size2MiB>> {
+        let start = Page::containing_address(self.start);
+        Page::range(start, start + self.size() / HUGE_PAGE_SIZE)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmError {
+    /// 大小为0或超出地址空间
+    Invalid,
+    /// 没有足够大的虚拟地址空间
+    OutOfAddressSpace,
+    /// 区域表已满
+    TableFull,
+    /// 没有空闲的物理帧
+    OutOfFrames,
+    /// 修改页表失败
+    MapFailed,
+    /// 页表或帧分配器还没有初始化
+    Unavailable,
+    /// 没有以该地址开始的区域
+    NotFound,
+}
+
+static AREAS: Mutex<[Option<VmArea>; MAX_AREAS]> = Mutex::new([None; MAX_AREAS]);
+
+/// 按需分配size字节(向上取整到页)的可写, 不可执行的内存
+pub fn vmalloc(size: u64) -> Result<VirtAddr, VmError> {
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE;
+    vmap("vmalloc", size, flags, Backing::Demand).map(|area| area.start)
+}
+
+/// 分配一段虚拟内存区域
+pub fn vmap(
+    name: &'static str,
+    size: u64,
+    flags: PageTableFlags,
+    backing: Backing,
+) -> Result<VmArea, VmError> {
+    use x86_64::instructions::interrupts;
+
+    if size == 0 {
+        return Err(VmError::Invalid);
+    }
+    // 大页区域的大小及起始地址都需要按2MiB对齐
+    let align = match backing {
+        Backing::Huge => HUGE_PAGE_SIZE / PAGE_SIZE,
+        _ => 1,
+    };
+    let pages = size
+        .checked_add(PAGE_SIZE - 1)
+        .and_then(|size| (size / PAGE_SIZE).checked_add(align - 1))
+        .ok_or(VmError::Invalid)?
+        / align
+        * align;
+    let flags = flags | PageTableFlags::PRESENT;
+
+    let area = interrupts::without_interrupts(|| {
+        let mut areas = AREAS.lock();
+        let start = find_free_range(&areas[..], pages, align).ok_or(VmError::OutOfAddressSpace)?;
+        let slot = areas
+            .iter_mut()
+            .find(|a| a.is_none())
+            .ok_or(VmError::TableFull)?;
+        let area = VmArea {
+            name,
+            start,
+            pages,
+            flags,
+            backing,
+        };
+        *slot = Some(area);
+        Ok(area)
+    })?;
+
+    let backed = match backing {
+        Backing::Demand => fault::register_region(FaultRegion {
+            name,
+            start: area.start,
+            end: area.end(),
+            flags,
+            handler: fault::zero_fill,
+        })
+        .map_err(|_| VmError::TableFull),
+        Backing::Eager => map_all(&area),
+        Backing::Huge => map_all_huge(&area),
+    };
+    if let Err(error) = backed {
+        let _ = vfree(area.start);
+        return Err(error);
+    }
+    Ok(area)
+}
+
+/// 释放以start开始的区域, 已映射的页会被取消映射, 帧还给帧分配器
+pub fn vfree(start: VirtAddr) -> Result<(), VmError> {
+    use x86_64::instructions::interrupts;
+
+    let area = interrupts::without_interrupts(|| {
+        AREAS
+            .lock()
+            .iter_mut()
+            .find(|a| a.map_or(false, |a| a.start == start))
+            .and_then(|a| a.take())
+    })
+    .ok_or(VmError::NotFound)?;
+
+    // 先删除缺页区域, 之后的访问不会再分配新的帧
+    fault::unregister_region(area.start);
+
+    interrupts::without_interrupts(|| {
+        let mut mapper = MAPPER.lock();
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let (mapper, frame_allocator) = match (mapper.as_mut(), frame_allocator.as_mut()) {
+            (Some(mapper), Some(frame_allocator)) => (mapper, frame_allocator),
+            _ => return Err(VmError::Unavailable),
+        };
+        if area.backing == Backing::Huge {
+            for page in area.huge_page_range() {
+                if let Ok((frame, flush)) = mapper.unmap(page) {
+                    flush.flush();
+                    unsafe { frame_allocator.deallocate_huge(frame) };
+                }
+            }
+            return Ok(());
+        }
+        for page in area.page_range() {
+            if let Ok((frame, flush)) = mapper.unmap(page) {
+                flush.flush();
+                unsafe { frame_allocator.deallocate_frame(frame) };
+            }
+        }
+        Ok(())
+    })
+}
+
+/// 包含addr的区域
+pub fn find_area(addr: VirtAddr) -> Option<VmArea> {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        AREAS
+            .lock()
+            .iter()
+            .flatten()
+            .find(|a| a.contains(addr))
+            .copied()
+    })
+}
+
+/// 区域中已经映射了帧的页数
+pub fn resident_pages(area: &VmArea) -> u64 {
+    use x86_64::instructions::interrupts;
+    use x86_64::structures::paging::Translate;
+
+    interrupts::without_interrupts(|| {
+        let mapper = MAPPER.lock();
+        let mapper = match mapper.as_ref() {
+            Some(mapper) => mapper,
+            None => return 0,
+        };
+        area.page_range()
+            .filter(|page| mapper.translate_addr(page.start_address()).is_some())
+            .count() as u64
+    })
+}
+
+// 首次适配, 找到能放下pages页, 且按align页对齐的空闲地址, 与前后的区域之间各留一个保护页
+fn find_free_range(areas: &[Option<VmArea>], pages: u64, align: u64) -> Option<VirtAddr> {
+    let size = pages.checked_add(1)?.checked_mul(PAGE_SIZE)?;
+    let align = align * PAGE_SIZE;
+    let mut candidate = VMALLOC_START;
+    loop {
+        candidate = (candidate + align - 1) / align * align;
+        let end = candidate.checked_add(size)?;
+        if end > VMALLOC_END {
+            return None;
+        }
+        let overlap = areas
+            .iter()
+            .flatten()
+            .filter(|a| a.start.as_u64() < end && candidate < a.end().as_u64() + PAGE_SIZE)
+            .map(|a| a.end().as_u64() + PAGE_SIZE)
+            .max();
+        match overlap {
+            Some(next) => candidate = next,
+            None => return Some(VirtAddr::new(candidate)),
+        }
+    }
+}
+
+// 映射区域中的所有页, 帧都清零
+fn map_all(area: &VmArea) -> Result<(), VmError> {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut mapper = MAPPER.lock();
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let (mapper, frame_allocator) = match (mapper.as_mut(), frame_allocator.as_mut()) {
+            (Some(mapper), Some(frame_allocator)) => (mapper, frame_allocator),
+            _ => return Err(VmError::Unavailable),
+        };
+        for page in area.page_range() {
+            let frame = frame_allocator
+                .allocate_frame()
+                .ok_or(VmError::OutOfFrames)?;
+            unsafe {
+                let virt = phys_to_virt(frame.start_address());
+                core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, PAGE_SIZE as usize);
+                match mapper.map_to(page, frame, area.flags, frame_allocator) {
+                    Ok(flush) => flush.flush(),
+                    Err(_) => {
+                        frame_allocator.deallocate_frame(frame);
+                        return Err(VmError::MapFailed);
+                    }
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+// 用2MiB的大页映射区域中的所有页, 帧都清零
+fn map_all_huge(area: &VmArea) -> Result<(), VmError> {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut mapper = MAPPER.lock();
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let (mapper, frame_allocator) = match (mapper.as_mut(), frame_allocator.as_mut()) {
+            (Some(mapper), Some(frame_allocator)) => (mapper, frame_allocator),
+            _ => return Err(VmError::Unavailable),
+        };
+        for page in area.huge_page_range() {
+            let frame = frame_allocator
+                .allocate_huge::<Size2MiB>()
+                .ok_or(VmError::OutOfFrames)?;
+            unsafe {
+                let virt = phys_to_virt(frame.start_address());
+                core::ptr::write_bytes(virt.as_mut_ptr::<u8>(), 0, HUGE_PAGE_SIZE as usize);
+                match mapper.map_to(page, frame, area.flags, frame_allocator) {
+                    Ok(flush) => flush.flush(),
+                    Err(_) => {
+                        frame_allocator.deallocate_huge(frame);
+                        return Err(VmError::MapFailed);
+                    }
+                }
+            }
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,17 @@
+// 会阻塞线程的同步原语
+// spin::Mutex在锁被占用时一直忙等, 被抢占的持有者要等到下一次时钟中断才能继续, 等待者浪费了整个时间片
+// 这里的类型在无法获取时把当前线程放到等待队列中并park, 释放时按先进先出的顺序直接把锁或许可交给队首的线程,
+// 后来的线程不能插队, 所以是公平的
+// 只能在线程上下文中使用(需要先调用thread::init), 中断处理中用到的锁(WRITER, SERIAL1, PICS等)仍然使用spin::Mutex
+
+pub mod condvar;
+pub mod mutex;
+pub mod rwlock;
+pub mod semaphore;
+pub mod wait_queue;
+
+pub use self::condvar::Condvar;
+pub use self::mutex::{Mutex, MutexGuard};
+pub use self::rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use self::semaphore::Semaphore;
+pub use self::wait_queue::WaitQueue;
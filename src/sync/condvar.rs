@@ -0,0 +1,58 @@
+// 条件变量
+// wait在持有等待队列的锁时释放互斥锁, 之后的notify一定能唤醒它, 不会丢失通知
+// 被唤醒后重新获取互斥锁, 此时条件可能又被其他线程改变, 调用者需要在循环中检查(或使用wait_while)
+
+use super::{MutexGuard, WaitQueue};
+use core::mem;
+
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Condvar {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// 释放guard对应的锁并阻塞, 被唤醒后重新获取锁
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex();
+        // 锁在排队之后由下面的闭包释放
+        mem::forget(guard);
+        self.waiters.wait_unless(|| {
+            unsafe { mutex.unlock() };
+            false
+        });
+        mutex.lock()
+    }
+
+    /// condition成立时一直等待
+    pub fn wait_while<'a, T: ?Sized>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+    ) -> MutexGuard<'a, T> {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// 唤醒最早等待的线程
+    pub fn notify_one(&self) -> bool {
+        self.waiters.notify_one()
+    }
+
+    /// 唤醒所有等待的线程, 返回唤醒的线程数
+    pub fn notify_all(&self) -> usize {
+        self.waiters.notify_all()
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
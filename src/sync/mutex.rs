@@ -0,0 +1,119 @@
+// 阻塞的互斥锁
+// 没有竞争时只需要一次原子操作, 锁被占用时排队等待
+// 释放时有等待者就把锁直接交给队首的线程(locked保持为true), 否则才清除locked
+
+use super::WaitQueue;
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+pub struct Mutex<T: ?Sized> {
+    locked: AtomicBool,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Self {
+        Mutex {
+            locked: AtomicBool::new(false),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// 获取锁, 被占用时阻塞当前线程
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        if !self.acquire() {
+            // 被唤醒时锁已经交给了当前线程
+            self.waiters.wait_unless(|| self.acquire());
+        }
+        MutexGuard {
+            mutex: self,
+            _marker: PhantomData,
+        }
+    }
+
+    /// 尝试获取锁, 不阻塞
+    /// 有线程在排队时锁不会被释放, 所以不会插到它们前面
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        if self.acquire() {
+            Some(MutexGuard {
+                mutex: self,
+                _marker: PhantomData,
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    fn acquire(&self) -> bool {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    // 释放锁, 调用者必须持有锁
+    pub(super) unsafe fn unlock(&self) {
+        self.waiters
+            .notify_one_or(|| self.locked.store(false, Ordering::Release));
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Mutex::new(T::default())
+    }
+}
+
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+    // 只有&'a Mutex<T>时, T: Send就会使guard成为Sync, 共享guard的线程可以同时访问只实现了Send的T
+    // 按&mut T计算: T: Sync时guard才是Sync
+    _marker: PhantomData<&'a mut T>,
+}
+
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    // 供Condvar在等待时释放锁, 之后重新获取
+    pub(super) fn mutex(&self) -> &'a Mutex<T> {
+        self.mutex
+    }
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        unsafe { self.mutex.unlock() };
+    }
+}
@@ -0,0 +1,114 @@
+// 读写锁
+// 读者及写者都先经过turnstile(公平的互斥锁): 读者经过后立即释放它, 写者一直持有到写完
+// 写者在turnstile中排队时, 之后到来的读者排在它后面, 所以写者不会被源源不断的读者饿死
+// 写者拿到turnstile后还要等已经进入的读者全部离开
+
+use super::{Mutex, MutexGuard, WaitQueue};
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub struct RwLock<T: ?Sized> {
+    turnstile: Mutex<()>,
+    readers: AtomicUsize,
+    // 最后一个读者离开时唤醒等待的写者
+    no_readers: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(data: T) -> Self {
+        RwLock {
+            turnstile: Mutex::new(()),
+            readers: AtomicUsize::new(0),
+            no_readers: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    /// 获取共享的读锁, 有写者持有或在等待时阻塞
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        let turnstile = self.turnstile.lock();
+        self.readers.fetch_add(1, Ordering::Acquire);
+        drop(turnstile);
+        RwLockReadGuard { lock: self }
+    }
+
+    /// 获取独占的写锁
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        let turnstile = self.turnstile.lock();
+        // 读者数量只会减少, 被唤醒后再检查一次即可
+        while self.readers.load(Ordering::Acquire) != 0 {
+            self.no_readers
+                .wait_unless(|| self.readers.load(Ordering::Acquire) == 0);
+        }
+        RwLockWriteGuard {
+            lock: self,
+            _turnstile: turnstile,
+        }
+    }
+
+    /// 当前持有读锁的线程数
+    pub fn readers(&self) -> usize {
+        self.readers.load(Ordering::Relaxed)
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        RwLock::new(T::default())
+    }
+}
+
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T: ?Sized> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.lock.readers.fetch_sub(1, Ordering::Release) == 1 {
+            self.lock.no_readers.notify_all();
+        }
+    }
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+    // 写完之前一直持有, 后来的读者及写者都在turnstile中排队
+    _turnstile: MutexGuard<'a, ()>,
+}
+
+impl<T: ?Sized> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
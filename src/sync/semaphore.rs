@@ -0,0 +1,48 @@
+// 计数信号量
+// 没有许可时排队等待, release时有等待者就把许可直接交给队首的线程, 否则才增加计数
+
+use super::WaitQueue;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub struct Semaphore {
+    permits: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Semaphore {
+            permits: AtomicUsize::new(permits),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// 获取一个许可, 没有时阻塞当前线程
+    pub fn acquire(&self) {
+        if !self.try_acquire() {
+            // 被唤醒时许可已经交给了当前线程
+            self.waiters.wait_unless(|| self.try_acquire());
+        }
+    }
+
+    /// 尝试获取一个许可, 不阻塞
+    pub fn try_acquire(&self) -> bool {
+        self.permits
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |permits| {
+                permits.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    /// 归还一个许可
+    pub fn release(&self) {
+        self.waiters.notify_one_or(|| {
+            self.permits.fetch_add(1, Ordering::Release);
+        });
+    }
+
+    /// 当前可用的许可数
+    pub fn available(&self) -> usize {
+        self.permits.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,121 @@
+// 等待队列
+// 等待的线程按到达的顺序排队, notify从队首开始唤醒
+// 每个等待者在自己的栈上有一个标志, notify设置标志后再unpark, 等待者只有看到标志才返回,
+// 所以park的虚假唤醒(比如之前留下的unpark)不会让wait提前返回
+
+use crate::thread::{self, ThreadId};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+struct Waiter {
+    id: ThreadId,
+    // 指向等待者栈上的标志, 等待者在标志被设置之前不会返回, 所以指针一直有效
+    notified: *const AtomicBool,
+}
+
+// 只在持有队列的锁时访问notified
+unsafe impl Send for Waiter {}
+
+pub struct WaitQueue {
+    waiters: spin::Mutex<Vec<Waiter>>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        WaitQueue {
+            waiters: spin::Mutex::new(Vec::new()),
+        }
+    }
+
+    /// 把当前线程放到队尾, 阻塞到被notify唤醒
+    pub fn wait(&self) {
+        self.wait_unless(|| false);
+    }
+
+    /// 在持有队列的锁时调用ready, 返回true时不等待, 否则与wait相同
+    /// ready与notify互斥, 所以在ready之后改变条件并notify的线程一定能看到这个等待者
+    /// 返回是否等待过
+    pub fn wait_unless(&self, ready: impl FnOnce() -> bool) -> bool {
+        use x86_64::instructions::interrupts;
+
+        let id = thread::current().expect("wait queue used before thread::init");
+        let notified = AtomicBool::new(false);
+        // 从排队到park一直关闭中断, 检查标志之后不会被抢占, park时中断由切换到的线程重新打开
+        interrupts::without_interrupts(|| {
+            {
+                let mut waiters = self.waiters.lock();
+                if ready() {
+                    return false;
+                }
+                waiters.push(Waiter {
+                    id,
+                    notified: &notified,
+                });
+            }
+            // 释放队列的锁之后才能park, 之前留下的unpark会让park立即返回, 所以要重新检查标志
+            while !notified.load(Ordering::Acquire) {
+                thread::park();
+            }
+            true
+        })
+    }
+
+    /// 唤醒队首的线程, 返回是否有等待的线程
+    pub fn notify_one(&self) -> bool {
+        self.notify_one_or(|| {})
+    }
+
+    /// 唤醒队首的线程, 没有等待的线程时在持有队列的锁时调用otherwise
+    /// 用于把锁或许可直接交给等待者: 有等待者时不释放, 没有时才释放
+    pub fn notify_one_or(&self, otherwise: impl FnOnce()) -> bool {
+        use x86_64::instructions::interrupts;
+
+        interrupts::without_interrupts(|| {
+            let mut waiters = self.waiters.lock();
+            if waiters.is_empty() {
+                otherwise();
+                return false;
+            }
+            let waiter = waiters.remove(0);
+            Self::wake(waiter);
+            true
+        })
+    }
+
+    /// 按排队的顺序唤醒所有等待的线程, 返回唤醒的线程数
+    pub fn notify_all(&self) -> usize {
+        use x86_64::instructions::interrupts;
+
+        interrupts::without_interrupts(|| {
+            let mut waiters = self.waiters.lock();
+            let count = waiters.len();
+            for waiter in waiters.drain(..) {
+                Self::wake(waiter);
+            }
+            count
+        })
+    }
+
+    /// 等待的线程数
+    pub fn len(&self) -> usize {
+        use x86_64::instructions::interrupts;
+
+        interrupts::without_interrupts(|| self.waiters.lock().len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // 设置标志之后等待者随时可能返回, 之后不能再访问notified
+    fn wake(waiter: Waiter) {
+        unsafe { (*waiter.notified).store(true, Ordering::Release) };
+        thread::unpark(waiter.id);
+    }
+}
+
+impl Default for WaitQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
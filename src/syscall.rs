@@ -0,0 +1,371 @@
+// 系统调用
+// 用户态通过syscall指令进入内核: cpu从LSTAR读取入口地址, 从STAR读取内核的代码段及栈段,
+// 把返回地址存入rcx, RFLAGS存入r11, 再按SFMASK清除RFLAGS中的位(这里清除IF, 进入内核时关中断)
+// syscall不会切换栈, 入口代码先把用户栈保存起来, 换成gdt::set_kernel_stack设置的内核栈,
+// 再把参数寄存器保存成SyscallFrame交给dispatch, 返回后恢复寄存器并通过sysretq回到用户态
+// 调用约定与linux相同: rax为调用号, 参数依次为rdi, rsi, rdx, r10, r8, r9, 返回值在rax中,
+// 除了rax, rcx及r11, 其他寄存器都会被保留, 出错时返回负的错误码
+// 另外提供int 0x80作为调试用的入口, 调用约定相同, 通过iretq返回
+
+use crate::memory::address_space::{self, AddressSpaceError};
+use crate::memory::user;
+use crate::{gdt, print, time};
+use core::arch::global_asm;
+use spin::Mutex;
+use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+/// int 0x80入口的中断号
+pub const SYSCALL_VECTOR: usize = 0x80;
+
+/// 向控制台写入: write(buf, len), 返回写入的字节数
+pub const SYS_WRITE: u64 = 0;
+/// 结束当前程序: exit(code), 不返回
+pub const SYS_EXIT: u64 = 1;
+/// 休眠: sleep(ms)
+pub const SYS_SLEEP: u64 = 2;
+/// 启动以来的毫秒数: time()
+pub const SYS_TIME: u64 = 3;
+/// 映射清零的匿名内存: mmap(addr, len, prot), 返回addr
+pub const SYS_MMAP: u64 = 4;
+/// 取消映射: munmap(addr, len)
+pub const SYS_MUNMAP: u64 = 5;
+
+/// 一次mmap最多映射的页数, 防止一次调用耗尽物理帧
+pub const MMAP_MAX_PAGES: u64 = 1024;
+
+/// mmap的权限
+pub const PROT_READ: u64 = 1;
+pub const PROT_WRITE: u64 = 2;
+pub const PROT_EXEC: u64 = 4;
+
+/// 系统调用的错误, 返回给用户态的是负的错误码(与linux的errno相同)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyscallError {
+    /// 没有足够的内存
+    OutOfMemory = 12,
+    /// 用户指针无效
+    BadAddress = 14,
+    /// 参数无效
+    Invalid = 22,
+    /// 没有这个系统调用
+    NoSuchCall = 38,
+}
+
+impl SyscallError {
+    /// 返回给用户态的值
+    pub fn to_return_value(self) -> u64 {
+        (-(self as i64)) as u64
+    }
+
+    /// 从返回值中解析错误, 不是错误时返回None
+    pub fn from_return_value(value: u64) -> Option<Self> {
+        // i64::MIN取负会溢出, 它不是错误码
+        match (value as i64).checked_neg()? {
+            12 => Some(SyscallError::OutOfMemory),
+            14 => Some(SyscallError::BadAddress),
+            22 => Some(SyscallError::Invalid),
+            38 => Some(SyscallError::NoSuchCall),
+            _ => None,
+        }
+    }
+}
+
+/// 入口代码保存在内核栈上的寄存器, 顺序与压栈的顺序相反
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct SyscallFrame {
+    /// 调用号(rax)
+    pub number: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub r10: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+}
+
+impl SyscallFrame {
+    /// 第index个参数
+    pub fn arg(&self, index: usize) -> u64 {
+        match index {
+            0 => self.rdi,
+            1 => self.rsi,
+            2 => self.rdx,
+            3 => self.r10,
+            4 => self.r8,
+            5 => self.r9,
+            _ => 0,
+        }
+    }
+}
+
+type SyscallFn = fn(&SyscallFrame) -> Result<u64, SyscallError>;
+
+// 系统调用表, 下标为调用号
+static SYSCALLS: [SyscallFn; 6] = [
+    sys_write, sys_exit, sys_sleep, sys_time, sys_mmap, sys_munmap,
+];
+
+// exit调用的处理函数, 还没有进程时由调用者决定exit之后做什么
+static EXIT_HANDLER: Mutex<Option<fn(u64) -> !>> = Mutex::new(None);
+
+/// 设置exit调用的处理函数, 为None时打印退出码并停机
+pub fn set_exit_handler(handler: Option<fn(u64) -> !>) {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| *EXIT_HANDLER.lock() = handler);
+}
+
+/// 设置syscall相关的MSR, 需要在gdt::init之后调用
+pub fn init() {
+    use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+    use x86_64::registers::rflags::RFlags;
+
+    let selectors = gdt::selectors();
+    Star::write(
+        selectors.user_code_selector,
+        selectors.user_data_selector,
+        selectors.code_selector,
+        selectors.data_selector,
+    )
+    .expect("invalid segment layout for syscall");
+    LStar::write(syscall_entry());
+    // 进入内核时关中断, 清除方向标志, 并清除AC使SMAP生效
+    SFMask::write(
+        RFlags::INTERRUPT_FLAG
+            | RFlags::TRAP_FLAG
+            | RFlags::DIRECTION_FLAG
+            | RFlags::ALIGNMENT_CHECK,
+    );
+    unsafe { Efer::update(|flags| flags.insert(EferFlags::SYSTEM_CALL_EXTENSIONS)) };
+}
+
+/// 根据调用号执行系统调用, 由入口代码调用, 返回值放入rax
+#[no_mangle]
+extern "C" fn syscall_dispatch(frame: &SyscallFrame) -> u64 {
+    use x86_64::instructions::interrupts;
+
+    let result = match SYSCALLS.get(frame.number as usize) {
+        Some(syscall) => syscall(frame),
+        None => Err(SyscallError::NoSuchCall),
+    };
+    // 入口代码恢复用户栈时不能被中断打断, sleep等调用可能打开了中断
+    interrupts::disable();
+    match result {
+        Ok(value) => value,
+        Err(error) => error.to_return_value(),
+    }
+}
+
+// 用户态写入的内容按块复制到内核中, 一个utf-8字符可能被分在两块中, 剩下的部分留到下一块
+fn sys_write(frame: &SyscallFrame) -> Result<u64, SyscallError> {
+    let (buf, len) = (VirtAddr::try_new(frame.arg(0)), frame.arg(1));
+    let buf = buf.map_err(|_| SyscallError::BadAddress)?;
+    user::check_user_range(buf, len, false).map_err(|_| SyscallError::BadAddress)?;
+
+    let mut chunk = [0u8; 128];
+    let mut pending = 0;
+    let mut done = 0;
+    while done < len {
+        let n = ((len - done) as usize).min(chunk.len() - pending);
+        user::copy_from_user(&mut chunk[pending..pending + n], buf + done)
+            .map_err(|_| SyscallError::BadAddress)?;
+        done += n as u64;
+        let end = pending + n;
+        let valid = match core::str::from_utf8(&chunk[..end]) {
+            Ok(s) => s.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => return Err(SyscallError::Invalid),
+        };
+        print!("{}", unsafe {
+            core::str::from_utf8_unchecked(&chunk[..valid])
+        });
+        chunk.copy_within(valid..end, 0);
+        pending = end - valid;
+    }
+    if pending > 0 {
+        return Err(SyscallError::Invalid);
+    }
+    Ok(len)
+}
+
+// 没有设置处理函数时释放程序的地址空间, 有线程时只结束当前线程, 否则停机
+fn sys_exit(frame: &SyscallFrame) -> Result<u64, SyscallError> {
+    use crate::{hlt_loop, println, thread};
+    use core::mem::ManuallyDrop;
+    use x86_64::instructions::interrupts;
+
+    let code = frame.arg(0);
+    let handler = interrupts::without_interrupts(|| *EXIT_HANDLER.lock());
+    if let Some(handler) = handler {
+        handler(code);
+    }
+    // 程序的地址空间属于程序本身(见loader::Program::run), 释放时会先切换回内核的页表
+    match address_space::current() {
+        Some(space) => drop(ManuallyDrop::into_inner(space)),
+        None => address_space::activate_kernel(),
+    }
+    println!("user program exited with code {}", code);
+    if thread::current().is_some() {
+        thread::exit();
+    }
+    interrupts::enable();
+    hlt_loop();
+}
+
+fn sys_sleep(frame: &SyscallFrame) -> Result<u64, SyscallError> {
+    time::sleep_ms(frame.arg(0));
+    Ok(0)
+}
+
+fn sys_time(_frame: &SyscallFrame) -> Result<u64, SyscallError> {
+    Ok(time::uptime_ms())
+}
+
+// 只支持固定地址的映射, addr需要按页对齐, 可写与可执行不能同时设置(W^X)
+fn sys_mmap(frame: &SyscallFrame) -> Result<u64, SyscallError> {
+    let (start, pages) = user_pages(frame.arg(0), frame.arg(1))?;
+    if pages > MMAP_MAX_PAGES {
+        return Err(SyscallError::OutOfMemory);
+    }
+    let prot = frame.arg(2);
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0
+        || prot & (PROT_WRITE | PROT_EXEC) == PROT_WRITE | PROT_EXEC
+    {
+        return Err(SyscallError::Invalid);
+    }
+    let mut flags = PageTableFlags::empty();
+    if prot & PROT_WRITE != 0 {
+        flags |= PageTableFlags::WRITABLE;
+    }
+    if prot & PROT_EXEC == 0 {
+        flags |= PageTableFlags::NO_EXECUTE;
+    }
+
+    let mut space = address_space::current().ok_or(SyscallError::Invalid)?;
+    for (i, page) in Page::range(start, start + pages).enumerate() {
+        if let Err(error) = space.map_user(page, flags) {
+            // 撤销已经映射的页
+            for page in Page::range(start, start + i as u64) {
+                let _ = space.unmap_user(page);
+            }
+            return Err(match error {
+                AddressSpaceError::OutOfFrames => SyscallError::OutOfMemory,
+                _ => SyscallError::Invalid,
+            });
+        }
+    }
+    Ok(start.start_address().as_u64())
+}
+
+// 没有映射的页会被忽略
+fn sys_munmap(frame: &SyscallFrame) -> Result<u64, SyscallError> {
+    let (start, pages) = user_pages(frame.arg(0), frame.arg(1))?;
+    let mut space = address_space::current().ok_or(SyscallError::Invalid)?;
+    for page in Page::range(start, start + pages) {
+        let _ = space.unmap_user(page);
+    }
+    Ok(0)
+}
+
+// 检查[addr, addr + len)是用户空间中按页对齐的范围, 返回起始页及页数
+fn user_pages(addr: u64, len: u64) -> Result<(Page<Size4KiB>, u64), SyscallError> {
+    let start = VirtAddr::try_new(addr).map_err(|_| SyscallError::Invalid)?;
+    let start = Page::from_start_address(start).map_err(|_| SyscallError::Invalid)?;
+    let pages = len
+        .checked_add(Page::<Size4KiB>::SIZE - 1)
+        .ok_or(SyscallError::Invalid)?
+        / Page::<Size4KiB>::SIZE;
+    let end = addr
+        .checked_add(pages * Page::<Size4KiB>::SIZE)
+        .ok_or(SyscallError::Invalid)?;
+    if pages == 0
+        || !address_space::is_user_address(start.start_address())
+        || end > address_space::USER_TOP
+    {
+        return Err(SyscallError::Invalid);
+    }
+    Ok((start, pages))
+}
+
+/// syscall指令的入口地址
+pub fn syscall_entry() -> VirtAddr {
+    extern "C" {
+        fn syscall_entry();
+    }
+    VirtAddr::from_ptr(syscall_entry as *const ())
+}
+
+/// int 0x80的入口地址
+pub fn int80_entry() -> VirtAddr {
+    extern "C" {
+        fn int80_entry();
+    }
+    VirtAddr::from_ptr(int80_entry as *const ())
+}
+
+// 用户栈的地址只在入口代码中暂存一下, 此时中断是关闭的
+#[no_mangle]
+static mut SYSCALL_USER_STACK: u64 = 0;
+
+// syscall入口: 切换到内核栈, 依次压入用户栈, 返回地址(rcx), RFLAGS(r11)及参数寄存器, 最后是调用号,
+// 一共10个寄存器, 内核栈按16字节对齐, 所以调用dispatch时栈也是对齐的
+// 返回的rcx来自syscall指令本身, 用户空间在USER_TOP以下, 所以总是规范地址, sysretq不会在内核态产生#GP
+global_asm!(
+    ".global syscall_entry",
+    "syscall_entry:",
+    "mov [rip + SYSCALL_USER_STACK], rsp",
+    "mov rsp, [rip + SYSCALL_KERNEL_STACK]",
+    "push qword ptr [rip + SYSCALL_USER_STACK]",
+    "push rcx",
+    "push r11",
+    "push rdi",
+    "push rsi",
+    "push rdx",
+    "push r10",
+    "push r8",
+    "push r9",
+    "push rax",
+    "mov rdi, rsp",
+    "call syscall_dispatch",
+    "add rsp, 8",
+    "pop r9",
+    "pop r8",
+    "pop r10",
+    "pop rdx",
+    "pop rsi",
+    "pop rdi",
+    "pop r11",
+    "pop rcx",
+    "pop rsp",
+    "sysretq",
+);
+
+// int 0x80入口: cpu已经切换到RSP0并压入了5个值的中断栈帧(此时栈不是16字节对齐的),
+// 再压入9个寄存器后对齐, rcx和r11也要保存, 因为int 0x80只改变rax
+global_asm!(
+    ".global int80_entry",
+    "int80_entry:",
+    "push rcx",
+    "push r11",
+    "push rdi",
+    "push rsi",
+    "push rdx",
+    "push r10",
+    "push r8",
+    "push r9",
+    "push rax",
+    "mov rdi, rsp",
+    "call syscall_dispatch",
+    "add rsp, 8",
+    "pop r9",
+    "pop r8",
+    "pop r10",
+    "pop rdx",
+    "pop rsi",
+    "pop rdi",
+    "pop r11",
+    "pop rcx",
+    "iretq",
+);
@@ -0,0 +1,48 @@
+// 基于async/await的协作式任务
+// 每个任务是一个Future, 由executor轮询, 返回Pending时让出cpu, 等待waker把它重新放入就绪队列
+// 与thread中的线程不同, 任务不会被抢占, 也不需要自己的栈
+// keyboard及timer提供由中断唤醒的Stream及Future
+
+pub mod executor;
+pub mod keyboard;
+pub mod timer;
+
+use alloc::boxed::Box;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll};
+
+/// 任务id, 用于executor中查找任务及其waker
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// 一个没有返回值的Future, 固定在堆上, 可以在轮询之间移动
+pub struct Task {
+    id: TaskId,
+    future: Pin<Box<dyn Future<Output = ()>>>,
+}
+
+impl Task {
+    pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
+        Task {
+            id: TaskId::new(),
+            future: Box::pin(future),
+        }
+    }
+
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    fn poll(&mut self, context: &mut Context) -> Poll<()> {
+        self.future.as_mut().poll(context)
+    }
+}
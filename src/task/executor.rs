@@ -0,0 +1,141 @@
+// 基于waker的executor
+// 只轮询被唤醒的任务: waker把任务id放入就绪队列, 就绪队列是无锁的, 所以可以在中断中唤醒任务
+// 没有就绪的任务时用hlt等待下一次中断
+
+use super::{Task, TaskId};
+use crate::println;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use core::task::{Context, Poll, Waker};
+use crossbeam_queue::ArrayQueue;
+
+// 就绪队列的容量, 同时存在的任务不能超过这个数
+const MAX_TASKS: usize = 100;
+
+pub struct Executor {
+    tasks: BTreeMap<TaskId, Task>,
+    ready: Arc<ArrayQueue<TaskId>>,
+    // 每个任务的waker只创建一次
+    waker_cache: BTreeMap<TaskId, Waker>,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Executor {
+            tasks: BTreeMap::new(),
+            ready: Arc::new(ArrayQueue::new(MAX_TASKS)),
+            waker_cache: BTreeMap::new(),
+        }
+    }
+
+    /// 添加任务, 第一次运行时会轮询它
+    pub fn spawn(&mut self, task: Task) {
+        let id = task.id;
+        if self.tasks.insert(id, task).is_some() {
+            panic!("task with same ID already in tasks");
+        }
+        self.ready.push(id).expect("ready queue full");
+    }
+
+    /// 还没有完成的任务数
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// 一直运行, 没有就绪的任务时休眠
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.run_ready_tasks();
+            self.sleep_if_idle();
+        }
+    }
+
+    /// 运行到所有任务都完成
+    pub fn run_until_empty(&mut self) {
+        loop {
+            self.run_ready_tasks();
+            if self.tasks.is_empty() {
+                return;
+            }
+            self.sleep_if_idle();
+        }
+    }
+
+    /// 轮询所有就绪的任务, 完成的任务被删除
+    pub fn run_ready_tasks(&mut self) {
+        // 解构self, 闭包中借用waker_cache时不会与tasks冲突
+        let Self {
+            tasks,
+            ready,
+            waker_cache,
+        } = self;
+
+        while let Some(id) = ready.pop() {
+            let task = match tasks.get_mut(&id) {
+                Some(task) => task,
+                // 任务已经完成, 但还有waker唤醒它
+                None => continue,
+            };
+            let waker = waker_cache
+                .entry(id)
+                .or_insert_with(|| TaskWaker::new(id, ready.clone()));
+            let mut context = Context::from_waker(waker);
+            if let Poll::Ready(()) = task.poll(&mut context) {
+                tasks.remove(&id);
+                waker_cache.remove(&id);
+            }
+        }
+    }
+
+    // 检查就绪队列与hlt之间不能被中断, 否则中断中唤醒的任务要等到下一次中断才会运行
+    fn sleep_if_idle(&self) {
+        use x86_64::instructions::interrupts;
+
+        interrupts::disable();
+        if self.ready.is_empty() {
+            interrupts::enable_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 唤醒时把任务id放入就绪队列, 不分配内存, 可以在中断中使用
+struct TaskWaker {
+    id: TaskId,
+    ready: Arc<ArrayQueue<TaskId>>,
+}
+
+impl TaskWaker {
+    fn new(id: TaskId, ready: Arc<ArrayQueue<TaskId>>) -> Waker {
+        Waker::from(Arc::new(TaskWaker { id, ready }))
+    }
+
+    fn wake_task(&self) {
+        // 可能在中断中调用, 队列满时只能丢弃这次唤醒
+        if self.ready.push(self.id).is_err() {
+            println!("WARNING: task queue full; dropping wakeup");
+        }
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
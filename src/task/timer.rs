@@ -0,0 +1,125 @@
+// 定时器
+// Timer在指定的时钟中断次数之后完成, 还没到时间时把waker登记到TIMERS中, 由时钟中断唤醒
+// 登记及唤醒都在关闭中断时进行, 时钟中断中获取TIMERS的锁不会死锁
+// 时钟中断只用wake_by_ref唤醒并标记, 不删除登记项: 丢弃waker可能释放堆内存, 登记项由Timer完成或被丢弃时删除
+
+use crate::time;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::mem;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use spin::Mutex;
+
+// 等待中的定时器登记的waker
+struct Entry {
+    id: u64,
+    deadline: u64,
+    waker: Waker,
+    // 已经被时钟中断唤醒过
+    woken: bool,
+}
+
+static TIMERS: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+
+/// 到期时完成的Future, 被丢弃时取消登记
+#[derive(Debug)]
+pub struct Timer {
+    // 用来在TIMERS中找到自己的登记项
+    id: u64,
+    deadline: u64,
+}
+
+impl Timer {
+    /// ticks次时钟中断之后到期
+    pub fn after_ticks(ticks: u64) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Timer {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            deadline: time::ticks().saturating_add(ticks),
+        }
+    }
+
+    /// 至少ms毫秒之后到期
+    pub fn after_ms(ms: u64) -> Self {
+        Self::after_ticks(time::ms_to_ticks(ms))
+    }
+
+    /// 到期的时钟中断次数
+    pub fn deadline(&self) -> u64 {
+        self.deadline
+    }
+
+    // 删除登记项, 返回其中的waker, 由调用者在释放锁之后丢弃
+    fn deregister(&self) -> Option<Waker> {
+        use x86_64::instructions::interrupts;
+
+        interrupts::without_interrupts(|| {
+            let mut timers = TIMERS.lock();
+            let i = timers.iter().position(|entry| entry.id == self.id)?;
+            Some(timers.swap_remove(i).waker)
+        })
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        drop(self.deregister());
+    }
+}
+
+/// 登记了waker但还没有完成或被丢弃的定时器数
+pub fn pending() -> usize {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| TIMERS.lock().len())
+}
+
+impl Future for Timer {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        use x86_64::instructions::interrupts;
+
+        // 检查与登记之间不能有时钟中断, 否则这次唤醒会丢失
+        // 不再需要的waker(完成时的登记项或被替换的旧waker)在释放锁之后丢弃
+        let (poll, unused) = interrupts::without_interrupts(|| {
+            let mut timers = TIMERS.lock();
+            let position = timers.iter().position(|entry| entry.id == self.id);
+            if time::ticks() >= self.deadline {
+                let unused = position.map(|i| timers.swap_remove(i).waker);
+                return (Poll::Ready(()), unused);
+            }
+            let unused = match position {
+                Some(i) if timers[i].waker.will_wake(cx.waker()) => None,
+                Some(i) => Some(mem::replace(&mut timers[i].waker, cx.waker().clone())),
+                None => {
+                    timers.push(Entry {
+                        id: self.id,
+                        deadline: self.deadline,
+                        waker: cx.waker().clone(),
+                        woken: false,
+                    });
+                    None
+                }
+            };
+            (Poll::Pending, unused)
+        });
+        drop(unused);
+        poll
+    }
+}
+
+/// 由时钟中断调用, 唤醒所有到期的定时器
+/// 登记项留在TIMERS中, 中断中不丢弃waker, 也就不会释放内存
+pub(crate) fn wake_expired() {
+    let now = time::ticks();
+    let mut timers = TIMERS.lock();
+    for entry in timers.iter_mut() {
+        if entry.deadline <= now && !entry.woken {
+            entry.woken = true;
+            entry.waker.wake_by_ref();
+        }
+    }
+}
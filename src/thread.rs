@@ -0,0 +1,769 @@
+// 内核线程
+// 每个线程有自己的内核栈(带保护页), 切换出去时所有通用寄存器都保存在自己的栈上, Thread中只记录栈指针
+// 时钟中断的入口timer_entry先保存被打断的线程的寄存器, 再由schedule决定是否抢占并换成下一个线程的栈,
+// 最后从新的栈上恢复寄存器并iretq, 所以线程是被抢占的, 不需要主动让出cpu
+// 新线程的栈上预先构造好同样的布局, 第一次被切换到时iretq到thread_start
+// 调用init的执行流(启动栈)成为0号线程
+//
+// 就绪的线程放在运行队列中, 由调度策略(Scheduler)决定下一个运行的线程, 策略通过cargo feature在编译时选择
+// 阻塞, 休眠及已结束的线程不在运行队列中, 分别放在blocked, sleeping及finished中
+// 没有就绪的线程时运行空闲线程, 它不在任何队列中, 也不计入线程数
+//
+// 堆, memory::MAPPER及FRAME_ALLOCATOR的锁都只在关闭中断时持有, 时钟中断不会打断持有它们的代码,
+// 被抢占的线程也不会带着这些锁被换下去. 时钟中断中仍然不分配或释放堆内存, 因为分配可能失败而中断中无法报告:
+// 各个队列的容量在spawn时按线程总数预留, 结束的线程由spawn或reap在线程上下文中释放
+
+pub mod priority;
+pub mod round_robin;
+
+use crate::gdt;
+use crate::memory::stack::{self, KernelStack};
+use crate::memory::vma::VmError;
+use crate::time;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::arch::{asm, global_asm};
+use core::mem;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::VirtAddr;
+
+// 调度策略通过cargo feature在编译时选择, 默认为sched-round-robin
+// 比如: cargo test --no-default-features --features alloc-fixed-block,sched-priority
+#[cfg(not(any(feature = "sched-round-robin", feature = "sched-priority")))]
+compile_error!("one of the `sched-*` features must be enabled to select the scheduler");
+
+#[cfg(all(feature = "sched-round-robin", feature = "sched-priority"))]
+compile_error!("the `sched-*` features are mutually exclusive, enable only one of them");
+
+// 轮转调度, 所有线程轮流运行一个时间片
+#[cfg(feature = "sched-round-robin")]
+type Policy = round_robin::RoundRobin;
+
+// 固定优先级调度, 总是运行优先级最高的线程, 同一优先级的线程之间轮转
+#[cfg(feature = "sched-priority")]
+type Policy = priority::FixedPriority;
+
+/// 主动让出cpu的中断号
+pub const YIELD_VECTOR: usize = 0x81;
+
+/// 线程内核栈的页数
+pub const THREAD_STACK_PAGES: u64 = 8;
+
+/// 时间片的长度(时钟中断次数), 用完后才会被同一优先级的线程抢占
+pub const TIME_SLICE: u64 = 1;
+
+/// 优先级的个数, 优先级为0..PRIORITY_LEVELS, 数字越大优先级越高
+pub const PRIORITY_LEVELS: usize = 8;
+
+/// spawn及0号线程使用的优先级
+pub const DEFAULT_PRIORITY: u8 = 3;
+
+// 中断时cpu压入的RFLAGS: 只开启中断(IF), 第1位保留为1
+const INITIAL_RFLAGS: u64 = 0x202;
+
+/// 线程id, 0号线程是调用init的执行流
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ThreadId(u64);
+
+impl ThreadId {
+    fn new() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        ThreadId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadState {
+    /// 在运行队列中等待运行
+    Ready,
+    /// 正在运行
+    Running,
+    /// 等待被unpark或等待的线程结束
+    Blocked,
+    /// 休眠到指定的时钟中断次数
+    Sleeping,
+    /// 已经结束, 等待释放栈
+    Exited,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadError {
+    /// 还没有调用init
+    Unavailable,
+    /// 分配内核栈失败
+    Stack(VmError),
+    /// 优先级不小于PRIORITY_LEVELS
+    InvalidPriority,
+    /// 等待自己结束
+    Deadlock,
+}
+
+impl From<VmError> for ThreadError {
+    fn from(error: VmError) -> Self {
+        ThreadError::Stack(error)
+    }
+}
+
+pub struct Thread {
+    id: ThreadId,
+    state: ThreadState,
+    priority: u8,
+    // 本次被调度以来经过的时钟中断次数
+    ticks: u64,
+    // 休眠时, 到这个时钟中断次数时唤醒
+    wake_at: u64,
+    // 阻塞在join中时, 等待的线程
+    joining: Option<ThreadId>,
+    // 在park之前已经被unpark过, 下一次park直接返回
+    unparked: bool,
+    // 0号线程使用启动栈, 没有自己分配的栈
+    stack: Option<KernelStack>,
+    // 用户态进入内核时使用的栈顶, 切换到该线程时写入TSS, 切换出去时从TSS取回(线程可能调用过set_kernel_stack)
+    kernel_stack: VirtAddr,
+    // 切换出去时保存的栈指针, 指向栈上保存的寄存器
+    stack_pointer: u64,
+}
+
+impl Thread {
+    fn new(priority: u8, stack: Option<KernelStack>, kernel_stack: VirtAddr) -> Self {
+        Thread {
+            id: ThreadId::new(),
+            state: ThreadState::Ready,
+            priority,
+            ticks: 0,
+            wake_at: 0,
+            joining: None,
+            unparked: false,
+            stack,
+            kernel_stack,
+            stack_pointer: 0,
+        }
+    }
+
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
+
+    pub fn state(&self) -> ThreadState {
+        self.state
+    }
+
+    pub fn priority(&self) -> u8 {
+        self.priority
+    }
+
+    /// 本次被调度以来经过的时钟中断次数
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+}
+
+/// 调度策略, 管理运行队列中就绪的线程
+/// 除new及reserve外的方法都可能在时钟中断中调用, 不能分配或释放内存
+pub trait Scheduler {
+    fn new() -> Self
+    where
+        Self: Sized;
+
+    /// 把就绪的线程放入运行队列
+    fn push(&mut self, thread: Box<Thread>);
+
+    /// 取出下一个要运行的线程
+    fn pop(&mut self) -> Option<Box<Thread>>;
+
+    /// 时钟中断时调用, 返回是否要抢占正在运行的线程
+    fn should_preempt(&self, current: &Thread) -> bool;
+
+    /// 运行队列中的线程数
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 运行队列中是否有id线程
+    fn contains(&self, id: ThreadId) -> bool;
+
+    /// 运行队列中的id线程, 用于修改还在排队的线程(比如unpark)
+    fn get_mut(&mut self, id: ThreadId) -> Option<&mut Thread>;
+
+    /// 预留容量, 之后运行队列中最多有threads个线程时, push不需要分配内存
+    fn reserve(&mut self, threads: usize);
+}
+
+// 被打断时入口代码保存在栈上的内容, 从低地址到高地址
+// 前15项由入口代码压栈, 之后是cpu压入的中断栈帧
+#[repr(C)]
+struct SavedContext {
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    r11: u64,
+    r10: u64,
+    r9: u64,
+    r8: u64,
+    rbp: u64,
+    rdi: u64,
+    rsi: u64,
+    rdx: u64,
+    rcx: u64,
+    rbx: u64,
+    rax: u64,
+    rip: u64,
+    cs: u64,
+    rflags: u64,
+    rsp: u64,
+    ss: u64,
+}
+
+// 所有线程, current之外的线程按照状态放在不同的队列中
+struct ThreadTable {
+    current: Box<Thread>,
+    // current是空闲线程时为None
+    idle: Option<Box<Thread>>,
+    ready: Policy,
+    blocked: Vec<Box<Thread>>,
+    sleeping: Vec<Box<Thread>>,
+    finished: Vec<Box<Thread>>,
+}
+
+impl ThreadTable {
+    // 时钟中断: 唤醒到时间的休眠线程, 当前线程用完时间片或有更高优先级的线程就绪时切换
+    fn tick(&mut self, stack_pointer: u64) -> u64 {
+        let now = time::ticks();
+        let mut i = 0;
+        while i < self.sleeping.len() {
+            if self.sleeping[i].wake_at <= now {
+                let thread = self.sleeping.remove(i);
+                self.make_ready(thread);
+            } else {
+                i += 1;
+            }
+        }
+
+        self.current.ticks += 1;
+        let preempt = match self.idle {
+            None => !self.ready.is_empty(),
+            Some(_) => self.ready.should_preempt(&self.current),
+        };
+        if preempt {
+            self.switch(stack_pointer)
+        } else {
+            stack_pointer
+        }
+    }
+
+    // 保存当前线程的栈指针, 按状态放到对应的队列中, 换成运行队列中的下一个线程, 返回它的栈指针
+    // 正在运行的线程会重新放入运行队列, 调度策略可能再次选中它
+    fn switch(&mut self, stack_pointer: u64) -> u64 {
+        self.current.stack_pointer = stack_pointer;
+        self.current.kernel_stack = gdt::kernel_stack();
+        // 先换成空闲线程, 运行队列为空时就运行它
+        if let Some(idle) = self.idle.take() {
+            let mut previous = mem::replace(&mut self.current, idle);
+            // 容量在spawn时已经预留, 这里不会分配内存
+            match previous.state {
+                ThreadState::Ready | ThreadState::Running => self.make_ready(previous),
+                ThreadState::Blocked => self.blocked.push(previous),
+                ThreadState::Sleeping => self.sleeping.push(previous),
+                ThreadState::Exited => {
+                    previous.joining = None;
+                    self.finished.push(previous);
+                }
+            }
+        }
+        if let Some(next) = self.ready.pop() {
+            let idle = mem::replace(&mut self.current, next);
+            self.idle = Some(idle);
+        }
+        self.current.state = ThreadState::Running;
+        self.current.ticks = 0;
+        gdt::set_kernel_stack(self.current.kernel_stack);
+        self.current.stack_pointer
+    }
+
+    fn make_ready(&mut self, mut thread: Box<Thread>) {
+        thread.state = ThreadState::Ready;
+        self.ready.push(thread);
+    }
+
+    // 唤醒阻塞的线程中第一个满足条件的, 返回是否找到
+    fn wake_blocked(&mut self, condition: impl Fn(&Thread) -> bool) -> bool {
+        match self.blocked.iter().position(|thread| condition(thread)) {
+            Some(i) => {
+                let mut thread = self.blocked.remove(i);
+                thread.joining = None;
+                self.make_ready(thread);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // 还没有结束的线程中是否有id线程
+    fn is_alive(&self, id: ThreadId) -> bool {
+        (self.current.id == id && self.current.state != ThreadState::Exited)
+            || self.ready.contains(id)
+            || self.blocked.iter().any(|thread| thread.id == id)
+            || self.sleeping.iter().any(|thread| thread.id == id)
+    }
+
+    // 还没有结束的id线程, 可能是当前线程或在任何一个队列中
+    fn alive_mut(&mut self, id: ThreadId) -> Option<&mut Thread> {
+        if self.current.id == id {
+            return match self.current.state {
+                ThreadState::Exited => None,
+                _ => Some(&mut self.current),
+            };
+        }
+        if let Some(thread) = self.ready.get_mut(id) {
+            return Some(thread);
+        }
+        self.blocked
+            .iter_mut()
+            .chain(self.sleeping.iter_mut())
+            .find(|thread| thread.id == id)
+            .map(|thread| &mut **thread)
+    }
+
+    // 不包括空闲线程
+    fn count(&self) -> usize {
+        let current = if self.idle.is_some() { 1 } else { 0 };
+        current + self.ready.len() + self.blocked.len() + self.sleeping.len() + self.finished.len()
+    }
+
+    // 按线程总数预留所有队列的容量
+    fn reserve(&mut self, threads: usize) {
+        self.ready.reserve(threads);
+        for queue in [&mut self.blocked, &mut self.sleeping, &mut self.finished] {
+            queue.reserve(threads.saturating_sub(queue.len()));
+        }
+    }
+}
+
+static THREADS: Mutex<Option<ThreadTable>> = Mutex::new(None);
+
+/// 把当前的执行流作为0号线程, 并创建空闲线程, 需要在memory::install之后调用
+pub fn init() -> Result<(), ThreadError> {
+    use x86_64::instructions::interrupts;
+
+    let mut main = Box::new(Thread::new(DEFAULT_PRIORITY, None, gdt::kernel_stack()));
+    main.id = ThreadId(0);
+    main.state = ThreadState::Running;
+    let idle = new_thread(idle, 0)?;
+    interrupts::without_interrupts(|| {
+        *THREADS.lock() = Some(ThreadTable {
+            current: main,
+            idle: Some(idle),
+            ready: Policy::new(),
+            blocked: Vec::new(),
+            sleeping: Vec::new(),
+            finished: Vec::new(),
+        });
+    });
+    Ok(())
+}
+
+/// 以默认优先级创建一个执行entry的内核线程, entry返回后线程结束
+pub fn spawn(entry: fn()) -> Result<ThreadId, ThreadError> {
+    spawn_with_priority(entry, DEFAULT_PRIORITY)
+}
+
+/// 以指定的优先级创建一个执行entry的内核线程, 放入运行队列
+/// 新线程不会立即抢占当前线程, 最早在下一次时钟中断时运行
+pub fn spawn_with_priority(entry: fn(), priority: u8) -> Result<ThreadId, ThreadError> {
+    use x86_64::instructions::interrupts;
+
+    if priority as usize >= PRIORITY_LEVELS {
+        return Err(ThreadError::InvalidPriority);
+    }
+    reap();
+    let thread = new_thread(entry, priority)?;
+    let id = thread.id;
+
+    let spawned = interrupts::without_interrupts(|| {
+        let mut threads = THREADS.lock();
+        let threads = match threads.as_mut() {
+            Some(threads) => threads,
+            None => return Err(thread),
+        };
+        // 时钟中断中切换线程时不能分配内存, 按线程总数预留各个队列的容量
+        let count = threads.count() + 1;
+        threads.reserve(count);
+        threads.make_ready(thread);
+        Ok(())
+    });
+    match spawned {
+        Ok(()) => Ok(id),
+        Err(thread) => {
+            let _ = unsafe { stack::free_stack(thread.stack.unwrap()) };
+            Err(ThreadError::Unavailable)
+        }
+    }
+}
+
+// 分配栈并在栈上构造第一次运行时的上下文
+fn new_thread(entry: fn(), priority: u8) -> Result<Box<Thread>, ThreadError> {
+    let stack = stack::alloc_stack("thread", THREAD_STACK_PAGES)?;
+    let mut thread = Box::new(Thread::new(priority, Some(stack), stack.top));
+    thread.stack_pointer = unsafe { prepare_stack(&stack, entry) };
+    Ok(thread)
+}
+
+// 空闲线程, 等待下一次中断
+fn idle() {
+    crate::hlt_loop();
+}
+
+/// 释放已经结束的线程的栈, 返回释放的线程数
+pub fn reap() -> usize {
+    use x86_64::instructions::interrupts;
+
+    let mut reaped = 0;
+    loop {
+        // 一次取出一个, 不改变finished的容量
+        let thread = interrupts::without_interrupts(|| {
+            THREADS
+                .lock()
+                .as_mut()
+                .and_then(|threads| threads.finished.pop())
+        });
+        let mut thread = match thread {
+            Some(thread) => thread,
+            None => return reaped,
+        };
+        if let Some(stack) = thread.stack.take() {
+            let _ = unsafe { stack::free_stack(stack) };
+        }
+        reaped += 1;
+    }
+}
+
+/// 当前线程的id, 没有初始化时返回None
+pub fn current() -> Option<ThreadId> {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| THREADS.lock().as_ref().map(|threads| threads.current.id))
+}
+
+/// 还没有被释放的线程数, 包括0号线程及已结束但还没有reap的线程, 不包括空闲线程
+pub fn count() -> usize {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| THREADS.lock().as_ref().map_or(0, ThreadTable::count))
+}
+
+/// id线程当前的状态, 线程已经被释放或不存在时返回None
+pub fn state(id: ThreadId) -> Option<ThreadState> {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let threads = THREADS.lock();
+        let threads = threads.as_ref()?;
+        if threads.current.id == id {
+            return Some(threads.current.state);
+        }
+        if threads.ready.contains(id) {
+            return Some(ThreadState::Ready);
+        }
+        [&threads.blocked, &threads.sleeping, &threads.finished]
+            .iter()
+            .flat_map(|queue| queue.iter())
+            .find(|thread| thread.id == id)
+            .map(|thread| thread.state)
+    })
+}
+
+/// 主动让出cpu, 当前线程放回运行队列, 由调度策略选出下一个线程
+pub fn yield_now() {
+    unsafe { asm!("int 0x81") };
+}
+
+/// 休眠至少ticks次时钟中断, 期间不占用cpu
+/// 没有初始化时退化为time中的忙等
+pub fn sleep(ticks: u64) {
+    use x86_64::instructions::interrupts;
+
+    if ticks == 0 {
+        yield_now();
+        return;
+    }
+    let wake_at = time::ticks().saturating_add(ticks);
+    let parked = interrupts::without_interrupts(|| {
+        // 释放锁之后再切换, 中断关闭到切换完成, 不会错过唤醒
+        let parked = match THREADS.lock().as_mut() {
+            Some(threads) => {
+                threads.current.state = ThreadState::Sleeping;
+                threads.current.wake_at = wake_at;
+                true
+            }
+            None => false,
+        };
+        if parked {
+            yield_now();
+        }
+        parked
+    });
+    if !parked {
+        time::sleep_ms(time::ticks_to_ms(ticks));
+    }
+}
+
+/// 阻塞当前线程直到被unpark, 如果在此之前已经被unpark过则立即返回
+/// 返回时不一定是因为unpark, 调用者需要重新检查等待的条件
+pub fn park() {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let blocked = match THREADS.lock().as_mut() {
+            Some(threads) if threads.current.unparked => {
+                threads.current.unparked = false;
+                false
+            }
+            Some(threads) => {
+                threads.current.state = ThreadState::Blocked;
+                true
+            }
+            None => false,
+        };
+        if blocked {
+            yield_now();
+        }
+    });
+}
+
+/// 唤醒park中的id线程, 它还没有park时让它的下一次park立即返回
+/// 线程不存在或已经结束时返回false
+pub fn unpark(id: ThreadId) -> bool {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut threads = THREADS.lock();
+        let threads = match threads.as_mut() {
+            Some(threads) => threads,
+            None => return false,
+        };
+        if threads.wake_blocked(|thread| thread.id == id && thread.joining.is_none()) {
+            return true;
+        }
+        // 线程可能在检查等待的条件之后, park之前被抢占而放回了运行队列, 所以排队的线程也要记下unpark
+        match threads.alive_mut(id) {
+            Some(thread) => {
+                thread.unparked = true;
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+/// 等待id线程结束
+pub fn join(id: ThreadId) -> Result<(), ThreadError> {
+    use x86_64::instructions::interrupts;
+
+    loop {
+        let finished = interrupts::without_interrupts(|| {
+            let blocked = {
+                let mut threads = THREADS.lock();
+                let threads = threads.as_mut().ok_or(ThreadError::Unavailable)?;
+                if threads.current.id == id {
+                    return Err(ThreadError::Deadlock);
+                }
+                if threads.is_alive(id) {
+                    threads.current.state = ThreadState::Blocked;
+                    threads.current.joining = Some(id);
+                    true
+                } else {
+                    false
+                }
+            };
+            if blocked {
+                yield_now();
+            }
+            Ok(!blocked)
+        })?;
+        if finished {
+            return Ok(());
+        }
+    }
+}
+
+/// 结束当前线程, 唤醒所有join它的线程, 栈在之后的spawn或reap中释放
+pub fn exit() -> ! {
+    use x86_64::instructions::interrupts;
+
+    interrupts::disable();
+    if let Some(threads) = THREADS.lock().as_mut() {
+        let id = threads.current.id;
+        threads.current.state = ThreadState::Exited;
+        while threads.wake_blocked(|thread| thread.joining == Some(id)) {}
+    }
+    yield_now();
+    unreachable!("exited thread was scheduled again");
+}
+
+/// 由时钟中断调用, 参数及返回值都是保存了寄存器的栈指针
+/// 线程表正被占用(只可能是中断前关闭了中断的代码)时不切换
+pub(crate) fn schedule(stack_pointer: u64) -> u64 {
+    match THREADS.try_lock() {
+        Some(mut threads) => match threads.as_mut() {
+            Some(threads) => threads.tick(stack_pointer),
+            None => stack_pointer,
+        },
+        None => stack_pointer,
+    }
+}
+
+// 在新栈的顶部构造与中断入口保存的相同的内容, 返回保存的栈指针
+// iretq之后从thread_start开始执行, rdi为entry
+unsafe fn prepare_stack(stack: &KernelStack, entry: fn()) -> u64 {
+    let selectors = gdt::selectors();
+    // 栈顶留8字节作为thread_start的返回地址, 使函数入口处rsp + 8按16字节对齐
+    let rsp = stack.top - 8u64;
+    rsp.as_mut_ptr::<u64>().write(0);
+    let context = (rsp - mem::size_of::<SavedContext>()).as_mut_ptr::<SavedContext>();
+    context.write(SavedContext {
+        r15: 0,
+        r14: 0,
+        r13: 0,
+        r12: 0,
+        r11: 0,
+        r10: 0,
+        r9: 0,
+        r8: 0,
+        rbp: 0,
+        rdi: entry as *const () as u64,
+        rsi: 0,
+        rdx: 0,
+        rcx: 0,
+        rbx: 0,
+        rax: 0,
+        rip: thread_start as *const () as u64,
+        cs: u64::from(selectors.code_selector.0),
+        rflags: INITIAL_RFLAGS,
+        rsp: rsp.as_u64(),
+        ss: u64::from(selectors.data_selector.0),
+    });
+    context as u64
+}
+
+// 新线程的第一个函数, entry是prepare_stack放在rdi中的fn()
+extern "C" fn thread_start(entry: *const ()) -> ! {
+    let entry: fn() = unsafe { mem::transmute(entry) };
+    entry();
+    exit();
+}
+
+// int 0x81调用, 与时钟中断相同, 但不计时也不需要发送EOI, 总是重新选择线程
+#[no_mangle]
+extern "C" fn thread_yield_handler(stack_pointer: u64) -> u64 {
+    match THREADS.try_lock() {
+        Some(mut threads) => match threads.as_mut() {
+            Some(threads) => threads.switch(stack_pointer),
+            None => stack_pointer,
+        },
+        None => stack_pointer,
+    }
+}
+
+/// 时钟中断的入口地址, 由idt使用
+pub fn timer_entry() -> VirtAddr {
+    extern "C" {
+        fn timer_entry();
+    }
+    VirtAddr::from_ptr(timer_entry as *const ())
+}
+
+/// int 0x81的入口地址, 由idt使用
+pub fn yield_entry() -> VirtAddr {
+    extern "C" {
+        fn yield_entry();
+    }
+    VirtAddr::from_ptr(yield_entry as *const ())
+}
+
+// 中断入口: cpu压入中断栈帧时会先把rsp按16字节对齐, 5项的栈帧加上15个寄存器之后栈仍是16字节对齐的
+// 处理函数返回的栈指针可能属于另一个线程, 从那个栈上恢复寄存器
+global_asm!(
+    ".global timer_entry",
+    "timer_entry:",
+    "push rax",
+    "push rbx",
+    "push rcx",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push rbp",
+    "push r8",
+    "push r9",
+    "push r10",
+    "push r11",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov rdi, rsp",
+    "call timer_interrupt_handler",
+    "mov rsp, rax",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop r11",
+    "pop r10",
+    "pop r9",
+    "pop r8",
+    "pop rbp",
+    "pop rdi",
+    "pop rsi",
+    "pop rdx",
+    "pop rcx",
+    "pop rbx",
+    "pop rax",
+    "iretq",
+);
+
+global_asm!(
+    ".global yield_entry",
+    "yield_entry:",
+    "push rax",
+    "push rbx",
+    "push rcx",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push rbp",
+    "push r8",
+    "push r9",
+    "push r10",
+    "push r11",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov rdi, rsp",
+    "call thread_yield_handler",
+    "mov rsp, rax",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop r11",
+    "pop r10",
+    "pop r9",
+    "pop r8",
+    "pop rbp",
+    "pop rdi",
+    "pop rsi",
+    "pop rdx",
+    "pop rcx",
+    "pop rbx",
+    "pop rax",
+    "iretq",
+);
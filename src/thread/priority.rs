@@ -0,0 +1,75 @@
+// 固定优先级调度
+// 每个优先级一个先进先出的队列, 总是运行优先级最高的就绪线程
+// 更高优先级的线程就绪时, 下一次时钟中断就会抢占当前线程; 同一优先级的线程之间按时间片轮转
+// 低优先级的线程在有更高优先级的线程就绪时得不到运行
+
+use super::{Scheduler, Thread, ThreadId, PRIORITY_LEVELS, TIME_SLICE};
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+
+pub struct FixedPriority {
+    levels: [VecDeque<Box<Thread>>; PRIORITY_LEVELS],
+}
+
+impl FixedPriority {
+    // 就绪线程中最高的优先级
+    fn highest(&self) -> Option<u8> {
+        self.levels
+            .iter()
+            .rposition(|queue| !queue.is_empty())
+            .map(|level| level as u8)
+    }
+}
+
+impl Scheduler for FixedPriority {
+    fn new() -> Self {
+        FixedPriority {
+            levels: Default::default(),
+        }
+    }
+
+    fn push(&mut self, thread: Box<Thread>) {
+        self.levels[thread.priority() as usize].push_back(thread);
+    }
+
+    fn pop(&mut self) -> Option<Box<Thread>> {
+        self.levels
+            .iter_mut()
+            .rev()
+            .find_map(|queue| queue.pop_front())
+    }
+
+    fn should_preempt(&self, current: &Thread) -> bool {
+        match self.highest() {
+            Some(priority) if priority > current.priority() => true,
+            Some(priority) => priority == current.priority() && current.ticks() >= TIME_SLICE,
+            None => false,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.levels.iter().map(VecDeque::len).sum()
+    }
+
+    fn contains(&self, id: ThreadId) -> bool {
+        self.levels
+            .iter()
+            .flat_map(|queue| queue.iter())
+            .any(|thread| thread.id() == id)
+    }
+
+    fn get_mut(&mut self, id: ThreadId) -> Option<&mut Thread> {
+        self.levels
+            .iter_mut()
+            .flat_map(|queue| queue.iter_mut())
+            .find(|thread| thread.id() == id)
+            .map(|thread| &mut **thread)
+    }
+
+    // 线程可以有任意的优先级, 每个队列都按线程总数预留
+    fn reserve(&mut self, threads: usize) {
+        for queue in self.levels.iter_mut() {
+            queue.reserve(threads.saturating_sub(queue.len()));
+        }
+    }
+}
@@ -0,0 +1,49 @@
+// 轮转调度
+// 运行队列是一个先进先出的队列, 线程用完时间片后放到队尾, 不考虑优先级
+
+use super::{Scheduler, Thread, ThreadId, TIME_SLICE};
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+
+pub struct RoundRobin {
+    queue: VecDeque<Box<Thread>>,
+}
+
+impl Scheduler for RoundRobin {
+    fn new() -> Self {
+        RoundRobin {
+            queue: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, thread: Box<Thread>) {
+        self.queue.push_back(thread);
+    }
+
+    fn pop(&mut self) -> Option<Box<Thread>> {
+        self.queue.pop_front()
+    }
+
+    fn should_preempt(&self, current: &Thread) -> bool {
+        !self.queue.is_empty() && current.ticks() >= TIME_SLICE
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn contains(&self, id: ThreadId) -> bool {
+        self.queue.iter().any(|thread| thread.id() == id)
+    }
+
+    fn get_mut(&mut self, id: ThreadId) -> Option<&mut Thread> {
+        self.queue
+            .iter_mut()
+            .find(|thread| thread.id() == id)
+            .map(|thread| &mut **thread)
+    }
+
+    fn reserve(&mut self, threads: usize) {
+        self.queue.reserve(threads.saturating_sub(self.queue.len()));
+    }
+}
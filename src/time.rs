@@ -0,0 +1,53 @@
+// 时钟
+// 没有重新设置PIT, 使用默认的分频系数65536, 时钟中断的频率为1193182 / 65536, 约18.2Hz, 每次约54.9ms
+// 时钟中断中调用tick增加计数, 其他时间都由计数换算而来, 所以精度只有一次中断的间隔
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// PIT的输入频率(Hz)
+pub const PIT_FREQUENCY: u64 = 1_193_182;
+/// PIT默认的分频系数
+pub const PIT_DIVISOR: u64 = 65536;
+
+// 启动以来的时钟中断次数
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// 由时钟中断调用
+pub fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// 启动以来的时钟中断次数
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// 时钟中断次数换算为毫秒, 向下取整, 溢出时取饱和值
+pub fn ticks_to_ms(ticks: u64) -> u64 {
+    ticks.saturating_mul(PIT_DIVISOR * 1000) / PIT_FREQUENCY
+}
+
+/// 毫秒换算为时钟中断次数, 向上取整
+/// ms可能直接来自用户态(sleep调用), 溢出时取饱和值, 换算出的仍是远超运行时间的次数
+pub fn ms_to_ticks(ms: u64) -> u64 {
+    let per_tick = PIT_DIVISOR * 1000;
+    ms.saturating_mul(PIT_FREQUENCY)
+        .saturating_add(per_tick - 1)
+        / per_tick
+}
+
+/// 启动以来的毫秒数
+pub fn uptime_ms() -> u64 {
+    ticks_to_ms(ticks())
+}
+
+/// 休眠至少ms毫秒, 期间通过hlt等待时钟中断
+/// 返回时中断是打开的
+pub fn sleep_ms(ms: u64) {
+    use x86_64::instructions::interrupts;
+
+    let target = ticks().saturating_add(ms_to_ticks(ms));
+    while ticks() < target {
+        interrupts::enable_and_hlt();
+    }
+}
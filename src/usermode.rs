@@ -0,0 +1,53 @@
+// 进入用户态(ring 3)
+// cpu只能通过中断返回(iretq)或sysret降低特权级, 这里手动构造一个中断栈帧再执行iretq:
+// 依次压入用户数据段(SS), 用户栈, RFLAGS, 用户代码段(CS)及入口地址, iretq弹出它们后就在用户态从入口开始执行
+// 用户态发生中断, 异常或执行int指令时, cpu从TSS的RSP0切换到内核栈, 所以进入用户态之前需要先调用gdt::set_kernel_stack
+// 入口及用户栈所在的页需要设置USER_ACCESSIBLE, 通常映射在address_space的用户空间中
+
+use crate::gdt;
+use core::arch::asm;
+use x86_64::VirtAddr;
+
+// 进入用户态时的RFLAGS: 第1位是保留位必须为1, IF(第9位)置位使用户态可以被时钟中断打断
+const USER_RFLAGS: u64 = 0x202;
+
+/// 跳转到用户态的entry执行, 使用user_stack作为栈, 不会返回
+///
+/// 调用者需要保证entry及user_stack在当前页表中对用户可访问, 并且已经通过gdt::set_kernel_stack设置了内核栈
+pub unsafe fn enter_user_mode(entry: VirtAddr, user_stack: VirtAddr) -> ! {
+    let selectors = gdt::selectors();
+    let code = u64::from(selectors.user_code_selector.0);
+    let data = u64::from(selectors.user_data_selector.0);
+    asm!(
+        "mov ds, {data:x}",
+        "mov es, {data:x}",
+        "push {data}",
+        "push {stack}",
+        "push {rflags}",
+        "push {code}",
+        "push {entry}",
+        // 不把内核的寄存器值带到用户态
+        "xor eax, eax",
+        "xor ebx, ebx",
+        "xor ecx, ecx",
+        "xor edx, edx",
+        "xor esi, esi",
+        "xor edi, edi",
+        "xor ebp, ebp",
+        "xor r8d, r8d",
+        "xor r9d, r9d",
+        "xor r10d, r10d",
+        "xor r11d, r11d",
+        "xor r12d, r12d",
+        "xor r13d, r13d",
+        "xor r14d, r14d",
+        "xor r15d, r15d",
+        "iretq",
+        data = in(reg) data,
+        stack = in(reg) user_stack.as_u64(),
+        rflags = in(reg) USER_RFLAGS,
+        code = in(reg) code,
+        entry = in(reg) entry.as_u64(),
+        options(noreturn)
+    );
+}
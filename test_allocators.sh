@@ -0,0 +1,7 @@
+#!/bin/sh
+# 分别使用每一种全局堆分配器运行堆分配的测试
+set -e
+for feature in alloc-bump alloc-linked-list alloc-fixed-block alloc-buddy alloc-external; do
+    echo "== $feature"
+    cargo test --test heap_allocation --no-default-features --features "$feature,sched-round-robin"
+done
@@ -0,0 +1,246 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(qxg_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use qxg_os::memory::address_space::{self, AddressSpace, AddressSpaceError, USER_START};
+use qxg_os::memory::{self, user, BitmapFrameAllocator};
+use x86_64::structures::paging::{Page, PageTableFlags, Translate};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use qxg_os::allocator;
+
+    qxg_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
+
+fn free_frames() -> usize {
+    memory::FRAME_ALLOCATOR
+        .lock()
+        .as_ref()
+        .unwrap()
+        .free_frames()
+}
+
+fn user_page(n: u64) -> Page {
+    Page::containing_address(VirtAddr::new(USER_START + n * 4096))
+}
+
+fn writable() -> PageTableFlags {
+    PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE
+}
+
+#[test_case]
+fn kernel_mappings_are_shared() {
+    let space = AddressSpace::new().unwrap();
+    let value = Box::new(7u64);
+    let addr = VirtAddr::from_ptr(&*value);
+    let kernel = memory::MAPPER.lock().as_ref().unwrap().translate_addr(addr);
+    assert_eq!(space.translate(addr).map(|(phys, _)| phys), kernel);
+    assert_eq!(space.user_pages(), 0);
+}
+
+#[test_case]
+fn map_and_unmap_user_page() {
+    let mut space = AddressSpace::new().unwrap();
+    let page = user_page(1);
+    let frame = space.map_user(page, writable()).unwrap();
+    let (phys, flags) = space.translate(page.start_address() + 8u64).unwrap();
+    assert_eq!(phys, frame.start_address() + 8u64);
+    assert!(flags.contains(PageTableFlags::USER_ACCESSIBLE));
+    assert_eq!(space.user_pages(), 1);
+    assert_eq!(
+        space.map_user(page, writable()),
+        Err(AddressSpaceError::AlreadyMapped)
+    );
+
+    space.unmap_user(page).unwrap();
+    assert_eq!(space.translate(page.start_address()), None);
+    assert_eq!(space.unmap_user(page), Err(AddressSpaceError::NotMapped));
+}
+
+#[test_case]
+fn kernel_addresses_are_rejected() {
+    use qxg_os::allocator::HEAP_START;
+
+    let mut space = AddressSpace::new().unwrap();
+    let page = Page::containing_address(VirtAddr::new(HEAP_START as u64));
+    assert_eq!(
+        space.map_user(page, writable()),
+        Err(AddressSpaceError::NotUserAddress)
+    );
+}
+
+#[test_case]
+fn activate_and_switch_back() {
+    let mut space = AddressSpace::new().unwrap();
+    let addr = user_page(3).start_address();
+    space.map_user(user_page(3), writable()).unwrap();
+
+    unsafe { space.activate() };
+    assert!(space.is_active());
+    // 切换后内核代码及堆仍然可用
+    let boxed = Box::new([1u8; 4]);
+    user::copy_to_user(addr, &*boxed).unwrap();
+    let mut buf = [0u8; 4];
+    user::copy_from_user(&mut buf, addr).unwrap();
+    assert_eq!(buf, [1, 1, 1, 1]);
+
+    address_space::activate_kernel();
+    assert!(!space.is_active());
+    assert!(user::copy_from_user(&mut buf, addr).is_err());
+}
+
+#[test_case]
+fn address_spaces_are_isolated() {
+    let page = user_page(5);
+    let mut a = AddressSpace::new().unwrap();
+    let mut b = AddressSpace::new().unwrap();
+    let frame_a = a.map_user(page, writable()).unwrap();
+    assert_eq!(b.translate(page.start_address()), None);
+    let frame_b = b.map_user(page, writable()).unwrap();
+    assert_ne!(frame_a, frame_b);
+}
+
+#[test_case]
+fn drop_returns_all_frames() {
+    // 第一次创建时会为内核窗口分配四级页表项, 先排除这部分
+    drop(AddressSpace::new().unwrap());
+    let before = free_frames();
+    {
+        let mut space = AddressSpace::new().unwrap();
+        for n in 0..4 {
+            space.map_user(user_page(n * 512), writable()).unwrap();
+        }
+        assert_eq!(space.user_pages(), 4);
+        assert!(free_frames() < before);
+    }
+    assert_eq!(free_frames(), before);
+}
+
+#[test_case]
+fn drop_while_active_switches_back() {
+    let space = AddressSpace::new().unwrap();
+    unsafe { space.activate() };
+    drop(space);
+    let value = Box::new(1);
+    assert_eq!(*value, 1);
+}
+
+// 在space中执行f, 之后切换回内核页表
+fn in_space<R>(space: &AddressSpace, f: impl FnOnce() -> R) -> R {
+    unsafe { space.activate() };
+    let result = f();
+    address_space::activate_kernel();
+    result
+}
+
+fn read_user(addr: VirtAddr) -> [u8; 4] {
+    let mut buf = [0u8; 4];
+    user::copy_from_user(&mut buf, addr).unwrap();
+    buf
+}
+
+#[test_case]
+fn clone_shares_frames_read_only() {
+    let page = user_page(7);
+    let mut parent = AddressSpace::new().unwrap();
+    let frame = parent.map_user(page, writable()).unwrap();
+    let child = parent.clone_cow().unwrap();
+
+    for space in [&parent, &child] {
+        let (phys, flags) = space.translate(page.start_address()).unwrap();
+        assert_eq!(phys, frame.start_address());
+        assert!(flags.contains(address_space::COW));
+        assert!(!flags.contains(PageTableFlags::WRITABLE));
+    }
+    let shares = memory::FRAME_ALLOCATOR
+        .lock()
+        .as_ref()
+        .unwrap()
+        .share_count(frame);
+    assert_eq!(shares, 1);
+}
+
+#[test_case]
+fn writes_after_clone_are_isolated() {
+    let addr = user_page(9).start_address();
+    let mut parent = AddressSpace::new().unwrap();
+    let original = parent.map_user(user_page(9), writable()).unwrap();
+    in_space(&parent, || user::copy_to_user(addr, b"base").unwrap());
+    let child = parent.clone_cow().unwrap();
+
+    // 子地址空间先写, 得到私有的副本
+    in_space(&child, || {
+        assert_eq!(&read_user(addr), b"base");
+        user::copy_to_user(addr, b"chld").unwrap();
+        assert_eq!(&read_user(addr), b"chld");
+    });
+    in_space(&parent, || assert_eq!(&read_user(addr), b"base"));
+
+    let (child_phys, child_flags) = child.translate(addr).unwrap();
+    assert_ne!(child_phys, original.start_address());
+    assert!(child_flags.contains(PageTableFlags::WRITABLE));
+
+    // 父地址空间是最后一个使用者, 写入时直接恢复可写, 不再复制
+    in_space(&parent, || user::copy_to_user(addr, b"prnt").unwrap());
+    let (parent_phys, parent_flags) = parent.translate(addr).unwrap();
+    assert_eq!(parent_phys, original.start_address());
+    assert!(!parent_flags.contains(address_space::COW));
+    in_space(&child, || assert_eq!(&read_user(addr), b"chld"));
+}
+
+#[test_case]
+fn clone_keeps_read_only_pages_read_only() {
+    let addr = user_page(11).start_address();
+    let mut parent = AddressSpace::new().unwrap();
+    parent
+        .map_user(user_page(11), PageTableFlags::NO_EXECUTE)
+        .unwrap();
+    let child = parent.clone_cow().unwrap();
+    let (_, flags) = child.translate(addr).unwrap();
+    assert!(!flags.contains(address_space::COW));
+    assert!(in_space(&child, || user::copy_to_user(addr, b"nope")).is_err());
+}
+
+#[test_case]
+fn dropping_clones_returns_all_frames() {
+    drop(AddressSpace::new().unwrap());
+    let before = free_frames();
+    {
+        let mut parent = AddressSpace::new().unwrap();
+        for n in 0..3 {
+            parent.map_user(user_page(n), writable()).unwrap();
+        }
+        let child = parent.clone_cow().unwrap();
+        in_space(&child, || {
+            user::copy_to_user(user_page(1).start_address(), b"copy").unwrap()
+        });
+        drop(parent);
+        assert!(free_frames() < before);
+        drop(child);
+    }
+    assert_eq!(free_frames(), before);
+}
@@ -0,0 +1,85 @@
+// 在用户态打开RFLAGS.AC后进行未对齐的读取, 触发真正的对齐检查异常(#AC, 17号)
+// 对齐检查只在CR0.AM及RFLAGS.AC都置位, 并且在ring 3时生效, #AC会压入错误码(总是0), 所以不能用int 17代替
+
+#![no_std]
+#![no_main]
+
+mod user_program;
+
+use bootloader::{entry_point, BootInfo};
+use core::arch::global_asm;
+use core::panic::PanicInfo;
+use qxg_os::interrupts::exceptions::{self, FaultReport};
+use qxg_os::{exit_qemu, hlt_loop, serial_print, serial_println, QemuExitCode};
+use user_program::CODE_ADDR;
+use x86_64::registers::control::{Cr0, Cr0Flags};
+
+global_asm!(
+    ".global alignment_user_start",
+    ".global alignment_user_fault",
+    ".global alignment_user_end",
+    "alignment_user_start:",
+    "pushfq",
+    "or dword ptr [rsp], 0x40000",
+    "popfq",
+    // 栈顶按16字节对齐, 读取跨越8字节边界的u64
+    "alignment_user_fault:",
+    "mov rax, [rsp - 7]",
+    // 没有触发#AC时执行到这里
+    "ud2",
+    "alignment_user_end:",
+);
+
+extern "C" {
+    static alignment_user_start: u8;
+    static alignment_user_fault: u8;
+    static alignment_user_end: u8;
+}
+
+fn check_report(report: &FaultReport) -> bool {
+    match report.vector {
+        17 => {}
+        6 => panic!("misaligned access did not raise an alignment check"),
+        _ => panic!("unexpected exception\n{}", report),
+    }
+    assert!(report.from_user());
+    assert_eq!(report.error_code, Some(0));
+    // #AC是错误类异常, 返回地址是出错的指令
+    let offset = unsafe {
+        core::ptr::addr_of!(alignment_user_fault) as u64
+            - core::ptr::addr_of!(alignment_user_start) as u64
+    };
+    assert_eq!(
+        report.stack_frame.instruction_pointer.as_u64(),
+        CODE_ADDR + offset
+    );
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    hlt_loop();
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("alignment_check::misaligned_access_from_user_mode...\t");
+
+    user_program::init(boot_info);
+    let code = unsafe {
+        user_program::code_between(
+            core::ptr::addr_of!(alignment_user_start),
+            core::ptr::addr_of!(alignment_user_end),
+        )
+    };
+    let space = user_program::load(code);
+    user_program::set_kernel_stack();
+    exceptions::set_fault_hook(Some(check_report));
+    unsafe {
+        Cr0::update(|flags| flags.insert(Cr0Flags::ALIGNMENT_MASK));
+        user_program::run(space)
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
@@ -0,0 +1,140 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(qxg_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::cell::RefCell;
+use core::panic::PanicInfo;
+use futures_util::stream::StreamExt;
+use qxg_os::memory::{self, BitmapFrameAllocator};
+use qxg_os::task::executor::Executor;
+use qxg_os::task::keyboard::{self, ScancodeStream};
+use qxg_os::task::timer::{self, Timer};
+use qxg_os::task::Task;
+use qxg_os::time;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use qxg_os::allocator;
+
+    qxg_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
+
+async fn async_number() -> u32 {
+    42
+}
+
+#[test_case]
+fn runs_tasks_to_completion() {
+    let result = Rc::new(RefCell::new(0));
+    let mut executor = Executor::new();
+    let output = result.clone();
+    executor.spawn(Task::new(async move {
+        *output.borrow_mut() = async_number().await;
+    }));
+    assert_eq!(executor.len(), 1);
+    executor.run_until_empty();
+    assert!(executor.is_empty());
+    assert_eq!(*result.borrow(), 42);
+}
+
+#[test_case]
+fn timer_completes_after_deadline() {
+    let elapsed = Rc::new(RefCell::new(0));
+    let mut executor = Executor::new();
+    let output = elapsed.clone();
+    executor.spawn(Task::new(async move {
+        let start = time::ticks();
+        Timer::after_ticks(3).await;
+        *output.borrow_mut() = time::ticks() - start;
+    }));
+    executor.run_until_empty();
+    assert!(*elapsed.borrow() >= 3);
+}
+
+#[test_case]
+fn timers_wake_in_deadline_order() {
+    let order = Rc::new(RefCell::new(Vec::new()));
+    let mut executor = Executor::new();
+    for &ticks in [6u64, 2, 4].iter() {
+        let order = order.clone();
+        executor.spawn(Task::new(async move {
+            Timer::after_ticks(ticks).await;
+            order.borrow_mut().push(ticks);
+        }));
+    }
+    executor.run_until_empty();
+    assert_eq!(*order.borrow(), [2, 4, 6]);
+}
+
+#[test_case]
+fn timers_deregister_when_completed_or_dropped() {
+    use core::future::{poll_fn, Future};
+    use core::pin::Pin;
+    use core::task::Poll;
+
+    let before = timer::pending();
+    let mut executor = Executor::new();
+    executor.spawn(Task::new(async move {
+        // 只轮询一次, 登记waker之后丢弃
+        let mut timer = Timer::after_ticks(1000);
+        poll_fn(|cx| {
+            assert!(Pin::new(&mut timer).poll(cx).is_pending());
+            Poll::Ready(())
+        })
+        .await;
+        assert_eq!(timer::pending(), before + 1);
+        drop(timer);
+        assert_eq!(timer::pending(), before);
+
+        Timer::after_ticks(2).await;
+    }));
+    executor.run_until_empty();
+    assert_eq!(timer::pending(), before);
+}
+
+#[test_case]
+fn scancode_stream_yields_queued_scancodes() {
+    let received = Rc::new(RefCell::new(Vec::new()));
+    let mut executor = Executor::new();
+    let output = received.clone();
+    executor.spawn(Task::new(async move {
+        let mut scancodes = ScancodeStream::new();
+        for _ in 0..3 {
+            let scancode = scancodes.next().await.unwrap();
+            output.borrow_mut().push(scancode);
+        }
+    }));
+    // 任务先运行一次, 队列为空时等待唤醒
+    executor.run_ready_tasks();
+    assert!(received.borrow().is_empty());
+
+    // 与键盘中断中的调用相同
+    for &scancode in [0x1e, 0x9e, 0x30].iter() {
+        keyboard::add_scancode(scancode);
+    }
+    executor.run_until_empty();
+    assert_eq!(*received.borrow(), [0x1e, 0x9e, 0x30]);
+}
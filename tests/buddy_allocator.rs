@@ -0,0 +1,128 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(qxg_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use alloc::alloc::Layout;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use qxg_os::allocator::buddy::BuddyAllocator;
+use qxg_os::allocator::HeapAllocator;
+use qxg_os::memory::BuddyFrameAllocator;
+use spin::Mutex;
+use x86_64::structures::paging::FrameDeallocator;
+use x86_64::VirtAddr;
+
+extern crate alloc;
+
+static FRAME_ALLOCATOR: Mutex<Option<BuddyFrameAllocator>> = Mutex::new(None);
+
+// 测试用的堆, 按64KiB对齐, 这样整个数组就是一个最大的块
+#[repr(align(65536))]
+struct TestHeap([u8; TEST_HEAP_SIZE]);
+
+const TEST_HEAP_SIZE: usize = 64 * 1024;
+static mut TEST_HEAP: TestHeap = TestHeap([0; TEST_HEAP_SIZE]);
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    qxg_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn heap_split_and_merge() {
+    let mut heap = BuddyAllocator::new();
+    unsafe {
+        let start = core::ptr::addr_of_mut!(TEST_HEAP.0) as usize;
+        heap.init(start, TEST_HEAP_SIZE);
+    }
+    assert_eq!(heap.stats().total, TEST_HEAP_SIZE);
+
+    // 64KiB的块需要拆分12次才能得到16字节的块
+    let layout = Layout::from_size_align(16, 8).unwrap();
+    let a = heap.alloc(layout);
+    assert!(!a.is_null());
+    assert_eq!(heap.stats().splits, 12);
+
+    // 伙伴块不需要再拆分
+    let b = heap.alloc(layout);
+    assert_eq!(b as usize, a as usize ^ 16);
+    assert_eq!(heap.stats().splits, 12);
+
+    unsafe {
+        heap.dealloc(a, layout);
+        heap.dealloc(b, layout);
+    }
+    let stats = heap.stats();
+    assert_eq!(stats.merges, 12);
+    assert_eq!(stats.allocated, 0);
+
+    // 合并后可以再次分配整个堆
+    let whole = Layout::from_size_align(TEST_HEAP_SIZE, 8).unwrap();
+    let c = heap.alloc(whole);
+    assert_eq!(c as usize, a as usize);
+    unsafe { heap.dealloc(c, whole) };
+}
+
+#[test_case]
+fn heap_respects_alignment() {
+    let mut heap = BuddyAllocator::new();
+    unsafe {
+        let start = core::ptr::addr_of_mut!(TEST_HEAP.0) as usize;
+        heap.init(start, TEST_HEAP_SIZE);
+    }
+
+    let layout = Layout::from_size_align(8, 4096).unwrap();
+    let small = heap.alloc(Layout::from_size_align(8, 8).unwrap());
+    let aligned = heap.alloc(layout);
+    assert!(!aligned.is_null());
+    assert_eq!(aligned as usize % 4096, 0);
+    assert_ne!(small, aligned);
+}
+
+#[test_case]
+fn contiguous_frames_are_aligned() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+    let free = allocator.free_frames();
+
+    // 分配连续的16个帧
+    let frame = allocator.allocate_frames(4).expect("out of frames");
+    assert_eq!(frame.start_address().as_u64() % (16 * 4096), 0);
+    assert_eq!(allocator.free_frames(), free - 16);
+
+    unsafe { allocator.deallocate_frames(frame, 4) };
+    assert_eq!(allocator.free_frames(), free);
+}
+
+#[test_case]
+fn single_frames_coalesce() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+    let merges = allocator.stats().merges;
+
+    // 分配两个连续的帧, 再分别作为单个帧释放, 第二次释放时一定会与伙伴合并
+    let a = allocator.allocate_frames(1).expect("out of frames");
+    let b = a + 1;
+    unsafe {
+        allocator.deallocate_frame(a);
+        allocator.deallocate_frame(b);
+    }
+    assert!(allocator.stats().merges > merges);
+    assert_eq!(allocator.allocate_frames(1), Some(a));
+    unsafe { allocator.deallocate_frames(a, 1) };
+}
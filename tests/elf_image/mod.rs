@@ -0,0 +1,123 @@
+// 测试用的ELF镜像: 在内存中拼出一个最小的静态可执行文件
+// 第一个段包含文件头及程序头表(只读), 之后依次是代码段(可执行)及数据段(可写, 末尾带一页bss)
+
+use alloc::vec::Vec;
+use core::mem;
+use qxg_os::elf::{FileHeader, ProgramHeader, ET_EXEC, PF_R, PF_W, PF_X, PT_LOAD};
+use qxg_os::memory::address_space::USER_START;
+
+/// 文件头所在的地址
+pub const BASE: u64 = USER_START;
+/// 代码段的地址, 也是入口地址
+pub const TEXT_ADDR: u64 = BASE + 0x1000;
+/// 数据段的地址
+pub const DATA_ADDR: u64 = BASE + 0x2000;
+/// bss的地址
+pub const BSS_ADDR: u64 = BASE + 0x3000;
+
+/// 镜像中的一个段
+pub struct Segment<'a> {
+    pub vaddr: u64,
+    pub flags: u32,
+    pub data: &'a [u8],
+    pub memsz: u64,
+}
+
+/// 标准布局的镜像: 头部, 代码及数据(加一页bss)
+pub fn build(text: &[u8], data: &[u8]) -> Vec<u8> {
+    let headers = mem::size_of::<FileHeader>() + 3 * mem::size_of::<ProgramHeader>();
+    build_segments(
+        TEXT_ADDR,
+        &[
+            Segment {
+                vaddr: BASE,
+                flags: PF_R,
+                data: &[],
+                memsz: headers as u64,
+            },
+            Segment {
+                vaddr: TEXT_ADDR,
+                flags: PF_R | PF_X,
+                data: text,
+                memsz: text.len() as u64,
+            },
+            Segment {
+                vaddr: DATA_ADDR,
+                flags: PF_R | PF_W,
+                data,
+                memsz: BSS_ADDR + 0x1000 - DATA_ADDR,
+            },
+        ],
+    )
+}
+
+/// 任意段组成的镜像, 每个段的内容从与vaddr同余的页开始存放
+/// 第一个段如果从BASE开始, 它的内容就是文件头及程序头表
+pub fn build_segments(entry: u64, segments: &[Segment]) -> Vec<u8> {
+    let phoff = mem::size_of::<FileHeader>();
+    let headers_end = phoff + segments.len() * mem::size_of::<ProgramHeader>();
+
+    let mut image = Vec::new();
+    image.resize(headers_end, 0);
+    let mut program_headers = Vec::new();
+    for segment in segments {
+        let offset = if segment.vaddr == BASE && segment.data.is_empty() {
+            0
+        } else {
+            // 文件偏移与虚拟地址按页同余
+            let page = (image.len() + 0xfff) & !0xfff;
+            let offset = page + (segment.vaddr & 0xfff) as usize;
+            image.resize(offset, 0);
+            image.extend_from_slice(segment.data);
+            offset
+        };
+        let filesz = if offset == 0 {
+            headers_end as u64
+        } else {
+            segment.data.len() as u64
+        };
+        program_headers.push(ProgramHeader {
+            p_type: PT_LOAD,
+            p_flags: segment.flags,
+            p_offset: offset as u64,
+            p_vaddr: segment.vaddr,
+            p_paddr: segment.vaddr,
+            p_filesz: filesz,
+            p_memsz: segment.memsz.max(filesz),
+            p_align: 0x1000,
+        });
+    }
+
+    let mut ident = [0u8; 16];
+    ident[..4].copy_from_slice(b"\x7fELF");
+    ident[4] = 2; // 64位
+    ident[5] = 1; // 小端
+    ident[6] = 1; // 版本
+    let header = FileHeader {
+        ident,
+        file_type: ET_EXEC,
+        machine: 0x3e,
+        version: 1,
+        entry,
+        phoff: phoff as u64,
+        shoff: 0,
+        flags: 0,
+        ehsize: phoff as u16,
+        phentsize: mem::size_of::<ProgramHeader>() as u16,
+        phnum: segments.len() as u16,
+        shentsize: 0,
+        shnum: 0,
+        shstrndx: 0,
+    };
+    write(&mut image, 0, &header);
+    for (i, ph) in program_headers.iter().enumerate() {
+        write(&mut image, phoff + i * mem::size_of::<ProgramHeader>(), ph);
+    }
+    image
+}
+
+fn write<T>(image: &mut [u8], offset: usize, value: &T) {
+    let bytes =
+        unsafe { core::slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>()) };
+    image[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
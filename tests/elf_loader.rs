@@ -0,0 +1,215 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(qxg_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+mod elf_image;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use elf_image::{Segment, BASE, BSS_ADDR, DATA_ADDR, TEXT_ADDR};
+use qxg_os::elf::{ElfError, PF_R, PF_W, PF_X};
+use qxg_os::loader::{self, LoadError, Program, AT_ENTRY, AT_NULL, AT_PAGESZ, AT_PHDR};
+use qxg_os::memory::address_space::AddressSpace;
+use qxg_os::memory::{self, phys_to_virt, BitmapFrameAllocator};
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use qxg_os::allocator;
+
+    qxg_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
+
+const TEXT: [u8; 4] = [0x90, 0x90, 0xeb, 0xfe];
+const DATA: [u8; 8] = 0x1122_3344_5566_7788u64.to_le_bytes();
+
+// 通过物理内存的映射读取地址空间中的一个u64
+fn read_u64(space: &AddressSpace, addr: u64) -> u64 {
+    let (phys, _) = space.translate(VirtAddr::new(addr)).unwrap();
+    unsafe { phys_to_virt(phys).as_ptr::<u64>().read_unaligned() }
+}
+
+fn read_str(space: &AddressSpace, addr: u64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for i in 0.. {
+        let (phys, _) = space.translate(VirtAddr::new(addr + i)).unwrap();
+        let byte = unsafe { *phys_to_virt(phys).as_ptr::<u8>() };
+        if byte == 0 {
+            break;
+        }
+        bytes.push(byte);
+    }
+    bytes
+}
+
+fn load_default() -> Program {
+    let image = elf_image::build(&TEXT, &DATA);
+    loader::load(&image, &["prog", "arg"], &["HOME=/"]).unwrap()
+}
+
+#[test_case]
+fn segments_have_elf_permissions() {
+    let program = load_default();
+    let space = &program.space;
+    assert_eq!(program.entry, VirtAddr::new(TEXT_ADDR));
+
+    let (_, text) = space.translate(VirtAddr::new(TEXT_ADDR)).unwrap();
+    assert!(!text.contains(PageTableFlags::WRITABLE));
+    assert!(!text.contains(PageTableFlags::NO_EXECUTE));
+    assert!(text.contains(PageTableFlags::USER_ACCESSIBLE));
+
+    let (_, data) = space.translate(VirtAddr::new(DATA_ADDR)).unwrap();
+    assert!(data.contains(PageTableFlags::WRITABLE));
+    assert!(data.contains(PageTableFlags::NO_EXECUTE));
+
+    let (_, headers) = space.translate(VirtAddr::new(BASE)).unwrap();
+    assert!(!headers.contains(PageTableFlags::WRITABLE));
+    assert!(headers.contains(PageTableFlags::NO_EXECUTE));
+}
+
+#[test_case]
+fn segment_contents_are_copied_and_bss_is_zero() {
+    let program = load_default();
+    let space = &program.space;
+    assert_eq!(read_u64(space, TEXT_ADDR) as u32, u32::from_le_bytes(TEXT));
+    assert_eq!(read_u64(space, DATA_ADDR), 0x1122_3344_5566_7788);
+    assert_eq!(read_u64(space, DATA_ADDR + 8), 0);
+    assert_eq!(read_u64(space, BSS_ADDR), 0);
+}
+
+#[test_case]
+fn stack_holds_arguments_and_auxv() {
+    let program = load_default();
+    let space = &program.space;
+    let sp = program.stack_pointer.as_u64();
+    assert_eq!(sp % 16, 0);
+
+    assert_eq!(read_u64(space, sp), 2);
+    assert_eq!(read_str(space, read_u64(space, sp + 8)), b"prog");
+    assert_eq!(read_str(space, read_u64(space, sp + 16)), b"arg");
+    assert_eq!(read_u64(space, sp + 24), 0);
+    assert_eq!(read_str(space, read_u64(space, sp + 32)), b"HOME=/");
+    assert_eq!(read_u64(space, sp + 40), 0);
+
+    // auxv中检查已知的几项
+    let mut auxv = sp + 48;
+    let mut found = 0;
+    while read_u64(space, auxv) != AT_NULL {
+        let (kind, value) = (read_u64(space, auxv), read_u64(space, auxv + 8));
+        auxv += 16;
+        match kind {
+            AT_PAGESZ => assert_eq!(value, 4096),
+            AT_ENTRY => assert_eq!(value, TEXT_ADDR),
+            AT_PHDR => assert_eq!(value, BASE + 64),
+            _ => continue,
+        }
+        found += 1;
+    }
+    assert_eq!(found, 3);
+}
+
+#[test_case]
+fn rejects_bad_images() {
+    let mut image = elf_image::build(&TEXT, &DATA);
+    assert_eq!(
+        loader::load(&image[..32], &[], &[]).unwrap_err(),
+        LoadError::Elf(ElfError::Truncated)
+    );
+    image[0] = 0;
+    assert_eq!(
+        loader::load(&image, &[], &[]).unwrap_err(),
+        LoadError::Elf(ElfError::BadMagic)
+    );
+}
+
+#[test_case]
+fn rejects_writable_executable_segment() {
+    let image = elf_image::build_segments(
+        TEXT_ADDR,
+        &[Segment {
+            vaddr: TEXT_ADDR,
+            flags: PF_R | PF_W | PF_X,
+            data: &TEXT,
+            memsz: TEXT.len() as u64,
+        }],
+    );
+    assert_eq!(
+        loader::load(&image, &[], &[]).unwrap_err(),
+        LoadError::WritableAndExecutable
+    );
+}
+
+#[test_case]
+fn rejects_kernel_addresses() {
+    use qxg_os::allocator::HEAP_START;
+
+    let image = elf_image::build_segments(
+        HEAP_START as u64,
+        &[Segment {
+            vaddr: HEAP_START as u64,
+            flags: PF_R | PF_X,
+            data: &TEXT,
+            memsz: TEXT.len() as u64,
+        }],
+    );
+    assert_eq!(
+        loader::load(&image, &[], &[]).unwrap_err(),
+        LoadError::BadSegment
+    );
+}
+
+#[test_case]
+fn ignores_file_range_of_empty_segment() {
+    use core::mem;
+    use qxg_os::elf::{FileHeader, ProgramHeader};
+
+    // 头部所在的段改为不占内存, 文件中的范围越过u64::MAX
+    let mut image = elf_image::build(&TEXT, &DATA);
+    let offset = mem::size_of::<FileHeader>();
+    let ph = image[offset..].as_mut_ptr() as *mut ProgramHeader;
+    unsafe {
+        let mut header = ph.read_unaligned();
+        header.p_offset = 1;
+        header.p_filesz = u64::MAX;
+        header.p_memsz = 0;
+        ph.write_unaligned(header);
+    }
+    let program = loader::load(&image, &[], &[]).unwrap();
+    assert_eq!(program.entry.as_u64(), TEXT_ADDR);
+}
+
+#[test_case]
+fn rejects_overlapping_segments() {
+    let segment = |flags| Segment {
+        vaddr: TEXT_ADDR,
+        flags,
+        data: &TEXT,
+        memsz: TEXT.len() as u64,
+    };
+    let image = elf_image::build_segments(TEXT_ADDR, &[segment(PF_R | PF_X), segment(PF_R)]);
+    assert_eq!(
+        loader::load(&image, &[], &[]).unwrap_err(),
+        LoadError::OverlappingSegments
+    );
+}
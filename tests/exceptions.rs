@@ -0,0 +1,117 @@
+// 通过int指令触发没有错误码的异常, 检查报告中的异常号
+// 带错误码的异常不能用int触发(int不会压入错误码), 由divide_error等单独的测试程序触发真正的异常,
+// 对齐检查(17号)需要在用户态触发, 见alignment_check
+// 机器检查(18号)由硬件错误产生, 无法由软件触发, 用int 18调用处理函数也只会停机(处理函数不能返回), 所以没有测试
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(qxg_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::arch::asm;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU8, Ordering};
+use qxg_os::interrupts::exceptions::{self, DescriptorTable, FaultReport, SelectorError};
+
+// 钩子收到的异常号, 0xff表示没有收到
+static LAST_VECTOR: AtomicU8 = AtomicU8::new(0xff);
+
+fn record_vector(report: &FaultReport) -> bool {
+    LAST_VECTOR.store(report.vector, Ordering::SeqCst);
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    qxg_os::init();
+    exceptions::set_fault_hook(Some(record_vector));
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
+
+// 触发第$vector号中断并检查钩子收到的异常号
+macro_rules! check_vector {
+    ($vector:expr) => {
+        LAST_VECTOR.store(0xff, Ordering::SeqCst);
+        unsafe { asm!(concat!("int ", stringify!($vector))) };
+        assert_eq!(LAST_VECTOR.load(Ordering::SeqCst), $vector);
+    };
+}
+
+#[test_case]
+fn divide_error_vector() {
+    check_vector!(0);
+}
+
+#[test_case]
+fn debug_vector() {
+    check_vector!(1);
+}
+
+#[test_case]
+fn non_maskable_interrupt_vector() {
+    check_vector!(2);
+}
+
+#[test_case]
+fn breakpoint_vector() {
+    check_vector!(3);
+}
+
+#[test_case]
+fn overflow_vector() {
+    check_vector!(4);
+}
+
+#[test_case]
+fn bound_range_exceeded_vector() {
+    check_vector!(5);
+}
+
+#[test_case]
+fn invalid_opcode_vector() {
+    check_vector!(6);
+}
+
+#[test_case]
+fn device_not_available_vector() {
+    check_vector!(7);
+}
+
+#[test_case]
+fn x87_floating_point_vector() {
+    check_vector!(16);
+}
+
+#[test_case]
+fn simd_floating_point_vector() {
+    check_vector!(19);
+}
+
+#[test_case]
+fn virtualization_vector() {
+    check_vector!(20);
+}
+
+#[test_case]
+fn decode_selector_error() {
+    // gdt中下标为5的选择子
+    let error = SelectorError::decode(5 << 3);
+    assert_eq!(error.index, 5);
+    assert_eq!(error.table, DescriptorTable::Gdt);
+    assert!(!error.external);
+
+    // 传递外部中断时idt中第32项出错
+    let error = SelectorError::decode((32 << 3) | 0b011);
+    assert_eq!(error.index, 32);
+    assert_eq!(error.table, DescriptorTable::Idt);
+    assert!(error.external);
+
+    assert_eq!(SelectorError::decode(0b100).table, DescriptorTable::Ldt);
+}
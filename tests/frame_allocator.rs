@@ -0,0 +1,85 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(qxg_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use qxg_os::memory::BitmapFrameAllocator;
+use spin::Mutex;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator};
+use x86_64::VirtAddr;
+
+static FRAME_ALLOCATOR: Mutex<Option<BitmapFrameAllocator>> = Mutex::new(None);
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    qxg_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn counts_are_consistent() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+    assert!(allocator.total_frames() > 0);
+    assert_eq!(
+        allocator.free_frames() + allocator.used_frames(),
+        allocator.total_frames()
+    );
+}
+
+#[test_case]
+fn freed_frame_is_reused() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+    let free = allocator.free_frames();
+
+    let frame = allocator.allocate_frame().expect("out of frames");
+    assert_eq!(allocator.free_frames(), free - 1);
+    unsafe { allocator.deallocate_frame(frame) };
+    assert_eq!(allocator.free_frames(), free);
+
+    assert_eq!(allocator.allocate_frame(), Some(frame));
+    unsafe { allocator.deallocate_frame(frame) };
+}
+
+#[test_case]
+fn release_restores_allocation_order() {
+    let mut guard = FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+    let free = allocator.free_frames();
+
+    let mut frames = [None; 128];
+    for slot in frames.iter_mut() {
+        *slot = allocator.allocate_frame();
+        assert!(slot.is_some());
+    }
+    assert_eq!(allocator.free_frames(), free - frames.len());
+
+    for frame in frames.iter().rev() {
+        unsafe { allocator.deallocate_frame(frame.unwrap()) };
+    }
+    assert_eq!(allocator.free_frames(), free);
+
+    // 全部释放后, 再次分配应当得到同样的帧
+    for frame in frames.iter() {
+        assert_eq!(allocator.allocate_frame(), *frame);
+    }
+    for frame in frames.iter() {
+        unsafe { allocator.deallocate_frame(frame.unwrap()) };
+    }
+}
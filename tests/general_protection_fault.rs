@@ -0,0 +1,42 @@
+// 触发真正的一般保护错误, 检查报告中的异常号及错误码
+// 错误类的异常返回后会重新执行出错的指令, 所以钩子检查后直接退出qemu
+
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+use core::panic::PanicInfo;
+use qxg_os::interrupts::exceptions::{self, FaultReport};
+use qxg_os::{exit_qemu, hlt_loop, serial_print, serial_println, QemuExitCode};
+
+fn check_report(report: &FaultReport) -> bool {
+    if report.vector != 13 {
+        panic!("unexpected exception\n{}", report);
+    }
+    // 错误码为超出gdt范围的选择子
+    let selector = report.selector_error().expect("missing selector");
+    assert_eq!(selector.index, 0x100);
+    assert_eq!(selector.table, exceptions::DescriptorTable::Gdt);
+    assert!(!selector.external);
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    hlt_loop();
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("general_protection_fault::general_protection_fault...\t");
+
+    qxg_os::init();
+    exceptions::set_fault_hook(Some(check_report));
+
+    // 把超出gdt范围的选择子加载到ds中
+    unsafe { asm!("mov ds, {0:x}", in(reg) 0x100u16 << 3) };
+
+    panic!("Execution continued after general protection fault");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
@@ -13,14 +13,16 @@ entry_point!(main);
 
 fn main(boot_info: &'static BootInfo) -> ! {
     use qxg_os::allocator;
-    use qxg_os::memory::{self, BootInfoFrameAllocator};
+    use qxg_os::memory::{self, BitmapFrameAllocator};
     use x86_64::VirtAddr;
 
     qxg_os::init();
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
     let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    let mut frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
 
     test_main();
     loop {}
@@ -62,3 +64,75 @@ fn many_boxes() {
         assert_eq!(*x, i);
     }
 }
+
+// 有一个一直存活的分配时, bump分配器无法复用内存, 所以跳过
+#[cfg(not(feature = "alloc-bump"))]
+#[test_case]
+fn many_boxes_long_lived() {
+    let long_lived = Box::new(1);
+    for i in 0..HEAP_SIZE {
+        let x = Box::new(i);
+        assert_eq!(*x, i);
+    }
+    assert_eq!(*long_lived, 1);
+}
+
+// 超过HEAP_SIZE的分配会自动扩展堆
+// bump分配器需要所有分配都释放后才能复用, 前面的测试不影响这里
+#[test_case]
+fn heap_grows_on_demand() {
+    use qxg_os::allocator::heap_size;
+
+    let n = HEAP_SIZE; // 每个元素8字节, 一共需要8倍HEAP_SIZE的空间
+    let mut vec = Vec::new();
+    for i in 0..n {
+        vec.push(i);
+    }
+    assert_eq!(vec.iter().sum::<usize>(), (n - 1) * n / 2);
+    assert!(heap_size() > HEAP_SIZE);
+}
+
+#[test_case]
+fn stats_track_live_allocations() {
+    use qxg_os::allocator::stats;
+
+    let before = stats();
+    let x = Box::new([0u8; 64]);
+    let during = stats();
+    assert_eq!(during.allocations, before.allocations + 1);
+    assert_eq!(during.allocated, before.allocated + 64);
+    assert!(during.peak >= during.allocated);
+    drop(x);
+
+    let after = stats();
+    assert_eq!(after.allocations, before.allocations);
+    assert_eq!(after.allocated, before.allocated);
+    assert!(after.largest_free_block <= after.free);
+}
+
+#[test_case]
+fn scope_without_leaks() {
+    use qxg_os::allocator::leak::check_leaks;
+
+    let report = check_leaks(|| {
+        let mut vec = Vec::new();
+        for i in 0..100 {
+            vec.push(Box::new(i));
+        }
+    });
+    assert!(report.is_empty(), "{}", report);
+}
+
+#[test_case]
+fn scope_with_leak() {
+    use qxg_os::allocator::leak::check_leaks;
+
+    let report = check_leaks(|| {
+        let kept = Box::new(1u64);
+        core::mem::forget(Box::new(2u64));
+        drop(kept);
+    });
+    assert_eq!(report.count, 1);
+    assert_eq!(report.bytes, 8);
+    assert!(report.leaks().all(|leak| leak.callers[0] != 0));
+}
@@ -0,0 +1,122 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(qxg_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::alloc::Layout;
+use core::panic::PanicInfo;
+use core::ptr;
+use qxg_os::allocator::debug::{Corruption, DebugAllocator};
+use qxg_os::allocator::linked_list::LinkedListAllocator;
+use qxg_os::allocator::{HeapAllocator, Locked};
+
+const TEST_HEAP_SIZE: usize = 64 * 1024;
+
+#[repr(align(16))]
+struct TestHeap([u8; TEST_HEAP_SIZE]);
+
+static mut TEST_HEAP: TestHeap = TestHeap([0; TEST_HEAP_SIZE]);
+
+static ALLOCATOR: Locked<DebugAllocator<LinkedListAllocator>> =
+    Locked::new(DebugAllocator::new(LinkedListAllocator::new()));
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    qxg_os::init();
+    {
+        let mut allocator = ALLOCATOR.lock();
+        unsafe { allocator.init(ptr::addr_of_mut!(TEST_HEAP.0) as usize, TEST_HEAP_SIZE) };
+        // 测试中故意制造错误, 只记录不panic
+        allocator.set_panic_on_error(false);
+    }
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
+
+fn layout() -> Layout {
+    Layout::from_size_align(24, 8).unwrap()
+}
+
+#[test_case]
+fn clean_alloc_and_free() {
+    let mut allocator = ALLOCATOR.lock();
+    let errors = allocator.errors();
+    let ptr = allocator.alloc(layout());
+    assert!(!ptr.is_null());
+    unsafe {
+        ptr.write_bytes(1, layout().size());
+        allocator.dealloc(ptr, layout());
+    }
+    assert_eq!(allocator.errors(), errors);
+}
+
+#[test_case]
+fn detects_overflow() {
+    let mut allocator = ALLOCATOR.lock();
+    let ptr = allocator.alloc(layout());
+    unsafe {
+        ptr.add(layout().size()).write(0);
+        allocator.dealloc(ptr, layout());
+    }
+    assert_eq!(allocator.last_error(), Some(Corruption::BufferOverflow));
+}
+
+#[test_case]
+fn detects_underflow() {
+    let mut allocator = ALLOCATOR.lock();
+    let ptr = allocator.alloc(layout());
+    unsafe {
+        ptr.sub(20).write(0);
+        allocator.dealloc(ptr, layout());
+    }
+    assert_eq!(allocator.last_error(), Some(Corruption::BufferUnderflow));
+}
+
+#[test_case]
+fn detects_double_free() {
+    let mut allocator = ALLOCATOR.lock();
+    let ptr = allocator.alloc(layout());
+    unsafe {
+        allocator.dealloc(ptr, layout());
+        allocator.dealloc(ptr, layout());
+    }
+    assert_eq!(allocator.last_error(), Some(Corruption::DoubleFree));
+}
+
+#[test_case]
+fn detects_layout_mismatch() {
+    let mut allocator = ALLOCATOR.lock();
+    let ptr = allocator.alloc(layout());
+    let wrong = Layout::from_size_align(16, 8).unwrap();
+    unsafe { allocator.dealloc(ptr, wrong) };
+    assert_eq!(
+        allocator.last_error(),
+        Some(Corruption::LayoutMismatch(layout().size()))
+    );
+}
+
+#[test_case]
+fn detects_use_after_free() {
+    let mut allocator = ALLOCATOR.lock();
+    let ptr = allocator.alloc(layout());
+    unsafe {
+        allocator.dealloc(ptr, layout());
+        ptr.write(42);
+    }
+
+    // 释放足够多的块, 把被修改的块挤出隔离区
+    for _ in 0..64 {
+        let other = allocator.alloc(layout());
+        unsafe { allocator.dealloc(other, layout()) };
+    }
+    assert_eq!(allocator.last_error(), Some(Corruption::UseAfterFree));
+}
@@ -0,0 +1,157 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(qxg_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use qxg_os::memory::dump::PageSize as MappedSize;
+use qxg_os::memory::huge;
+use qxg_os::memory::vma::{self, Backing};
+use qxg_os::memory::{self, BitmapFrameAllocator};
+use x86_64::structures::paging::{
+    Mapper, Page, PageSize, PageTableFlags, PhysFrame, Size1GiB, Size2MiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use qxg_os::allocator;
+
+    qxg_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
+
+fn free_frames() -> usize {
+    memory::FRAME_ALLOCATOR
+        .lock()
+        .as_ref()
+        .unwrap()
+        .free_frames()
+}
+
+#[test_case]
+fn contiguous_frames() {
+    let mut guard = memory::FRAME_ALLOCATOR.lock();
+    let allocator = guard.as_mut().unwrap();
+    let free = allocator.free_frames();
+
+    let frame = allocator
+        .allocate_huge::<Size2MiB>()
+        .expect("no 2MiB frame");
+    assert_eq!(frame.start_address().as_u64() % Size2MiB::SIZE, 0);
+    assert_eq!(allocator.free_frames(), free - 512);
+
+    unsafe { allocator.deallocate_huge(frame) };
+    assert_eq!(allocator.free_frames(), free);
+}
+
+#[test_case]
+fn map_and_translate_2mib_page() {
+    let page = Page::<Size2MiB>::containing_address(VirtAddr::new(0x7100_0000_0000));
+    huge::map_huge(page, PageTableFlags::WRITABLE).unwrap();
+
+    let addr = page.start_address() + 0x12_3456u64;
+    unsafe { addr.as_mut_ptr::<u64>().write_volatile(42) };
+    let (phys, size) = huge::translate(addr).unwrap();
+    assert_eq!(size, MappedSize::Size2MiB);
+    assert_eq!(phys.as_u64() % Size2MiB::SIZE, 0x12_3456);
+    unsafe {
+        assert_eq!(
+            memory::phys_to_virt(phys).as_ptr::<u64>().read_volatile(),
+            42
+        );
+        huge::unmap_huge(page).unwrap();
+    }
+    assert!(huge::translate(addr).is_none());
+}
+
+#[test_case]
+fn huge_vm_area() {
+    let free = free_frames();
+    let area = vma::vmap(
+        "huge",
+        3 * 1024 * 1024,
+        PageTableFlags::WRITABLE,
+        Backing::Huge,
+    )
+    .unwrap();
+    // 大小向上取整到2MiB的整数倍
+    assert_eq!(area.size(), 4 * 1024 * 1024);
+    assert_eq!(area.start.as_u64() % Size2MiB::SIZE, 0);
+
+    let addr = area.start + (2 * 1024 * 1024 + 8u64);
+    unsafe {
+        assert_eq!(addr.as_ptr::<u64>().read_volatile(), 0);
+        addr.as_mut_ptr::<u64>().write_volatile(1);
+    }
+    assert_eq!(huge::translate(addr).unwrap().1, MappedSize::Size2MiB);
+
+    vma::vfree(area.start).unwrap();
+    assert_eq!(free_frames(), free);
+}
+
+#[test_case]
+fn heap_grows_with_huge_pages() {
+    use alloc::vec::Vec;
+    use qxg_os::allocator::{self, HEAP_START};
+
+    allocator::set_heap_huge_pages(true);
+    let vec: Vec<u8> = Vec::with_capacity(3 * 1024 * 1024);
+    allocator::set_heap_huge_pages(false);
+
+    // 堆起始地址之后的第一个2MiB边界开始使用大页
+    let boundary = (HEAP_START as u64 + Size2MiB::SIZE - 1) / Size2MiB::SIZE * Size2MiB::SIZE;
+    assert!(HEAP_START as u64 + allocator::heap_size() as u64 > boundary);
+    let (_, size) = huge::translate(VirtAddr::new(boundary)).unwrap();
+    assert_eq!(size, MappedSize::Size2MiB);
+    drop(vec);
+}
+
+#[test_case]
+fn map_1gib_page_if_supported() {
+    if !huge::supports_1gib_pages() {
+        return;
+    }
+    // 模拟器的内存一般不够分配1GiB的帧, 所以直接映射物理地址0开始的1GiB, 不分配也不释放帧
+    let page = Page::<Size1GiB>::containing_address(VirtAddr::new(0x7200_0000_0000));
+    let frame = PhysFrame::<Size1GiB>::containing_address(PhysAddr::new(0));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE;
+    {
+        let mut mapper = memory::MAPPER.lock();
+        let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+        let (mapper, frame_allocator) =
+            (mapper.as_mut().unwrap(), frame_allocator.as_mut().unwrap());
+        unsafe {
+            mapper
+                .map_to(page, frame, flags, frame_allocator)
+                .unwrap()
+                .flush()
+        };
+    }
+    let addr = page.start_address() + 0x1234_5678u64;
+    assert_eq!(
+        huge::translate(addr),
+        Some((PhysAddr::new(0x1234_5678), MappedSize::Size1GiB))
+    );
+    let mut mapper = memory::MAPPER.lock();
+    let (_, flush) = Mapper::<Size1GiB>::unmap(mapper.as_mut().unwrap(), page).unwrap();
+    flush.flush();
+}
@@ -0,0 +1,38 @@
+// 触发真正的无效指令, 检查报告中的异常号及错误码
+// 错误类的异常返回后会重新执行出错的指令, 所以钩子检查后直接退出qemu
+
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+use core::panic::PanicInfo;
+use qxg_os::interrupts::exceptions::{self, FaultReport};
+use qxg_os::{exit_qemu, hlt_loop, serial_print, serial_println, QemuExitCode};
+
+fn check_report(report: &FaultReport) -> bool {
+    if report.vector != 6 {
+        panic!("unexpected exception\n{}", report);
+    }
+    assert_eq!(report.error_code, None);
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    hlt_loop();
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("invalid_opcode::invalid_opcode...\t");
+
+    qxg_os::init();
+    exceptions::set_fault_hook(Some(check_report));
+
+    // ud2是专门用来产生无效指令异常的指令
+    unsafe { asm!("ud2") };
+
+    panic!("Execution continued after invalid opcode");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
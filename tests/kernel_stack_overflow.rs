@@ -0,0 +1,55 @@
+// 在带保护页的启动栈上触发栈溢出, 二重中断应该在新分配的ist栈上执行, 并报告溢出的是启动栈
+
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use qxg_os::interrupts::exceptions::{self, FaultReport};
+use qxg_os::memory::{self, BitmapFrameAllocator};
+use qxg_os::{allocator, exit_qemu, hlt_loop, serial_print, serial_println, QemuExitCode};
+use x86_64::VirtAddr;
+
+fn check_report(report: &FaultReport) -> bool {
+    if report.vector != 8 {
+        panic!("unexpected exception\n{}", report);
+    }
+    assert_eq!(report.stack_overflow, Some("boot"));
+    assert_eq!(memory::stack::overflow_cause(), Some("boot"));
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    hlt_loop();
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("kernel_stack_overflow::kernel_stack_overflow...\t");
+
+    qxg_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+
+    memory::stack::guard_boot_stack().expect("boot stack guard page not found");
+    qxg_os::gdt::install_guarded_stacks().expect("failed to allocate interrupt stacks");
+    exceptions::set_fault_hook(Some(check_report));
+
+    stack_overflow();
+
+    panic!("Execution continued after stack overflow");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
+
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+    stack_overflow(); // for each recursion, the return address is pushed
+    volatile::Volatile::new(0).read(); // prevent tail recursion optimizations
+}
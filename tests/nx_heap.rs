@@ -0,0 +1,63 @@
+// 堆被映射为不可执行, 跳转到堆上的代码应该产生取指令引起的缺页
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use qxg_os::interrupts::exceptions::{self, FaultReport};
+use qxg_os::memory::{self, BitmapFrameAllocator};
+use qxg_os::{allocator, exit_qemu, hlt_loop, serial_print, serial_println, QemuExitCode};
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::VirtAddr;
+
+// 放在堆上的代码的地址
+static CODE_ADDR: AtomicU64 = AtomicU64::new(0);
+
+fn check_report(report: &FaultReport) -> bool {
+    if report.vector != 14 {
+        panic!("unexpected exception\n{}", report);
+    }
+    let error_code = PageFaultErrorCode::from_bits_truncate(report.error_code.unwrap_or(0));
+    assert!(error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH));
+    assert!(error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION));
+    assert_eq!(Cr2::read().as_u64(), CODE_ADDR.load(Ordering::SeqCst));
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    hlt_loop();
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("nx_heap::execute_from_heap...\t");
+
+    qxg_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    memory::protect::protect_kernel().expect("failed to protect kernel sections");
+    exceptions::set_fault_hook(Some(check_report));
+
+    // 0xc3是ret指令
+    let code = Box::new([0xc3u8; 16]);
+    let addr = code.as_ptr() as u64;
+    CODE_ADDR.store(addr, Ordering::SeqCst);
+    let f: extern "C" fn() = unsafe { core::mem::transmute(addr) };
+    f();
+
+    panic!("Execution continued after executing heap memory");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
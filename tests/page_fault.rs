@@ -0,0 +1,167 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(qxg_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use qxg_os::memory::fault::{self, FaultError, FaultRegion, PageFault};
+use qxg_os::memory::{self, BitmapFrameAllocator};
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::structures::paging::{
+    FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB, Translate,
+};
+use x86_64::VirtAddr;
+
+const PAGE_SIZE: u64 = Page::<Size4KiB>::SIZE;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use qxg_os::allocator;
+
+    qxg_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
+
+fn region(name: &'static str, start: u64, pages: u64, handler: fault::FaultHandler) -> FaultRegion {
+    FaultRegion {
+        name,
+        start: VirtAddr::new(start),
+        end: VirtAddr::new(start + pages * PAGE_SIZE),
+        flags: PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+        handler,
+    }
+}
+
+#[test_case]
+fn zero_fill_on_first_touch() {
+    let start = 0x5555_0000_0000;
+    fault::register_region(region("zero", start, 4, fault::zero_fill)).unwrap();
+
+    let used = memory::FRAME_ALLOCATOR
+        .lock()
+        .as_ref()
+        .unwrap()
+        .used_frames();
+    // 读和写都会触发缺页, 读到的是0
+    let first = start as *mut u64;
+    let last = (start + 3 * PAGE_SIZE) as *mut u64;
+    unsafe {
+        assert_eq!(first.read_volatile(), 0);
+        last.write_volatile(42);
+        assert_eq!(last.read_volatile(), 42);
+    }
+    // 只有访问过的两页分配了帧(可能还有新建的页表)
+    let used_now = memory::FRAME_ALLOCATOR
+        .lock()
+        .as_ref()
+        .unwrap()
+        .used_frames();
+    assert!(used_now >= used + 2);
+
+    let mapper = memory::MAPPER.lock();
+    let mapper = mapper.as_ref().unwrap();
+    assert!(mapper
+        .translate_addr(VirtAddr::new(start + PAGE_SIZE))
+        .is_none());
+    fault::unregister_region(VirtAddr::new(start)).unwrap();
+}
+
+#[test_case]
+fn copy_on_write_copies_frame() {
+    let start = 0x5556_0000_0000;
+    let page = Page::<Size4KiB>::containing_address(VirtAddr::new(start));
+
+    // 以只读方式映射一个帧, 并通过物理内存映射写入内容
+    let frame = {
+        let mut mapper = memory::MAPPER.lock();
+        let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+        let (mapper, frame_allocator) =
+            (mapper.as_mut().unwrap(), frame_allocator.as_mut().unwrap());
+        let frame = frame_allocator.allocate_frame().unwrap();
+        unsafe {
+            memory::phys_to_virt(frame.start_address())
+                .as_mut_ptr::<u64>()
+                .write(7);
+            mapper
+                .map_to(page, frame, PageTableFlags::PRESENT, frame_allocator)
+                .unwrap()
+                .flush();
+        }
+        frame
+    };
+    fault::register_region(region("cow", start, 1, fault::copy_on_write)).unwrap();
+
+    let ptr = start as *mut u64;
+    unsafe {
+        assert_eq!(ptr.read_volatile(), 7);
+        ptr.write_volatile(8);
+        assert_eq!(ptr.read_volatile(), 8);
+        // 原来的帧没有被修改
+        assert_eq!(
+            memory::phys_to_virt(frame.start_address())
+                .as_ptr::<u64>()
+                .read(),
+            7
+        );
+    }
+    let mapper = memory::MAPPER.lock();
+    let copied = mapper
+        .as_ref()
+        .unwrap()
+        .translate_addr(VirtAddr::new(start));
+    assert_ne!(copied, Some(frame.start_address()));
+    fault::unregister_region(VirtAddr::new(start)).unwrap();
+}
+
+#[test_case]
+fn unresolved_faults() {
+    let start = 0x5557_0000_0000;
+    fault::register_region(region("guard", start, 1, fault::guard)).unwrap();
+
+    let page_fault = PageFault {
+        addr: VirtAddr::new(start + 8),
+        error_code: PageFaultErrorCode::CAUSED_BY_WRITE,
+    };
+    assert_eq!(
+        fault::resolve(&page_fault),
+        Err(FaultError::GuardPage("guard"))
+    );
+
+    let page_fault = PageFault {
+        addr: VirtAddr::new(start + PAGE_SIZE),
+        error_code: PageFaultErrorCode::empty(),
+    };
+    assert_eq!(fault::resolve(&page_fault), Err(FaultError::Unclaimed));
+    fault::unregister_region(VirtAddr::new(start)).unwrap();
+}
+
+#[test_case]
+fn overlapping_regions_are_rejected() {
+    let start = 0x5558_0000_0000;
+    fault::register_region(region("a", start, 2, fault::zero_fill)).unwrap();
+    assert_eq!(
+        fault::register_region(region("b", start + PAGE_SIZE, 2, fault::zero_fill)),
+        Err(fault::RegionError::Overlap)
+    );
+    assert_eq!(
+        fault::register_region(region("c", start + 1, 1, fault::zero_fill)),
+        Err(fault::RegionError::Invalid)
+    );
+    fault::unregister_region(VirtAddr::new(start)).unwrap();
+}
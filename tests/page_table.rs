@@ -0,0 +1,112 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(qxg_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use qxg_os::memory::dump::{self, Mapping, PageSize};
+use qxg_os::memory::{self, BitmapFrameAllocator};
+use x86_64::structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use qxg_os::allocator;
+
+    qxg_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
+
+// 包含addr的合并后的映射
+fn find_mapping(addr: VirtAddr) -> Option<Mapping> {
+    let mut found = None;
+    dump::for_each_mapping(dump::active_level_4_table(), &mut |mapping| {
+        if mapping.start <= addr && addr < mapping.end() {
+            found = Some(*mapping);
+        }
+    });
+    found
+}
+
+#[test_case]
+fn heap_is_mapped() {
+    use qxg_os::allocator::HEAP_START;
+
+    let mapping = find_mapping(VirtAddr::new(HEAP_START as u64)).expect("heap not mapped");
+    assert_eq!(mapping.page_size, PageSize::Size4KiB);
+    assert!(mapping.flags.contains(PageTableFlags::WRITABLE));
+    assert!(!mapping.flags.contains(PageTableFlags::USER_ACCESSIBLE));
+}
+
+#[test_case]
+fn contiguous_pages_are_merged() {
+    let start = VirtAddr::new(0x7777_0000_0000);
+    let page = Page::<Size4KiB>::containing_address(start);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE;
+
+    {
+        let mut mapper = memory::MAPPER.lock();
+        let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+        let (mapper, frame_allocator) =
+            (mapper.as_mut().unwrap(), frame_allocator.as_mut().unwrap());
+        // 位图帧分配器从低到高分配, 连续两次分配一般得到相邻的帧
+        let first = frame_allocator.allocate_frame().unwrap();
+        let second = frame_allocator.allocate_frame().unwrap();
+        assert_eq!(second, first + 1, "frames are not contiguous");
+        unsafe {
+            mapper
+                .map_to(page, first, flags, frame_allocator)
+                .unwrap()
+                .flush();
+            mapper
+                .map_to(page + 1, second, flags, frame_allocator)
+                .unwrap()
+                .flush();
+        }
+    }
+
+    let mapping = find_mapping(start).unwrap();
+    assert_eq!(mapping.start, start);
+    assert_eq!(mapping.size, 2 * 4096);
+    assert!(!mapping.flags.contains(PageTableFlags::WRITABLE));
+    assert!(mapping.flags.contains(PageTableFlags::NO_EXECUTE));
+
+    let mut mapper = memory::MAPPER.lock();
+    let mapper = mapper.as_mut().unwrap();
+    for page in Page::range(page, page + 2) {
+        mapper.unmap(page).unwrap().1.flush();
+    }
+}
+
+#[test_case]
+fn walk_is_sorted() {
+    let mut last = None;
+    dump::walk(dump::active_level_4_table(), &mut |mapping| {
+        if let Some(last) = last {
+            assert!(last <= mapping.start);
+        }
+        last = Some(mapping.end());
+    });
+    assert!(last.is_some());
+}
+
+#[test_case]
+fn dump_active_table() {
+    dump::dump_active();
+}
@@ -0,0 +1,108 @@
+// 加载一个ELF程序并在用户态运行, 程序检查栈上的参数, 数据段及bss, 全部正确时exit(0), 否则以出错的检查点编号exit
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+mod elf_image;
+mod user_program;
+
+use bootloader::{entry_point, BootInfo};
+use core::arch::global_asm;
+use core::panic::PanicInfo;
+use qxg_os::{loader, serial_print, syscall};
+
+// 程序的代码, 数据段在0x100000002000, bss在0x100000003000(见elf_image)
+global_asm!(
+    ".global user_elf_start",
+    ".global user_elf_end",
+    "user_elf_start:",
+    // 1: 栈按16字节对齐, argc为2
+    "mov ebx, 1",
+    "test rsp, 0xf",
+    "jnz 9f",
+    "cmp qword ptr [rsp], 2",
+    "jne 9f",
+    // 2: argv[1]为"arg", argv[2]为NULL
+    "mov ebx, 2",
+    "mov rsi, [rsp + 16]",
+    "cmp dword ptr [rsi], 0x677261",
+    "jne 9f",
+    "cmp qword ptr [rsp + 24], 0",
+    "jne 9f",
+    // 3: 只有一个环境变量
+    "mov ebx, 3",
+    "cmp qword ptr [rsp + 32], 0",
+    "je 9f",
+    "cmp qword ptr [rsp + 40], 0",
+    "jne 9f",
+    // 4: auxv中AT_PAGESZ为4096
+    "mov ebx, 4",
+    "lea rcx, [rsp + 48]",
+    "2:",
+    "mov rax, [rcx]",
+    "test rax, rax",
+    "jz 9f",
+    "add rcx, 16",
+    "cmp rax, 6",
+    "jne 2b",
+    "cmp qword ptr [rcx - 8], 4096",
+    "jne 9f",
+    // 5: 数据段的内容来自文件, bss为0且可写
+    "mov ebx, 5",
+    "mov rcx, 0x100000002000",
+    "mov rax, 0x1122334455667788",
+    "cmp [rcx], rax",
+    "jne 9f",
+    "mov rcx, 0x100000003000",
+    "cmp qword ptr [rcx], 0",
+    "jne 9f",
+    "mov qword ptr [rcx], 1",
+    // 6: 通过系统调用输出
+    "mov ebx, 6",
+    "mov rdi, [rsp + 8]",
+    "mov esi, 4",
+    "mov eax, 0",
+    "syscall",
+    "cmp rax, 4",
+    "jne 9f",
+    "xor ebx, ebx",
+    "9:",
+    "mov edi, ebx",
+    "mov eax, 1",
+    "syscall",
+    "ud2",
+    "user_elf_end:",
+);
+
+extern "C" {
+    static user_elf_start: u8;
+    static user_elf_end: u8;
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("run_program::run_elf_in_user_mode...\t");
+
+    user_program::init(boot_info);
+    let text = unsafe {
+        user_program::code_between(
+            core::ptr::addr_of!(user_elf_start),
+            core::ptr::addr_of!(user_elf_end),
+        )
+    };
+    let data = 0x1122_3344_5566_7788u64.to_le_bytes();
+    let image = elf_image::build(text, &data);
+    let program = loader::load(&image, &["prog", "arg"], &["HOME=/"]).expect("failed to load");
+
+    user_program::set_kernel_stack();
+    syscall::set_exit_handler(Some(user_program::exit_handler));
+    unsafe { program.run() }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
@@ -0,0 +1,197 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(qxg_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use qxg_os::memory::{self, BitmapFrameAllocator};
+use qxg_os::thread::{self, ThreadError, ThreadState, PRIORITY_LEVELS};
+use qxg_os::{time, wait_until};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use qxg_os::allocator;
+
+    qxg_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    thread::init().expect("failed to start threads");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn main_thread_sleeps() {
+    // 没有其他线程, 休眠期间运行空闲线程
+    let start = time::ticks();
+    thread::sleep(5);
+    assert!(time::ticks() - start >= 5);
+    assert_eq!(
+        thread::state(thread::current().unwrap()),
+        Some(ThreadState::Running)
+    );
+}
+
+#[test_case]
+fn sleeping_thread_wakes_after_ticks() {
+    static STARTED: AtomicBool = AtomicBool::new(false);
+    static ELAPSED: AtomicU64 = AtomicU64::new(0);
+
+    fn sleeper() {
+        let start = time::ticks();
+        STARTED.store(true, Ordering::SeqCst);
+        thread::sleep(10);
+        ELAPSED.store(time::ticks() - start, Ordering::SeqCst);
+    }
+
+    let id = thread::spawn(sleeper).unwrap();
+    assert!(wait_until(5000, || STARTED.load(Ordering::SeqCst)));
+    assert!(wait_until(5000, || thread::state(id) == Some(ThreadState::Sleeping)));
+    thread::join(id).unwrap();
+    assert!(ELAPSED.load(Ordering::SeqCst) >= 10);
+}
+
+#[test_case]
+fn join_waits_for_exit() {
+    static DONE: AtomicBool = AtomicBool::new(false);
+
+    fn worker() {
+        thread::sleep(2);
+        DONE.store(true, Ordering::SeqCst);
+    }
+
+    let id = thread::spawn(worker).unwrap();
+    thread::join(id).unwrap();
+    assert!(DONE.load(Ordering::SeqCst));
+    assert_eq!(thread::state(id), Some(ThreadState::Exited));
+    // 已经结束的线程可以再次join
+    thread::join(id).unwrap();
+    thread::reap();
+    assert_eq!(thread::state(id), None);
+}
+
+#[test_case]
+fn join_self_is_deadlock() {
+    let id = thread::current().unwrap();
+    assert_eq!(thread::join(id), Err(ThreadError::Deadlock));
+}
+
+#[test_case]
+fn park_blocks_until_unpark() {
+    static WOKEN: AtomicBool = AtomicBool::new(false);
+
+    fn parker() {
+        thread::park();
+        WOKEN.store(true, Ordering::SeqCst);
+    }
+
+    let id = thread::spawn(parker).unwrap();
+    assert!(wait_until(5000, || thread::state(id) == Some(ThreadState::Blocked)));
+    // 阻塞的线程不会被时钟中断调度
+    thread::sleep(3);
+    assert!(!WOKEN.load(Ordering::SeqCst));
+
+    assert!(thread::unpark(id));
+    thread::join(id).unwrap();
+    assert!(WOKEN.load(Ordering::SeqCst));
+    assert!(!thread::unpark(id));
+}
+
+#[test_case]
+fn unpark_before_park_is_remembered() {
+    let id = thread::current().unwrap();
+    assert!(thread::unpark(id));
+    // 直接返回, 不会阻塞
+    thread::park();
+}
+
+#[test_case]
+fn unpark_while_ready_is_remembered() {
+    static CHECKED: AtomicBool = AtomicBool::new(false);
+    static UNPARKED: AtomicBool = AtomicBool::new(false);
+    static WOKEN: AtomicBool = AtomicBool::new(false);
+
+    // 检查完等待的条件之后, park之前一直忙等, 只能被时钟中断抢占, 此时在运行队列中
+    fn parker() {
+        CHECKED.store(true, Ordering::SeqCst);
+        while !UNPARKED.load(Ordering::SeqCst) {}
+        thread::park();
+        WOKEN.store(true, Ordering::SeqCst);
+    }
+
+    let id = thread::spawn(parker).unwrap();
+    assert!(wait_until(5000, || CHECKED.load(Ordering::SeqCst)));
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        assert_eq!(thread::state(id), Some(ThreadState::Ready));
+        assert!(thread::unpark(id));
+        UNPARKED.store(true, Ordering::SeqCst);
+    });
+    // unpark没有丢失, park直接返回
+    assert!(wait_until(5000, || WOKEN.load(Ordering::SeqCst)));
+    thread::join(id).unwrap();
+}
+
+#[test_case]
+fn invalid_priority_is_rejected() {
+    fn nothing() {}
+
+    assert_eq!(
+        thread::spawn_with_priority(nothing, PRIORITY_LEVELS as u8),
+        Err(ThreadError::InvalidPriority)
+    );
+}
+
+static SEQUENCE: AtomicU64 = AtomicU64::new(1);
+static LOW_RAN_AT: AtomicU64 = AtomicU64::new(0);
+static HIGH_RAN_AT: AtomicU64 = AtomicU64::new(0);
+
+fn low() {
+    LOW_RAN_AT.store(SEQUENCE.fetch_add(1, Ordering::SeqCst), Ordering::SeqCst);
+}
+
+fn high() {
+    HIGH_RAN_AT.store(SEQUENCE.fetch_add(1, Ordering::SeqCst), Ordering::SeqCst);
+}
+
+// 先创建低优先级的线程, 再创建高优先级的线程, 然后阻塞在join中
+fn run_low_then_high() -> (u64, u64) {
+    let low = thread::spawn_with_priority(low, 1).unwrap();
+    let high = thread::spawn_with_priority(high, PRIORITY_LEVELS as u8 - 1).unwrap();
+    thread::join(low).unwrap();
+    thread::join(high).unwrap();
+    (
+        LOW_RAN_AT.load(Ordering::SeqCst),
+        HIGH_RAN_AT.load(Ordering::SeqCst),
+    )
+}
+
+#[cfg(feature = "sched-round-robin")]
+#[test_case]
+fn round_robin_runs_in_spawn_order() {
+    let (low, high) = run_low_then_high();
+    assert!(low < high);
+}
+
+#[cfg(feature = "sched-priority")]
+#[test_case]
+fn priority_runs_highest_first() {
+    let (low, high) = run_low_then_high();
+    assert!(high < low);
+}
@@ -0,0 +1,42 @@
+// 触发真正的栈段错误, 检查报告中的异常号及错误码
+// 错误类的异常返回后会重新执行出错的指令, 所以钩子检查后直接退出qemu
+
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+use core::panic::PanicInfo;
+use qxg_os::interrupts::exceptions::{self, FaultReport};
+use qxg_os::{exit_qemu, hlt_loop, serial_print, serial_println, QemuExitCode};
+
+fn check_report(report: &FaultReport) -> bool {
+    if report.vector != 12 {
+        panic!("unexpected exception\n{}", report);
+    }
+    // 非规范地址导致的栈段错误, 错误码为0
+    assert_eq!(report.error_code, Some(0));
+    assert_eq!(report.selector_error(), None);
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    hlt_loop();
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("stack_segment_fault::stack_segment_fault...\t");
+
+    qxg_os::init();
+    exceptions::set_fault_hook(Some(check_report));
+
+    // 以rbp为基址访问非规范地址(bit 47~63不一致), 产生的是栈段错误而不是一般保护错误
+    unsafe {
+        asm!("mov {0}, [rbp + {0}]", inout(reg) 0x8000_0000_0000_0000u64 => _);
+    }
+
+    panic!("Execution continued after stack-segment fault");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
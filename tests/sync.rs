@@ -0,0 +1,328 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(qxg_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use qxg_os::memory::{self, BitmapFrameAllocator};
+use qxg_os::sync::{Condvar, Mutex, RwLock, Semaphore, WaitQueue};
+use qxg_os::thread::{self, ThreadId, ThreadState};
+use qxg_os::{time, wait_until};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use qxg_os::allocator;
+
+    qxg_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    thread::init().expect("failed to start threads");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
+
+// 创建线程并等待它阻塞, 依次调用时线程按创建的顺序排队
+fn spawn_blocked(entry: fn()) -> ThreadId {
+    let id = thread::spawn(entry).unwrap();
+    assert!(wait_until(5000, || thread::state(id) == Some(ThreadState::Blocked)));
+    id
+}
+
+fn join_all(ids: &[ThreadId]) {
+    for &id in ids {
+        thread::join(id).unwrap();
+    }
+}
+
+// 记录线程获取锁或被唤醒的顺序
+static ORDER: spin::Mutex<Vec<ThreadId>> = spin::Mutex::new(Vec::new());
+
+fn record() {
+    let id = thread::current().unwrap();
+    x86_64::instructions::interrupts::without_interrupts(|| ORDER.lock().push(id));
+}
+
+fn take_order() -> Vec<ThreadId> {
+    x86_64::instructions::interrupts::without_interrupts(|| core::mem::take(&mut *ORDER.lock()))
+}
+
+#[test_case]
+fn mutex_provides_mutual_exclusion() {
+    static COUNTER: Mutex<u64> = Mutex::new(0);
+
+    // 读出和写回之间让出cpu, 没有互斥时会丢失更新
+    fn increment() {
+        for _ in 0..100 {
+            let mut counter = COUNTER.lock();
+            let value = *counter;
+            thread::yield_now();
+            *counter = value + 1;
+        }
+    }
+
+    let ids: Vec<_> = (0..4).map(|_| thread::spawn(increment).unwrap()).collect();
+    join_all(&ids);
+    assert_eq!(*COUNTER.lock(), 400);
+    assert!(!COUNTER.is_locked());
+}
+
+#[test_case]
+fn mutex_hands_off_in_arrival_order() {
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    fn contend() {
+        let _guard = LOCK.lock();
+        record();
+    }
+
+    let guard = LOCK.lock();
+    let ids: Vec<_> = (0..3).map(|_| spawn_blocked(contend)).collect();
+    // 有线程在排队时不能插队
+    drop(guard);
+    assert!(LOCK.try_lock().is_none());
+    join_all(&ids);
+    assert_eq!(take_order(), ids);
+    assert!(LOCK.try_lock().is_some());
+}
+
+#[test_case]
+fn wait_queue_wakes_in_fifo_order() {
+    static QUEUE: WaitQueue = WaitQueue::new();
+
+    fn waiter() {
+        QUEUE.wait();
+        record();
+    }
+
+    let ids: Vec<_> = (0..3).map(|_| spawn_blocked(waiter)).collect();
+    assert_eq!(QUEUE.len(), 3);
+    for _ in 0..3 {
+        assert!(QUEUE.notify_one());
+        // 每次只唤醒一个
+        thread::sleep(2);
+    }
+    assert!(!QUEUE.notify_one());
+    join_all(&ids);
+    assert_eq!(take_order(), ids);
+}
+
+#[test_case]
+fn wait_queue_notify_all() {
+    static QUEUE: WaitQueue = WaitQueue::new();
+
+    fn waiter() {
+        QUEUE.wait();
+        record();
+    }
+
+    let ids: Vec<_> = (0..3).map(|_| spawn_blocked(waiter)).collect();
+    assert_eq!(QUEUE.notify_all(), 3);
+    assert!(QUEUE.is_empty());
+    join_all(&ids);
+    assert_eq!(take_order(), ids);
+}
+
+#[test_case]
+fn spurious_unpark_does_not_end_wait() {
+    static QUEUE: WaitQueue = WaitQueue::new();
+    static WOKEN: AtomicBool = AtomicBool::new(false);
+
+    fn waiter() {
+        QUEUE.wait();
+        WOKEN.store(true, Ordering::SeqCst);
+    }
+
+    let id = spawn_blocked(waiter);
+    thread::unpark(id);
+    thread::sleep(3);
+    assert!(!WOKEN.load(Ordering::SeqCst));
+    assert!(QUEUE.notify_one());
+    thread::join(id).unwrap();
+    assert!(WOKEN.load(Ordering::SeqCst));
+}
+
+#[test_case]
+fn semaphore_limits_concurrency() {
+    static SEMAPHORE: Semaphore = Semaphore::new(2);
+    static ACTIVE: AtomicUsize = AtomicUsize::new(0);
+    static MAX_ACTIVE: AtomicUsize = AtomicUsize::new(0);
+
+    fn worker() {
+        SEMAPHORE.acquire();
+        let active = ACTIVE.fetch_add(1, Ordering::SeqCst) + 1;
+        MAX_ACTIVE.fetch_max(active, Ordering::SeqCst);
+        thread::sleep(2);
+        ACTIVE.fetch_sub(1, Ordering::SeqCst);
+        SEMAPHORE.release();
+    }
+
+    let ids: Vec<_> = (0..5).map(|_| thread::spawn(worker).unwrap()).collect();
+    join_all(&ids);
+    assert_eq!(MAX_ACTIVE.load(Ordering::SeqCst), 2);
+    assert_eq!(SEMAPHORE.available(), 2);
+}
+
+#[test_case]
+fn semaphore_hands_permits_to_waiters_in_order() {
+    static SEMAPHORE: Semaphore = Semaphore::new(0);
+
+    fn worker() {
+        SEMAPHORE.acquire();
+        record();
+    }
+
+    assert!(!SEMAPHORE.try_acquire());
+    let ids: Vec<_> = (0..3).map(|_| spawn_blocked(worker)).collect();
+    for _ in 0..3 {
+        SEMAPHORE.release();
+    }
+    // 许可都直接交给了等待的线程
+    assert_eq!(SEMAPHORE.available(), 0);
+    join_all(&ids);
+    assert_eq!(take_order(), ids);
+}
+
+#[test_case]
+fn semaphore_ping_pong_under_preemption() {
+    static PING: Semaphore = Semaphore::new(0);
+    static PONG: Semaphore = Semaphore::new(0);
+    static ROUNDS: AtomicUsize = AtomicUsize::new(0);
+    static STOP: AtomicBool = AtomicBool::new(false);
+
+    // 每轮之间忙等不同的时间, 让时钟中断落在排队与park之间等各个位置
+    fn spin(round: usize) {
+        for _ in 0..round % 5000 {
+            core::hint::spin_loop();
+        }
+    }
+
+    fn ponger() {
+        let mut round = 0;
+        loop {
+            PING.acquire();
+            if STOP.load(Ordering::SeqCst) {
+                return;
+            }
+            spin(round * 7);
+            round += 1;
+            ROUNDS.store(round, Ordering::SeqCst);
+            PONG.release();
+        }
+    }
+
+    let id = thread::spawn(ponger).unwrap();
+    let deadline = time::ticks() + 40;
+    let mut round = 0;
+    while time::ticks() < deadline {
+        PING.release();
+        spin(round * 13);
+        // 丢失唤醒时两个线程会永远等待
+        PONG.acquire();
+        round += 1;
+    }
+    assert_eq!(ROUNDS.load(Ordering::SeqCst), round);
+    STOP.store(true, Ordering::SeqCst);
+    PING.release();
+    thread::join(id).unwrap();
+}
+
+#[test_case]
+fn condvar_signals_waiting_consumer() {
+    static VALUE: Mutex<Option<u32>> = Mutex::new(None);
+    static READY: Condvar = Condvar::new();
+    static RECEIVED: AtomicUsize = AtomicUsize::new(0);
+
+    fn consumer() {
+        let value = READY.wait_while(VALUE.lock(), |value| value.is_none());
+        RECEIVED.store(value.unwrap() as usize, Ordering::SeqCst);
+    }
+
+    let id = spawn_blocked(consumer);
+    // 等待时已经释放了互斥锁
+    *VALUE.lock() = Some(7);
+    assert!(READY.notify_one());
+    thread::join(id).unwrap();
+    assert_eq!(RECEIVED.load(Ordering::SeqCst), 7);
+}
+
+#[test_case]
+fn condvar_wakes_in_wait_order() {
+    static LOCK: Mutex<()> = Mutex::new(());
+    static CONDVAR: Condvar = Condvar::new();
+
+    fn waiter() {
+        let guard = LOCK.lock();
+        let _guard = CONDVAR.wait(guard);
+        record();
+    }
+
+    let ids: Vec<_> = (0..3).map(|_| spawn_blocked(waiter)).collect();
+    for _ in 0..3 {
+        assert!(CONDVAR.notify_one());
+        thread::sleep(2);
+    }
+    join_all(&ids);
+    assert_eq!(take_order(), ids);
+}
+
+#[test_case]
+fn rwlock_allows_concurrent_readers() {
+    static LOCK: RwLock<u32> = RwLock::new(5);
+    static READ: AtomicUsize = AtomicUsize::new(0);
+
+    fn reader() {
+        READ.store(*LOCK.read() as usize, Ordering::SeqCst);
+    }
+
+    let guard = LOCK.read();
+    // 主线程持有读锁时, 其他读者不会阻塞
+    let id = thread::spawn(reader).unwrap();
+    thread::join(id).unwrap();
+    assert_eq!(READ.load(Ordering::SeqCst), 5);
+    assert_eq!(LOCK.readers(), 1);
+    drop(guard);
+    assert_eq!(LOCK.readers(), 0);
+}
+
+#[test_case]
+fn rwlock_writer_is_not_starved_by_readers() {
+    static LOCK: RwLock<u32> = RwLock::new(0);
+
+    fn writer() {
+        let mut value = LOCK.write();
+        *value += 1;
+        record();
+    }
+
+    fn reader() {
+        let value = LOCK.read();
+        assert_eq!(*value, 1);
+        record();
+    }
+
+    let guard = LOCK.read();
+    let writer = spawn_blocked(writer);
+    // 写者在等待时, 新的读者排在它后面
+    let reader = spawn_blocked(reader);
+    drop(guard);
+    join_all(&[writer, reader]);
+    assert_eq!(take_order(), [writer, reader]);
+}
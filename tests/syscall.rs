@@ -0,0 +1,151 @@
+// 在用户态依次调用每一个系统调用并检查返回值, 全部通过时exit(0), 否则以出错的检查点编号exit
+// 用户程序用汇编编写, 只使用相对rip的寻址, 整段复制到用户页中执行
+
+#![no_std]
+#![no_main]
+
+mod user_program;
+
+use bootloader::{entry_point, BootInfo};
+use core::arch::global_asm;
+use core::panic::PanicInfo;
+use qxg_os::{serial_print, syscall};
+
+global_asm!(
+    ".global user_program_start",
+    ".global user_program_end",
+    "user_program_start:",
+    // 1: write返回写入的字节数, 调用前后r12不变
+    "mov r12, 0x1234",
+    "lea rdi, [rip + message]",
+    "mov esi, 14",
+    "mov eax, 0",
+    "syscall",
+    "mov ebx, 1",
+    "cmp rax, 14",
+    "jne 9f",
+    "cmp r12, 0x1234",
+    "jne 9f",
+    // 2: time和sleep, 休眠100毫秒后时间至少增加100
+    "mov eax, 3",
+    "syscall",
+    "mov r13, rax",
+    "mov edi, 100",
+    "mov eax, 2",
+    "syscall",
+    "mov ebx, 2",
+    "test rax, rax",
+    "jnz 9f",
+    "mov eax, 3",
+    "syscall",
+    "sub rax, r13",
+    "cmp rax, 100",
+    "jb 9f",
+    // 3: mmap一页可写内存并写入, 再munmap, 地址为USER_START + 0x20_0000
+    "mov rdi, 0x100000200000",
+    "mov esi, 4096",
+    "mov edx, 3",
+    "mov eax, 4",
+    "syscall",
+    "mov ebx, 3",
+    "cmp rax, rdi",
+    "jne 9f",
+    "mov qword ptr [rdi], 42",
+    "cmp qword ptr [rdi], 42",
+    "jne 9f",
+    "mov esi, 4096",
+    "mov eax, 5",
+    "syscall",
+    "test rax, rax",
+    "jnz 9f",
+    // 4: 可写又可执行的mmap被拒绝(-EINVAL)
+    "mov esi, 4096",
+    "mov edx, 6",
+    "mov eax, 4",
+    "syscall",
+    "mov ebx, 4",
+    "cmp rax, -22",
+    "jne 9f",
+    // 5: write内核地址返回-EFAULT
+    "mov rdi, 0xffff800000000000",
+    "mov esi, 4",
+    "mov eax, 0",
+    "syscall",
+    "mov ebx, 5",
+    "cmp rax, -14",
+    "jne 9f",
+    // 6: 不存在的调用返回-ENOSYS
+    "mov eax, 999",
+    "syscall",
+    "mov ebx, 6",
+    "cmp rax, -38",
+    "jne 9f",
+    // 7: 通过int 0x80调用write
+    "lea rdi, [rip + message]",
+    "mov esi, 14",
+    "mov eax, 0",
+    "int 0x80",
+    "mov ebx, 7",
+    "cmp rax, 14",
+    "jne 9f",
+    // 8: 长度接近u64::MAX的mmap及munmap返回-EINVAL, 而不是让内核溢出
+    "mov rdi, 0x100000200000",
+    "mov rsi, -1",
+    "mov edx, 3",
+    "mov eax, 4",
+    "syscall",
+    "mov ebx, 8",
+    "cmp rax, -22",
+    "jne 9f",
+    "mov rsi, -1",
+    "mov eax, 5",
+    "syscall",
+    "cmp rax, -22",
+    "jne 9f",
+    // 9: 超过MMAP_MAX_PAGES的mmap返回-ENOMEM, 地址范围本身是合法的
+    "mov esi, 0x401000",
+    "mov edx, 3",
+    "mov eax, 4",
+    "syscall",
+    "mov ebx, 9",
+    "cmp rax, -12",
+    "jne 9f",
+    // 全部通过
+    "xor ebx, ebx",
+    "9:",
+    "mov edi, ebx",
+    "mov eax, 1",
+    "syscall",
+    "ud2",
+    "message:",
+    ".ascii \"hello, kernel\\n\"",
+    "user_program_end:",
+);
+
+extern "C" {
+    static user_program_start: u8;
+    static user_program_end: u8;
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("syscall::syscalls_from_user_mode...\t");
+
+    user_program::init(boot_info);
+    let code = unsafe {
+        user_program::code_between(
+            core::ptr::addr_of!(user_program_start),
+            core::ptr::addr_of!(user_program_end),
+        )
+    };
+    let space = user_program::load(code);
+    user_program::set_kernel_stack();
+    syscall::set_exit_handler(Some(user_program::exit_handler));
+    unsafe { user_program::run(space) }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
@@ -0,0 +1,158 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(qxg_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use qxg_os::memory::{self, BitmapFrameAllocator};
+use qxg_os::thread;
+use qxg_os::wait_until;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use qxg_os::allocator;
+
+    qxg_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    thread::init().expect("failed to start threads");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
+
+const WORKERS: usize = 4;
+
+static COUNTERS: [AtomicU64; WORKERS] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+static NEXT_SLOT: AtomicUsize = AtomicUsize::new(0);
+static STOP: AtomicBool = AtomicBool::new(false);
+static STOPPED: AtomicUsize = AtomicUsize::new(0);
+
+// 一直增加自己的计数器, 从不主动让出cpu, 只能靠时钟中断切换到其他线程
+fn worker() {
+    let slot = NEXT_SLOT.fetch_add(1, Ordering::SeqCst);
+    while !STOP.load(Ordering::SeqCst) {
+        COUNTERS[slot].fetch_add(1, Ordering::Relaxed);
+    }
+    STOPPED.fetch_add(1, Ordering::SeqCst);
+}
+
+fn snapshot() -> [u64; WORKERS] {
+    let mut values = [0; WORKERS];
+    for (value, counter) in values.iter_mut().zip(COUNTERS.iter()) {
+        *value = counter.load(Ordering::Relaxed);
+    }
+    values
+}
+
+#[test_case]
+fn main_thread_is_thread_zero() {
+    assert_eq!(thread::current().map(|id| id.as_u64()), Some(0));
+}
+
+#[test_case]
+fn preempted_threads_all_make_progress() {
+    let mut ids = [None; WORKERS];
+    for id in ids.iter_mut() {
+        *id = Some(thread::spawn(worker).expect("spawn failed"));
+    }
+    // 线程id各不相同
+    for (i, a) in ids.iter().enumerate() {
+        assert!(ids[i + 1..].iter().all(|b| a != b));
+    }
+
+    // 每个线程都被调度过
+    let all_started = || snapshot().iter().all(|&count| count > 0);
+    assert!(wait_until(5000, all_started));
+    // 之后仍然轮流运行
+    let before = snapshot();
+    assert!(wait_until(5000, || {
+        snapshot()
+            .iter()
+            .zip(before.iter())
+            .all(|(now, then)| now > then)
+    }));
+
+    STOP.store(true, Ordering::SeqCst);
+    assert!(wait_until(5000, || STOPPED.load(Ordering::SeqCst) == WORKERS));
+}
+
+#[test_case]
+fn finished_threads_are_reaped() {
+    static DONE: AtomicBool = AtomicBool::new(false);
+
+    fn finish() {
+        DONE.store(true, Ordering::SeqCst);
+    }
+
+    // 等待之前测试中结束的线程被切换出去
+    assert!(wait_until(5000, || {
+        thread::reap();
+        thread::count() == 1
+    }));
+    let free = memory::FRAME_ALLOCATOR
+        .lock()
+        .as_ref()
+        .unwrap()
+        .free_frames();
+
+    thread::spawn(finish).unwrap();
+    assert_eq!(thread::count(), 2);
+    assert!(wait_until(5000, || DONE.load(Ordering::SeqCst)));
+    assert!(wait_until(5000, || {
+        thread::reap();
+        thread::count() == 1
+    }));
+    // 线程的栈已经释放
+    assert_eq!(
+        memory::FRAME_ALLOCATOR
+            .lock()
+            .as_ref()
+            .unwrap()
+            .free_frames(),
+        free
+    );
+}
+
+#[test_case]
+fn yield_now_runs_other_threads() {
+    static FLAG: AtomicBool = AtomicBool::new(false);
+
+    fn set_flag() {
+        FLAG.store(true, Ordering::SeqCst);
+    }
+
+    thread::spawn(set_flag).unwrap();
+    // 关闭中断, 只有主动让出cpu才能运行新线程
+    let ran = x86_64::instructions::interrupts::without_interrupts(|| {
+        for _ in 0..100 {
+            if FLAG.load(Ordering::SeqCst) {
+                return true;
+            }
+            thread::yield_now();
+        }
+        false
+    });
+    assert!(ran);
+}
@@ -0,0 +1,126 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(qxg_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use qxg_os::memory::user::{self, UserCopyError};
+use qxg_os::memory::{self, BitmapFrameAllocator};
+use x86_64::structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags};
+use x86_64::VirtAddr;
+
+// 测试用的用户页, 一页可写, 紧跟着一页只读
+const USER_PAGE: u64 = 0x10_0000_0000;
+const READ_ONLY_PAGE: u64 = USER_PAGE + 4096;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use qxg_os::allocator;
+
+    qxg_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    let user =
+        PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::NO_EXECUTE;
+    for (addr, flags) in [
+        (USER_PAGE, user | PageTableFlags::WRITABLE),
+        (READ_ONLY_PAGE, user),
+    ] {
+        let page = Page::containing_address(VirtAddr::new(addr));
+        let frame = frame_allocator.allocate_frame().unwrap();
+        unsafe {
+            mapper
+                .map_to(page, frame, flags, &mut frame_allocator)
+                .unwrap()
+                .flush()
+        };
+    }
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn enabled_protections_match_cpuid() {
+    assert_eq!(user::enabled_protections(), user::detect());
+}
+
+#[test_case]
+fn copy_round_trip() {
+    let addr = VirtAddr::new(USER_PAGE + 100);
+    user::copy_to_user(addr, b"hello user").unwrap();
+    let mut buf = [0u8; 10];
+    user::copy_from_user(&mut buf, addr).unwrap();
+    assert_eq!(&buf, b"hello user");
+}
+
+#[test_case]
+fn read_only_page_rejects_writes() {
+    let addr = VirtAddr::new(READ_ONLY_PAGE);
+    assert_eq!(
+        user::copy_to_user(addr, &[1, 2, 3]),
+        Err(UserCopyError::BadAddress(addr))
+    );
+    let mut buf = [0xffu8; 3];
+    user::copy_from_user(&mut buf, addr).unwrap();
+    assert_eq!(buf, [0, 0, 0]);
+}
+
+#[test_case]
+fn write_across_into_read_only_page_fails() {
+    let addr = VirtAddr::new(USER_PAGE + 4090);
+    assert_eq!(
+        user::copy_to_user(addr, &[0u8; 16]),
+        Err(UserCopyError::BadAddress(VirtAddr::new(READ_ONLY_PAGE)))
+    );
+}
+
+#[test_case]
+fn unmapped_address_is_rejected() {
+    let addr = VirtAddr::new(USER_PAGE + 2 * 4096 + 8);
+    let mut buf = [0u8; 4];
+    assert_eq!(
+        user::copy_from_user(&mut buf, addr),
+        Err(UserCopyError::BadAddress(addr))
+    );
+}
+
+#[test_case]
+fn kernel_address_is_rejected() {
+    use qxg_os::allocator::HEAP_START;
+
+    // 堆在低半部分, 但没有用户权限
+    let addr = VirtAddr::new(HEAP_START as u64);
+    let mut buf = [0u8; 4];
+    assert_eq!(
+        user::copy_from_user(&mut buf, addr),
+        Err(UserCopyError::BadAddress(addr))
+    );
+    assert_eq!(
+        user::copy_to_user(VirtAddr::new(0xffff_8000_0000_0000), &buf),
+        Err(UserCopyError::OutOfRange)
+    );
+}
+
+#[test_case]
+fn range_past_user_space_is_rejected() {
+    let addr = VirtAddr::new(user::USER_END - 2);
+    let mut buf = [0u8; 4];
+    assert_eq!(
+        user::copy_from_user(&mut buf, addr),
+        Err(UserCopyError::OutOfRange)
+    );
+}
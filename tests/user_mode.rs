@@ -0,0 +1,53 @@
+// 在用户态执行一小段代码, 它通过int3回到内核
+// int3的门特权级为3, 所以用户态可以执行; 断点异常在TSS中RSP0指向的内核栈上处理
+
+#![no_std]
+#![no_main]
+
+mod user_program;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use qxg_os::interrupts::exceptions::{self, FaultReport};
+use qxg_os::{exit_qemu, gdt, hlt_loop, serial_print, serial_println, QemuExitCode};
+use user_program::{CODE_ADDR, STACK_TOP};
+
+// 用户代码: int3; jmp $
+const USER_CODE: [u8; 3] = [0xcc, 0xeb, 0xfe];
+
+fn check_report(report: &FaultReport) -> bool {
+    if report.vector != 3 {
+        panic!("unexpected exception\n{}", report);
+    }
+    assert!(report.from_user());
+    assert_eq!(
+        report.stack_frame.code_segment,
+        u64::from(gdt::selectors().user_code_selector.0)
+    );
+    // int3是陷阱, 返回地址是下一条指令
+    assert_eq!(
+        report.stack_frame.instruction_pointer.as_u64(),
+        CODE_ADDR + 1
+    );
+    assert_eq!(report.stack_frame.stack_pointer.as_u64(), STACK_TOP);
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    hlt_loop();
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("user_mode::int3_from_user_mode...\t");
+
+    user_program::init(boot_info);
+    let space = user_program::load(&USER_CODE);
+    user_program::set_kernel_stack();
+    exceptions::set_fault_hook(Some(check_report));
+    unsafe { user_program::run(space) }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
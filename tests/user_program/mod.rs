@@ -0,0 +1,79 @@
+// 在用户态运行测试代码的公共部分: 初始化内存, 把一段代码复制到用户页, 映射用户栈及设置内核栈
+// 用户程序全部检查通过时exit(0), 否则以出错的检查点编号exit
+// 每个测试程序只用到其中的一部分
+#![allow(dead_code)]
+
+use bootloader::BootInfo;
+use qxg_os::memory::address_space::{AddressSpace, USER_START};
+use qxg_os::memory::{self, phys_to_virt, stack, BitmapFrameAllocator};
+use qxg_os::{allocator, exit_qemu, gdt, hlt_loop, serial_println, QemuExitCode};
+use x86_64::structures::paging::{Page, PageTableFlags};
+use x86_64::VirtAddr;
+
+/// 用户代码的地址, 也是入口地址
+pub const CODE_ADDR: u64 = USER_START;
+/// 用户栈的栈顶
+pub const STACK_TOP: u64 = USER_START + 0x10_0000;
+
+/// 初始化内核, 堆及全局的页表和帧分配器
+pub fn init(boot_info: &'static BootInfo) {
+    qxg_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+}
+
+/// global_asm中start与end两个标号之间的代码
+///
+/// 代码需要只使用相对rip的寻址, 这样复制到用户页中也能执行
+pub unsafe fn code_between(start: *const u8, end: *const u8) -> &'static [u8] {
+    core::slice::from_raw_parts(start, end as usize - start as usize)
+}
+
+/// 创建地址空间, 把code复制到CODE_ADDR(只读可执行), 并映射STACK_TOP下面的一页作为用户栈
+pub fn load(code: &[u8]) -> AddressSpace {
+    assert!(code.len() <= 4096);
+    let mut space = AddressSpace::new().expect("failed to create address space");
+    let code_page = Page::containing_address(VirtAddr::new(CODE_ADDR));
+    let frame = space
+        .map_user(code_page, PageTableFlags::empty())
+        .expect("failed to map user code");
+    unsafe {
+        let dst = phys_to_virt(frame.start_address()).as_mut_ptr::<u8>();
+        dst.copy_from_nonoverlapping(code.as_ptr(), code.len());
+    }
+    let stack_page = Page::containing_address(VirtAddr::new(STACK_TOP - 1));
+    space
+        .map_user(
+            stack_page,
+            PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+        )
+        .expect("failed to map user stack");
+    space
+}
+
+/// 分配内核栈并设置为从用户态进入内核时使用的栈, 返回栈顶
+pub fn set_kernel_stack() -> VirtAddr {
+    let kernel_stack = stack::alloc_stack("user test", 4).expect("failed to allocate stack");
+    gdt::set_kernel_stack(kernel_stack.top);
+    kernel_stack.top
+}
+
+/// 切换到space并从CODE_ADDR开始在用户态执行, 需要先设置内核栈
+pub unsafe fn run(space: AddressSpace) -> ! {
+    space.activate();
+    qxg_os::usermode::enter_user_mode(VirtAddr::new(CODE_ADDR), VirtAddr::new(STACK_TOP));
+}
+
+/// 用作syscall::set_exit_handler: exit(0)时测试通过, 否则报告出错的检查点
+pub fn exit_handler(code: u64) -> ! {
+    if code != 0 {
+        panic!("user program failed at check {}", code);
+    }
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    hlt_loop();
+}
@@ -0,0 +1,92 @@
+// 在线程中进入用户态, 用户程序忙等到被抢占几次之后再syscall
+// 线程被换下去再换回来时, TSS中的RSP0及syscall使用的内核栈要恢复成这个线程用set_kernel_stack设置的栈
+
+#![no_std]
+#![no_main]
+
+mod user_program;
+
+use bootloader::{entry_point, BootInfo};
+use core::arch::global_asm;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use qxg_os::{gdt, serial_print, syscall, thread};
+
+// 用户程序: 用time忙等至少100毫秒, 期间0号线程会抢占它, 然后exit(0)
+global_asm!(
+    ".global user_thread_start",
+    ".global user_thread_end",
+    "user_thread_start:",
+    "mov eax, 3",
+    "syscall",
+    "mov r12, rax",
+    "2:",
+    "pause",
+    "mov eax, 3",
+    "syscall",
+    "sub rax, r12",
+    "cmp rax, 100",
+    "jb 2b",
+    "xor edi, edi",
+    "mov eax, 1",
+    "syscall",
+    "ud2",
+    "user_thread_end:",
+);
+
+extern "C" {
+    static user_thread_start: u8;
+    static user_thread_end: u8;
+}
+
+// 用户线程设置的内核栈的栈顶
+static KERNEL_STACK: AtomicU64 = AtomicU64::new(0);
+// 0号线程运行的次数, 只有用户线程被抢占时才会增加
+static MAIN_RUNS: AtomicU64 = AtomicU64::new(0);
+// 进入用户态之前MAIN_RUNS的值
+static RUNS_BEFORE: AtomicU64 = AtomicU64::new(0);
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("user_thread::kernel_stack_survives_preemption...\t");
+
+    user_program::init(boot_info);
+    thread::init().expect("failed to start threads");
+    syscall::set_exit_handler(Some(exit_handler));
+    thread::spawn(user_thread).expect("failed to spawn thread");
+    // 一直保持就绪, 每次时钟中断都会和用户线程轮流运行
+    loop {
+        MAIN_RUNS.fetch_add(1, Ordering::SeqCst);
+        x86_64::instructions::hlt();
+    }
+}
+
+fn user_thread() {
+    let code = unsafe {
+        user_program::code_between(
+            core::ptr::addr_of!(user_thread_start),
+            core::ptr::addr_of!(user_thread_end),
+        )
+    };
+    let space = user_program::load(code);
+    let top = user_program::set_kernel_stack();
+    KERNEL_STACK.store(top.as_u64(), Ordering::SeqCst);
+    RUNS_BEFORE.store(MAIN_RUNS.load(Ordering::SeqCst), Ordering::SeqCst);
+    unsafe { user_program::run(space) }
+}
+
+fn exit_handler(code: u64) -> ! {
+    let runs = MAIN_RUNS.load(Ordering::SeqCst) - RUNS_BEFORE.load(Ordering::SeqCst);
+    assert!(runs >= 2, "user thread was preempted only {} times", runs);
+    assert_eq!(
+        gdt::kernel_stack().as_u64(),
+        KERNEL_STACK.load(Ordering::SeqCst)
+    );
+    user_program::exit_handler(code)
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
@@ -0,0 +1,158 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(qxg_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use qxg_os::memory::vma::{self, Backing, VmError};
+use qxg_os::memory::{self, BitmapFrameAllocator};
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use qxg_os::allocator;
+
+    qxg_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
+
+fn used_frames() -> usize {
+    memory::FRAME_ALLOCATOR
+        .lock()
+        .as_ref()
+        .unwrap()
+        .used_frames()
+}
+
+#[test_case]
+fn demand_paged_area() {
+    // 1MiB的区域, 分配时不占用任何帧
+    let start = vma::vmalloc(1024 * 1024).expect("vmalloc failed");
+    let area = vma::find_area(start).unwrap();
+    assert_eq!(area.pages, 256);
+    assert_eq!(vma::resident_pages(&area), 0);
+
+    let ptr = start.as_mut_ptr::<u8>();
+    unsafe {
+        ptr.write_volatile(1);
+        ptr.add(100 * 4096).write_volatile(2);
+        assert_eq!(ptr.add(100 * 4096 + 8).read_volatile(), 0);
+    }
+    assert_eq!(vma::resident_pages(&area), 2);
+
+    vma::vfree(start).unwrap();
+    assert!(vma::find_area(start).is_none());
+}
+
+#[test_case]
+fn eager_area() {
+    let flags = PageTableFlags::WRITABLE;
+    let area = vma::vmap("eager", 3 * 4096 + 1, flags, Backing::Eager).unwrap();
+    assert_eq!(area.pages, 4);
+    assert_eq!(vma::resident_pages(&area), 4);
+    unsafe {
+        let ptr = area.start.as_mut_ptr::<u64>();
+        assert_eq!(ptr.read_volatile(), 0);
+        ptr.write_volatile(3);
+    }
+    vma::vfree(area.start).unwrap();
+}
+
+#[test_case]
+fn vfree_returns_frames() {
+    // 先分配再释放一次, 让中间级页表都已经存在, 之后帧的个数应该完全恢复
+    let warm = vma::vmalloc(4096).unwrap();
+    unsafe { warm.as_mut_ptr::<u8>().write_volatile(1) };
+    vma::vfree(warm).unwrap();
+
+    let used = used_frames();
+    let start = vma::vmalloc(8 * 4096).unwrap();
+    for i in 0..8 {
+        unsafe { (start + i * 4096u64).as_mut_ptr::<u8>().write_volatile(1) };
+    }
+    assert_eq!(used_frames(), used + 8);
+    vma::vfree(start).unwrap();
+    assert_eq!(used_frames(), used);
+}
+
+#[test_case]
+fn areas_do_not_overlap() {
+    let a = vma::vmalloc(4096).unwrap();
+    let b = vma::vmalloc(2 * 4096).unwrap();
+    let area_a = vma::find_area(a).unwrap();
+    let area_b = vma::find_area(b).unwrap();
+    // 中间至少隔了一个保护页
+    assert!(area_a.end() < area_b.start || area_b.end() < area_a.start);
+
+    vma::vfree(a).unwrap();
+    vma::vfree(b).unwrap();
+    assert_eq!(vma::vfree(a), Err(VmError::NotFound));
+    assert_eq!(vma::vmalloc(0), Err(VmError::Invalid));
+}
+
+#[test_case]
+fn oversized_area_is_invalid() {
+    // 向上取整到页时会溢出
+    assert_eq!(vma::vmalloc(u64::MAX), Err(VmError::Invalid));
+    // 页数加上保护页之后的字节数会溢出
+    assert_eq!(
+        vma::vmalloc(u64::MAX - 4095),
+        Err(VmError::OutOfAddressSpace)
+    );
+    let flags = PageTableFlags::WRITABLE;
+    assert_eq!(
+        vma::vmap("huge", u64::MAX - 1, flags, Backing::Huge).unwrap_err(),
+        VmError::Invalid
+    );
+}
+
+#[test_case]
+fn kernel_stack_has_guard_page() {
+    use qxg_os::memory::fault::{self, FaultError, PageFault};
+    use x86_64::structures::idt::PageFaultErrorCode;
+
+    let stack = memory::stack::alloc_stack("test", 4).unwrap();
+    assert_eq!(stack.top - stack.bottom, 4 * 4096);
+    unsafe {
+        let ptr = (stack.top - 8u64).as_mut_ptr::<u64>();
+        ptr.write_volatile(1);
+        stack.bottom.as_mut_ptr::<u64>().write_volatile(2);
+    }
+
+    // 保护页没有映射, 访问时报告溢出的栈
+    let area = vma::find_area(stack.guard).unwrap();
+    assert_eq!(vma::resident_pages(&area), 4);
+    let page_fault = PageFault {
+        addr: stack.bottom - 8u64,
+        error_code: PageFaultErrorCode::CAUSED_BY_WRITE,
+    };
+    assert_eq!(
+        fault::resolve(&page_fault),
+        Err(FaultError::StackOverflow("test"))
+    );
+    assert_eq!(
+        memory::stack::overflowed_stack(stack.bottom - 8u64),
+        Some("test")
+    );
+
+    unsafe { memory::stack::free_stack(stack).unwrap() };
+    assert!(vma::find_area(stack.guard).is_none());
+    assert_eq!(memory::stack::overflowed_stack(stack.bottom - 8u64), None);
+}
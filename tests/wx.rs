@@ -0,0 +1,142 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(qxg_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+// 只用到其中构造镜像的部分
+#[allow(dead_code)]
+mod elf_image;
+
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use qxg_os::memory::protect::{self, page_flags};
+use qxg_os::memory::{self, BitmapFrameAllocator};
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+// 只读数据, 位于.rodata
+static RODATA: [u8; 8] = *b"readonly";
+// 有内部可变性的数据, 位于.data
+static DATA: AtomicU64 = AtomicU64::new(1);
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use qxg_os::allocator;
+
+    qxg_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::install(mapper, frame_allocator);
+    protect::protect_kernel().expect("failed to protect kernel sections");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    qxg_os::test_panic_handler(info)
+}
+
+// 位于.text的函数
+#[inline(never)]
+fn in_text() {}
+
+#[test_case]
+fn nxe_enabled() {
+    assert!(Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE));
+}
+
+#[test_case]
+fn text_is_read_only_and_executable() {
+    let flags = page_flags(VirtAddr::from_ptr(in_text as *const ())).unwrap();
+    assert!(!flags.contains(PageTableFlags::WRITABLE));
+    assert!(!flags.contains(PageTableFlags::NO_EXECUTE));
+}
+
+#[test_case]
+fn rodata_is_read_only_and_not_executable() {
+    let flags = page_flags(VirtAddr::from_ptr(&RODATA)).unwrap();
+    assert!(!flags.contains(PageTableFlags::WRITABLE));
+    assert!(flags.contains(PageTableFlags::NO_EXECUTE));
+}
+
+#[test_case]
+fn data_is_writable_and_not_executable() {
+    DATA.fetch_add(1, Ordering::SeqCst);
+    let flags = page_flags(VirtAddr::from_ptr(&DATA)).unwrap();
+    assert!(flags.contains(PageTableFlags::WRITABLE));
+    assert!(flags.contains(PageTableFlags::NO_EXECUTE));
+}
+
+#[test_case]
+fn heap_is_not_executable() {
+    let value = Box::new(41);
+    let flags = page_flags(VirtAddr::from_ptr(&*value)).unwrap();
+    assert!(flags.contains(PageTableFlags::WRITABLE));
+    assert!(flags.contains(PageTableFlags::NO_EXECUTE));
+}
+
+#[test_case]
+fn vmalloc_is_not_executable() {
+    let addr = memory::vma::vmalloc(4096).unwrap();
+    unsafe { addr.as_mut_ptr::<u8>().write_volatile(1) };
+    let flags = page_flags(addr).unwrap();
+    assert!(flags.contains(PageTableFlags::NO_EXECUTE));
+    memory::vma::vfree(addr).unwrap();
+}
+
+#[test_case]
+fn kernel_image_has_no_writable_executable_pages() {
+    let (start, end) = protect::kernel_range().unwrap();
+    assert!(start < end);
+    assert_eq!(protect::wx_violations(start, end), 0);
+}
+
+#[test_case]
+fn shared_writable_and_executable_page_is_rejected() {
+    use elf_image::{Segment, TEXT_ADDR};
+    use qxg_os::elf::{Elf, PF_R, PF_W, PF_X};
+    use qxg_os::memory::protect::ProtectError;
+
+    const CODE: [u8; 4] = [0x90, 0x90, 0xeb, 0xfe];
+    let segment = |vaddr, flags| Segment {
+        vaddr,
+        flags,
+        data: &CODE,
+        memsz: CODE.len() as u64,
+    };
+    // 代码与数据在不同的页
+    let image = elf_image::build_segments(
+        TEXT_ADDR,
+        &[
+            segment(TEXT_ADDR, PF_R | PF_X),
+            segment(TEXT_ADDR + 0x1000, PF_R | PF_W),
+        ],
+    );
+    assert_eq!(protect::check_wx(&Elf::parse(&image).unwrap()), Ok(()));
+    // 数据段紧跟在代码段之后, 在同一页
+    let image = elf_image::build_segments(
+        TEXT_ADDR,
+        &[
+            segment(TEXT_ADDR, PF_R | PF_X),
+            segment(TEXT_ADDR + 0x800, PF_R | PF_W),
+        ],
+    );
+    assert_eq!(
+        protect::check_wx(&Elf::parse(&image).unwrap()),
+        Err(ProtectError::WritableAndExecutable(VirtAddr::new(
+            TEXT_ADDR
+        )))
+    );
+}